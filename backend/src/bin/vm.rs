@@ -0,0 +1,338 @@
+use clap::{Args, Parser, Subcommand};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use virtual_machine::core::assembler::{Assembler, FileResolver};
+use virtual_machine::core::bytecode;
+use virtual_machine::core::debugger::Debugger;
+use virtual_machine::core::disassembler::Disassembler;
+use virtual_machine::core::error::AssemblerError;
+use virtual_machine::core::instruction::Instruction;
+use virtual_machine::core::optimizer;
+use virtual_machine::core::repl::Repl;
+use virtual_machine::core::state::{ArithmeticMode, DataItem, DebugOptions};
+use virtual_machine::core::verifier;
+use virtual_machine::core::vm::VM;
+
+/// The program raised a `VMError`
+const EXIT_RUNTIME_ERROR: u8 = 1;
+/// The program could not be read, assembled, decoded or verified
+const EXIT_INVALID_PROGRAM: u8 = 3;
+/// `--max-steps` ran out before the program halted
+const EXIT_STEP_LIMIT: u8 = 4;
+
+/// Run, assemble, disassemble and check programs for the virtual machine
+#[derive(Parser)]
+#[command(name = "vm", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Execute a program to completion, printing its output on stdout
+    Run(RunArgs),
+    /// Assemble a program into a bytecode file
+    Asm {
+        file: PathBuf,
+        /// Where to write the bytecode; defaults to the input with a `.svmb` extension
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Optimize the program before encoding it
+        #[arg(long)]
+        optimize: bool,
+    },
+    /// Print a program as assembly source
+    Disasm {
+        file: PathBuf,
+        /// Annotate each line with its instruction address
+        #[arg(long)]
+        addresses: bool,
+    },
+    /// Assemble and verify a program without running it
+    Check {
+        file: PathBuf,
+    },
+    /// Assemble and run lines interactively against one long-lived VM
+    Repl,
+    /// Debug a program with breakpoints and single-stepping
+    Debug {
+        file: PathBuf,
+        /// How integer overflow behaves: checked, wrapping or saturating
+        #[arg(long, default_value = "checked", value_parser = parse_arithmetic_mode)]
+        arithmetic_mode: ArithmeticMode,
+    },
+}
+
+#[derive(Args)]
+struct RunArgs {
+    /// Assembly source or bytecode file
+    file: PathBuf,
+    /// Give up after executing this many instructions
+    #[arg(long)]
+    max_steps: Option<u64>,
+    /// How integer overflow behaves: checked, wrapping or saturating
+    #[arg(long, default_value = "checked", value_parser = parse_arithmetic_mode)]
+    arithmetic_mode: ArithmeticMode,
+    /// Optimize the program before running it
+    #[arg(long)]
+    optimize: bool,
+    /// Trace every instruction and its address on stderr
+    #[arg(long)]
+    trace: bool,
+    /// Include the stack in each trace line
+    #[arg(long)]
+    trace_stack: bool,
+    /// Include global variables in each trace line
+    #[arg(long)]
+    trace_memory: bool,
+}
+
+/// A verified program ready to run
+struct Program {
+    instructions: Vec<Instruction>,
+    data: Vec<DataItem>,
+    /// Label addresses from the assembler; empty for bytecode
+    labels: HashMap<String, usize>,
+}
+
+fn parse_arithmetic_mode(mode: &str) -> Result<ArithmeticMode, String> {
+    match mode {
+        "checked" => Ok(ArithmeticMode::Checked),
+        "wrapping" => Ok(ArithmeticMode::Wrapping),
+        "saturating" => Ok(ArithmeticMode::Saturating),
+        _ => Err("expected checked, wrapping or saturating".to_string()),
+    }
+}
+
+fn print_diagnostic(path: &Path, diagnostic: &AssemblerError) {
+    match diagnostic.file {
+        // Included files are named relative to the main file's directory
+        Some(_) => eprintln!("{}", diagnostic),
+        None => eprintln!("{}:{}", path.display(), diagnostic),
+    }
+    if let Some(help) = &diagnostic.help {
+        eprintln!("    help: {}", help);
+    }
+}
+
+/// Read bytecode or assembly source, assemble it if needed and verify it.
+/// Problems are reported on stderr.
+fn load(path: &Path) -> Result<Program, ExitCode> {
+    let invalid = ExitCode::from(EXIT_INVALID_PROGRAM);
+    let bytes = std::fs::read(path).map_err(|e| {
+        eprintln!("{}: {}", path.display(), e);
+        invalid
+    })?;
+
+    let program = if bytes.starts_with(bytecode::MAGIC) {
+        let instructions = bytecode::decode(&bytes).map_err(|e| {
+            eprintln!("{}: {}", path.display(), e);
+            invalid
+        })?;
+        Program { instructions, data: Vec::new(), labels: HashMap::new() }
+    } else {
+        let source = String::from_utf8(bytes).map_err(|_| {
+            eprintln!("{}: neither bytecode nor UTF-8 assembly source", path.display());
+            invalid
+        })?;
+        let root = path.parent().unwrap_or(Path::new("."));
+        let mut assembler = Assembler::new().with_resolver(FileResolver::new(root));
+        let result = assembler.assemble(&source);
+        for diagnostic in assembler.diagnostics() {
+            print_diagnostic(path, diagnostic);
+        }
+        let instructions = result.map_err(|_| invalid)?;
+        Program { instructions, data: assembler.data().to_vec(), labels: assembler.labels().clone() }
+    };
+
+    let report = verifier::verify(&program.instructions);
+    if !report.is_ok() {
+        for error in &report.errors {
+            eprintln!("{}: {}", path.display(), error);
+        }
+        return Err(invalid);
+    }
+    Ok(program)
+}
+
+/// Optimize a verified program and verify the result, which is what will run
+fn optimize_verified(path: &Path, instructions: &[Instruction]) -> Result<Vec<Instruction>, ExitCode> {
+    let optimized = optimizer::optimize(instructions).0;
+    let report = verifier::verify(&optimized);
+    if !report.is_ok() {
+        for error in &report.errors {
+            eprintln!("{}: optimized program: {}", path.display(), error);
+        }
+        return Err(ExitCode::from(EXIT_INVALID_PROGRAM));
+    }
+    Ok(optimized)
+}
+
+fn run(args: RunArgs) -> Result<(), ExitCode> {
+    let program = load(&args.file)?;
+    let instructions = if args.optimize {
+        optimize_verified(&args.file, &program.instructions)?
+    } else {
+        program.instructions
+    };
+
+    let mut vm = VM::new(instructions);
+    vm.load_data(&program.data);
+    vm.set_arithmetic_mode(args.arithmetic_mode);
+    vm.set_debug_options(DebugOptions {
+        show_stack: args.trace_stack,
+        show_pc: args.trace,
+        show_memory: args.trace_memory,
+        show_instructions: args.trace,
+    });
+
+    let mut stdout = std::io::stdout().lock();
+    let mut steps = 0;
+    loop {
+        if args.max_steps.is_some_and(|limit| steps >= limit) {
+            let _ = stdout.flush();
+            eprintln!("error: step limit of {} reached at instruction {}", steps, vm.get_state().program_counter);
+            return Err(ExitCode::from(EXIT_STEP_LIMIT));
+        }
+        let result = vm.step();
+        steps += 1;
+        for output in vm.take_output() {
+            let _ = write!(stdout, "{}", output);
+        }
+        match result {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => {
+                let _ = stdout.flush();
+                eprintln!("error: {} (at instruction {})", e, vm.get_state().program_counter);
+                return Err(ExitCode::from(EXIT_RUNTIME_ERROR));
+            }
+        }
+    }
+    let _ = stdout.flush();
+    Ok(())
+}
+
+fn assemble(file: &Path, output: Option<PathBuf>, optimize: bool) -> Result<(), ExitCode> {
+    let program = load(file)?;
+    if !program.data.is_empty() {
+        eprintln!("{}: bytecode cannot hold .data items", file.display());
+        return Err(ExitCode::from(EXIT_INVALID_PROGRAM));
+    }
+    let instructions = if optimize {
+        optimize_verified(file, &program.instructions)?
+    } else {
+        program.instructions
+    };
+
+    let output = output.unwrap_or_else(|| file.with_extension("svmb"));
+    bytecode::save(&output, &instructions).map_err(|e| {
+        eprintln!("{}: {}", output.display(), e);
+        ExitCode::from(EXIT_INVALID_PROGRAM)
+    })
+}
+
+fn disassemble(file: &Path, addresses: bool) -> Result<(), ExitCode> {
+    let program = load(file)?;
+    if !program.data.is_empty() {
+        eprintln!("{}: disassembly cannot hold .data items", file.display());
+        return Err(ExitCode::from(EXIT_INVALID_PROGRAM));
+    }
+    let disassembler = if addresses { Disassembler::with_addresses() } else { Disassembler::new() };
+    let source = disassembler.disassemble(&program.instructions).map_err(|e| {
+        eprintln!("{}: {}", file.display(), e);
+        ExitCode::from(EXIT_INVALID_PROGRAM)
+    })?;
+    print!("{}", source);
+    Ok(())
+}
+
+fn check(file: &Path) -> Result<(), ExitCode> {
+    let program = load(file)?;
+    println!("{}: ok, {} instruction(s)", file.display(), program.instructions.len());
+    Ok(())
+}
+
+/// A line-oriented session driven from stdin
+trait Console {
+    fn prompt(&self) -> &'static str;
+    fn handle(&mut self, line: &str) -> String;
+    fn is_done(&self) -> bool;
+}
+
+impl Console for Repl {
+    fn prompt(&self) -> &'static str {
+        Repl::prompt(self)
+    }
+
+    fn handle(&mut self, line: &str) -> String {
+        self.eval(line)
+    }
+
+    fn is_done(&self) -> bool {
+        self.is_finished()
+    }
+}
+
+impl Console for Debugger {
+    fn prompt(&self) -> &'static str {
+        "(vmdb) "
+    }
+
+    fn handle(&mut self, line: &str) -> String {
+        self.execute(line)
+    }
+
+    fn is_done(&self) -> bool {
+        self.is_finished()
+    }
+}
+
+/// Prompt for lines until end of input or the console is done, printing each
+/// non-empty reply
+fn interact(console: &mut impl Console) -> Result<(), ExitCode> {
+    let mut line = String::new();
+    while !console.is_done() {
+        print!("{}", console.prompt());
+        let _ = std::io::stdout().flush();
+        line.clear();
+        match std::io::stdin().read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("{}", e);
+                return Err(ExitCode::from(EXIT_RUNTIME_ERROR));
+            }
+        }
+        let reply = console.handle(line.trim_end_matches(['\n', '\r']));
+        if !reply.is_empty() {
+            println!("{}", reply);
+        }
+    }
+    Ok(())
+}
+
+fn debug(file: &Path, arithmetic_mode: ArithmeticMode) -> Result<(), ExitCode> {
+    let program = load(file)?;
+    let mut debugger = Debugger::new(program.instructions, program.data, program.labels);
+    debugger.set_arithmetic_mode(arithmetic_mode);
+    interact(&mut debugger)
+}
+
+fn main() -> ExitCode {
+    let result = match Cli::parse().command {
+        Command::Run(args) => run(args),
+        Command::Asm { file, output, optimize } => assemble(&file, output, optimize),
+        Command::Disasm { file, addresses } => disassemble(&file, addresses),
+        Command::Check { file } => check(&file),
+        Command::Repl => interact(&mut Repl::new()),
+        Command::Debug { file, arithmetic_mode } => debug(&file, arithmetic_mode),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(code) => code,
+    }
+}
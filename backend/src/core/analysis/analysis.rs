@@ -0,0 +1,236 @@
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use crate::core::cfg::successor_addresses;
+use crate::core::error::StackWarning;
+use crate::core::instruction::Instruction;
+use crate::core::state::Function;
+
+/// Stack behaviour of a function as seen by its callers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct FunctionSummary {
+    pub params: usize,
+    /// Values the body pops from below its entry, i.e. from the caller's stack
+    pub borrowed: usize,
+    /// Depth on return relative to entry, `None` if the function never returns
+    pub returns: Option<isize>,
+    /// Deepest the stack gets inside the body, relative to entry, or `None`
+    /// if a loop in it can grow the stack without bound
+    pub max_depth: Option<usize>,
+}
+
+impl FunctionSummary {
+    /// Values a `CALL` pops and pushes, or `None` if the call never returns
+    pub fn call_effect(&self) -> Option<(usize, usize)> {
+        let returns = self.returns?;
+        Some((self.params + self.borrowed, (self.borrowed as isize + returns).max(0) as usize))
+    }
+}
+
+/// Result of `analyze_stack`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StackAnalysis {
+    /// Depth before each instruction, `None` where unreachable. Inside function
+    /// bodies the depth is relative to the function's entry and may be negative.
+    pub depths: Vec<Option<isize>>,
+    /// Deepest the stack gets on any path from the entry point, including
+    /// calls, or `None` if a loop can grow the stack without bound
+    pub max_depth: Option<usize>,
+    pub warnings: Vec<StackWarning>,
+    pub functions: HashMap<String, FunctionSummary>,
+}
+
+/// Compute the stack depth at every instruction across all control-flow paths.
+///
+/// The top level is walked from address 0 with an empty stack, and every
+/// function body from its `BEGIN` with a depth of zero. `CALL` uses the
+/// callee's summary; summaries are refined over a few rounds so recursive
+/// functions are approximated by a bounded number of levels.
+pub fn analyze_stack(instructions: &[Instruction]) -> StackAnalysis {
+    let functions = Function::scan(instructions);
+    let mut summaries = HashMap::new();
+    let mut bodies = HashMap::new();
+
+    for _ in 0..=functions.len() {
+        let walker = Walker { instructions, functions: &functions, summaries: &summaries };
+        bodies = functions.iter()
+            .map(|(name, function)| (name.clone(), walker.walk(function.address + 1, true)))
+            .collect::<HashMap<_, _>>();
+        let next: HashMap<String, FunctionSummary> = bodies.iter()
+            .map(|(name, body)| (name.clone(), body.summary(functions[name].param_count)))
+            .collect();
+        if next == summaries {
+            break;
+        }
+        summaries = next;
+    }
+
+    let walker = Walker { instructions, functions: &functions, summaries: &summaries };
+    let main = walker.walk(0, false);
+    let max_depth = main.bounded_max_depth();
+
+    let mut depths = main.depths;
+    let mut warnings = main.warnings;
+    for body in bodies.into_values() {
+        for (address, depth) in body.depths.into_iter().enumerate() {
+            if depth.is_some() {
+                depths[address] = depth;
+            }
+        }
+        warnings.extend(body.warnings);
+    }
+    warnings.sort_by_key(StackWarning::address);
+
+    StackAnalysis {
+        depths,
+        max_depth,
+        warnings,
+        functions: summaries,
+    }
+}
+
+struct Walker<'a> {
+    instructions: &'a [Instruction],
+    functions: &'a HashMap<String, Function>,
+    summaries: &'a HashMap<String, FunctionSummary>,
+}
+
+/// Depths found by walking from one entry point
+struct Region {
+    depths: Vec<Option<isize>>,
+    max_depth: isize,
+    /// Some loop comes back to its header with more on the stack than before
+    unbounded: bool,
+    borrowed: usize,
+    returns: Option<isize>,
+    warnings: Vec<StackWarning>,
+}
+
+impl Region {
+    fn bounded_max_depth(&self) -> Option<usize> {
+        (!self.unbounded).then_some(self.max_depth.max(0) as usize)
+    }
+
+    fn summary(&self, params: usize) -> FunctionSummary {
+        FunctionSummary {
+            params,
+            borrowed: self.borrowed,
+            returns: self.returns,
+            max_depth: self.bounded_max_depth(),
+        }
+    }
+}
+
+impl Walker<'_> {
+    /// Visit everything reachable from `entry`. Underflows are reported at the
+    /// top level; inside a function they borrow from the caller's stack instead.
+    fn walk(&self, entry: usize, in_function: bool) -> Region {
+        let len = self.instructions.len();
+        let mut region = Region {
+            depths: vec![None; len],
+            max_depth: 0,
+            unbounded: false,
+            borrowed: 0,
+            returns: None,
+            warnings: Vec::new(),
+        };
+        let mut mismatched = HashSet::new();
+        let mut worklist = Vec::new();
+        if entry < len {
+            region.depths[entry] = Some(0);
+            worklist.push(entry);
+        }
+
+        while let Some(address) = worklist.pop() {
+            let depth = region.depths[address].expect("queued instructions have a depth");
+            let instruction = &self.instructions[address];
+
+            // Paths through a call that never returns, or to an unknown
+            // function, end here; the verifier reports the latter.
+            let (pops, pushes, peak) = match instruction {
+                Instruction::Call(name) => {
+                    let Some(summary) = self.summaries.get(name) else { continue };
+                    // Checked first, since such a callee may never return
+                    if summary.max_depth.is_none() {
+                        region.unbounded = true;
+                    }
+                    let Some((pops, pushes)) = summary.call_effect() else { continue };
+                    let callee_depth = summary.max_depth.unwrap_or(0) as isize;
+                    (pops, pushes, depth - summary.params as isize + callee_depth)
+                }
+                _ => match instruction.stack_effect() {
+                    Some((pops, pushes)) => (pops, pushes, depth),
+                    None => continue,
+                },
+            };
+
+            if depth < pops as isize {
+                if in_function {
+                    region.borrowed = region.borrowed.max((pops as isize - depth) as usize);
+                } else {
+                    region.warnings.push(StackWarning::Underflow { address, depth: depth as usize, needed: pops });
+                    continue;
+                }
+            }
+            let after = depth - pops as isize + pushes as isize;
+            region.max_depth = region.max_depth.max(peak).max(after);
+
+            if in_function && matches!(instruction, Instruction::Return | Instruction::EndFunction) {
+                match region.returns {
+                    None => region.returns = Some(after),
+                    Some(expected) if expected != after && mismatched.insert(address) => {
+                        region.warnings.push(StackWarning::DepthMismatch { address, expected, found: after });
+                    }
+                    Some(_) => {}
+                }
+            }
+
+            for successor in self.successors(address) {
+                match region.depths[successor] {
+                    None => {
+                        region.depths[successor] = Some(after);
+                        worklist.push(successor);
+                    }
+                    Some(expected) if expected != after => {
+                        // Coming back around a loop with more on the stack
+                        // means every further trip adds more
+                        if after > expected && self.reaches(successor, address) {
+                            region.unbounded = true;
+                        }
+                        if mismatched.insert(successor) {
+                            region.warnings.push(StackWarning::DepthMismatch { address: successor, expected, found: after });
+                        }
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+
+        region
+    }
+
+    /// Where control can go after `address`. Falling off the end stops the
+    /// program; jumps outside it are the verifier's concern.
+    fn successors(&self, address: usize) -> Vec<usize> {
+        successor_addresses(&self.instructions[address], address, self.functions)
+            .into_iter()
+            .filter(|&s| s < self.instructions.len())
+            .collect()
+    }
+
+    /// Whether some path leads from `from` to `to`
+    fn reaches(&self, from: usize, to: usize) -> bool {
+        let mut seen = HashSet::from([from]);
+        let mut stack = vec![from];
+        while let Some(address) = stack.pop() {
+            if address == to {
+                return true;
+            }
+            for successor in self.successors(address) {
+                if seen.insert(successor) {
+                    stack.push(successor);
+                }
+            }
+        }
+        false
+    }
+}
@@ -0,0 +1,3 @@
+#[allow(clippy::module_inception)]
+pub mod analysis;
+pub use analysis::*;
\ No newline at end of file
@@ -1,14 +1,22 @@
 use nom::{
     IResult,
     branch::alt,
-    bytes::complete::{tag, take_while1},
-    character::complete::{alpha1, alphanumeric1, char, digit1, multispace0},
-    combinator::{map, map_res, opt, recognize},
-    multi::many0,
-    sequence::{delimited, pair, terminated}
+    bytes::complete::{tag, take_while, take_while1, take_while_m_n},
+    character::complete::{alpha1, alphanumeric1, char, digit1, multispace0, one_of, satisfy},
+    combinator::{map, map_res, not, opt, recognize},
+    multi::{many0, separated_list1},
+    error::ErrorKind,
+    sequence::{delimited, pair, preceded, terminated, tuple}
 };
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
-use crate::core::instruction::Instruction;
+use crate::core::instruction::{Instruction, Opcode, Operand, OperandKind, OPCODES};
+use crate::core::analysis::analyze_stack;
+use crate::core::error::{AssemblerError, AssemblerErrors, Severity, StackWarning};
+use crate::core::heap::HeapValue;
+use crate::core::state::DataItem;
+use crate::core::value::Value;
+use super::{directive, Preprocessor, SourceLine, SourceResolver};
 
 /// Represents a token in the assembly language
 #[derive(Debug, PartialEq)]
@@ -17,8 +25,18 @@ pub enum Token {
     Instruction(String),
     Register(String),
     Number(i64),
+    Float(f64),
     String(String),
     Identifier(String),
+    /// Jump target relative to the instruction itself: `$`, `$+n` or `$-n`
+    Relative(i64),
+}
+
+/// Byte range of a token within its source line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
 }
 
 /// Represents a single line of assembly code
@@ -27,13 +45,67 @@ pub struct AsmLine {
     pub label: Option<String>,
     pub instruction: String,
     pub operands: Vec<Token>,
+    pub instruction_span: Span,
+    pub operand_spans: Vec<Span>,
+}
+
+/// A successfully parsed source line
+struct ParsedLine<'a> {
+    source: &'a SourceLine,
+    label: Option<String>,
+    /// `None` for a line holding only a label
+    parsed: Option<AsmLine>,
+}
+
+/// Where an operand appears, for resolving local, anonymous and relative labels
+#[derive(Debug, Clone, Copy, Default)]
+struct Site<'a> {
+    address: usize,
+    /// Innermost non-local label defined before the instruction
+    scope: Option<&'a str>,
+}
+
+/// Which kind of lines the source is currently declaring
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Text,
+    Data,
+}
+
+/// Problem with a line, before it is turned into an `AssemblerError`
+struct Issue {
+    span: Span,
+    severity: Severity,
+    message: String,
+    help: Option<String>,
+}
+
+impl Issue {
+    fn new(span: Span, message: impl Into<String>) -> Self {
+        Issue { span, severity: Severity::Error, message: message.into(), help: None }
+    }
+
+    fn warning(span: Span, message: impl Into<String>) -> Self {
+        Issue { severity: Severity::Warning, ..Issue::new(span, message) }
+    }
+
+    fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
 }
 
 /// Assembler for converting assembly code to VM instructions
 #[derive(Default, Debug)]
 pub struct Assembler {
     labels: HashMap<String, usize>,
+    /// Numeric labels such as `1:`, in definition order
+    anonymous: Vec<(String, usize)>,
+    constants: HashMap<String, Value>,
+    data: Vec<DataItem>,
     instructions: Vec<Instruction>,
+    diagnostics: Vec<AssemblerError>,
+    resolver: Option<Box<dyn SourceResolver>>,
 }
 
 impl Assembler {
@@ -42,224 +114,419 @@ impl Assembler {
         Self::default()
     }
 
-    /// Assemble the given source code into VM instructions
-    pub fn assemble(&mut self, source: &str) -> Result<Vec<Instruction>, String> {
+    /// Use `resolver` to find the files named by `.include`
+    pub fn with_resolver(mut self, resolver: impl SourceResolver + 'static) -> Self {
+        self.resolver = Some(Box::new(resolver));
+        self
+    }
+
+    /// Assemble the given source code into VM instructions.
+    /// Every line is checked, so a failure reports all errors at once.
+    pub fn assemble(&mut self, source: &str) -> Result<Vec<Instruction>, AssemblerErrors> {
         self.labels.clear();
+        self.anonymous.clear();
+        self.constants.clear();
+        self.data.clear();
         self.instructions.clear();
+        self.diagnostics.clear();
 
-        // First pass: collect labels and count actual instructions
-        let mut instr_count = 0;
-        for line_str in source.lines()
-            .filter(|l| !l.trim().is_empty() && !l.trim().starts_with("//"))
-        {
-            if let Ok((_, asm_line)) = parse_line(line_str.trim()) {
-                if let Some(label) = asm_line.label {
-                    self.labels.insert(label, instr_count);
-                }
-                // Only count if it's not just a label
-                if !asm_line.instruction.is_empty() {
-                    instr_count += 1;
+        let mut preprocessor = Preprocessor::new();
+        let expanded = preprocessor.process(source, self.resolver.as_deref());
+        self.diagnostics.extend_from_slice(preprocessor.diagnostics());
+        let lines = self.parse_source(&expanded);
+
+        // First pass: give each label the address of the next instruction and
+        // note the parent label each instruction's local labels belong to
+        let mut defined_on = HashMap::new();
+        let mut scope = None;
+        let mut scopes = Vec::new();
+        for line in &lines {
+            if let Some(label) = &line.label {
+                if let Err(issue) = self.define_label(label, scopes.len(), line.source, &mut scope, &mut defined_on) {
+                    self.report(line.source, issue);
                 }
             }
+            if line.parsed.is_some() {
+                scopes.push(scope.clone());
+            }
         }
+        let instruction_lines: Vec<(&SourceLine, &AsmLine)> = lines.iter()
+            .filter_map(|line| line.parsed.as_ref().map(|parsed| (line.source, parsed)))
+            .collect();
 
         // Second pass: generate instructions
-        for line_str in source.lines()
-            .filter(|l| !l.trim().is_empty() && !l.trim().starts_with("//"))
-        {
-            if let Ok((_, asm_line)) = parse_line(line_str.trim()) {
-                if !asm_line.instruction.is_empty() {
-                    self.process_instruction(asm_line)?;
-                }
+        for (address, ((source, parsed), scope)) in instruction_lines.iter().zip(&scopes).enumerate() {
+            let site = Site { address, scope: scope.as_deref() };
+            match self.process_instruction(parsed, site) {
+                Ok(instruction) => self.instructions.push(instruction),
+                Err(issue) => self.report(source, issue),
             }
         }
 
+        // Stack analysis needs the whole program, so it only runs on clean input
+        if self.diagnostics.is_empty() {
+            for warning in analyze_stack(&self.instructions).warnings {
+                let (source, parsed) = instruction_lines[warning.address()];
+                let help = match warning {
+                    StackWarning::Underflow { .. } => "push the missing values on every path that reaches this line",
+                    StackWarning::DepthMismatch { .. } => "every path into this line should leave the same number of values on the stack",
+                };
+                self.report(source, Issue::warning(parsed.instruction_span, warning.to_string()).with_help(help));
+            }
+        }
+
+        // Main source first, then each included file
+        self.diagnostics.sort_by(|a, b| (&a.file, a.line, a.column).cmp(&(&b.file, b.line, b.column)));
+        if self.diagnostics.iter().any(|d| d.severity == Severity::Error) {
+            return Err(AssemblerErrors(self.diagnostics.clone()));
+        }
         Ok(self.instructions.clone())
     }
 
-    fn process_instruction(&mut self, line: AsmLine) -> Result<(), String> {
-        match line.instruction.as_str() {
-            // Stack Operations
-            "PUSH" => {
-                if let Some(Token::Number(n)) = line.operands.get(0) {
-                    self.instructions.push(Instruction::Push(*n));
-                    Ok(())
-                } else {
-                    Err("PUSH requires a number operand".to_string())
-                }
-            }
-            "POP" => {
-                self.instructions.push(Instruction::Pop);
-                Ok(())
-            }
-            "DUP" => {
-                self.instructions.push(Instruction::Dup);
-                Ok(())
-            }
-            "SWAP" => {
-                self.instructions.push(Instruction::Swap);
-                Ok(())
-            }
+    /// Label addresses from the last call to `assemble`
+    pub fn labels(&self) -> &HashMap<String, usize> {
+        &self.labels
+    }
 
-            // Arithmetic Operations
-            "ADD" => {
-                self.instructions.push(Instruction::Add);
-                Ok(())
-            }
-            "SUB" => {
-                self.instructions.push(Instruction::Sub);
-                Ok(())
-            }
-            "MUL" => {
-                self.instructions.push(Instruction::Mul);
-                Ok(())
-            }
-            "DIV" => {
-                self.instructions.push(Instruction::Div);
-                Ok(())
+    /// `.const` values from the last call to `assemble`
+    pub fn constants(&self) -> &HashMap<String, Value> {
+        &self.constants
+    }
+
+    /// `.data` items from the last call to `assemble`, in declaration order.
+    /// Pass them to `VM::load_data` before running the program.
+    pub fn data(&self) -> &[DataItem] {
+        &self.data
+    }
+
+    /// Errors and warnings from the last call to `assemble`
+    pub fn diagnostics(&self) -> &[AssemblerError] {
+        &self.diagnostics
+    }
+
+    /// Parse every non-blank, non-comment line, reporting the ones that fail.
+    /// Directives and `.data` entries are handled here; only instructions are returned.
+    fn parse_source<'a>(&mut self, source: &'a [SourceLine]) -> Vec<ParsedLine<'a>> {
+        let mut lines = Vec::new();
+        let mut section = Section::Text;
+        let mut defined_on = HashMap::new();
+        for source_line in source {
+            let text = source_line.text.as_str();
+            if source_line.is_blank() {
+                continue;
             }
-            "LT" => {
-                self.instructions.push(Instruction::LessThan);
-                Ok(())
-            },
-            "LE" => {
-                self.instructions.push(Instruction::LessEqual);
-                Ok(())
-            },
-            "GT" => {
-                self.instructions.push(Instruction::GreaterThan);
-                Ok(())
-            },
-            "GE" => {
-                self.instructions.push(Instruction::GreaterEqual);
-                Ok(())
-            },
+            let start = text.len() - text.trim_start().len();
 
-            // Memory Operations
-            "LOAD" => {
-                if let Some(Token::Identifier(name)) = line.operands.get(0) {
-                    self.instructions.push(Instruction::Load(name.clone()));
-                    Ok(())
-                } else {
-                    Err("LOAD requires an identifier operand".to_string())
+            if let Some((name, start, _)) = directive(text) {
+                if let Err(issue) = self.directive(source_line, name, start, &mut section, &mut defined_on) {
+                    self.report(source_line, issue);
                 }
+                continue;
             }
-            "STORE" => {
-                if let Some(Token::Identifier(name)) = line.operands.get(0) {
-                    self.instructions.push(Instruction::Store(name.clone()));
-                    Ok(())
-                } else {
-                    Err("STORE requires an identifier operand".to_string())
+            if section == Section::Data {
+                if let Err(issue) = self.data_entry(source_line, &mut defined_on) {
+                    self.report(source_line, issue);
                 }
+                continue;
             }
 
-            // Array Operations
-            "NEWARRAY" => {
-                self.instructions.push(Instruction::NewArray);
-                Ok(())
-            }
-            "ARRAYGET" => {
-                self.instructions.push(Instruction::ArrayGet);
-                Ok(())
+            match parse_line(text) {
+                Ok((rest, parsed)) => {
+                    let trailing = rest.trim_start();
+                    if trailing.is_empty() || is_comment(trailing) {
+                        let label = parsed.label.clone();
+                        lines.push(ParsedLine { source: source_line, label, parsed: Some(parsed) });
+                    } else {
+                        let start = text.len() - trailing.len();
+                        let end = start + trailing.find(char::is_whitespace).unwrap_or(trailing.len());
+                        self.report(source_line, Issue::new(Span { start, end }, "Unexpected input")
+                            .with_help("operands are numbers, names, labels or quoted strings; comments start with `//` or `;`"));
+                    }
+                }
+                Err(_) if label_line(text).is_ok_and(|(rest, _)| rest.trim().is_empty() || is_comment(rest.trim_start())) => {
+                    let (_, label) = label_line(text).expect("checked above");
+                    lines.push(ParsedLine { source: source_line, label: Some(label), parsed: None });
+                }
+                Err(error) => {
+                    let end = start + text[start..].find(char::is_whitespace).unwrap_or(text.len() - start);
+                    let issue = lex_issue(text, &error).unwrap_or_else(|| {
+                        Issue::new(Span { start, end }, "Could not parse line")
+                            .with_help("expected `[label:] MNEMONIC [operands]`")
+                    });
+                    self.report(source_line, issue);
+                }
             }
-            "ARRAYSET" => {
-                self.instructions.push(Instruction::ArraySet);
-                Ok(())
+        }
+        lines
+    }
+
+    /// Handle `.const NAME value`, `.data` and `.text`
+    fn directive(&mut self, line: &SourceLine, name: &str, start: usize, section: &mut Section,
+                 defined_on: &mut HashMap<String, usize>) -> Result<(), Issue> {
+        let text = line.text.as_str();
+        let name_span = Span { start, end: start + name.len() };
+        let (tokens, spans) = trailing_operands(text, name_span.end)?;
+
+        match name {
+            ".const" => {
+                let [Token::Identifier(constant), value] = tokens.as_slice() else {
+                    return Err(Issue::new(spans.get(2).copied().unwrap_or(name_span), "Expected a name and a value")
+                        .with_help("usage: .const NAME value"));
+                };
+                self.define(constant, spans[0], line.number, defined_on)?;
+                let value = match self.resolve_operand(OperandKind::Value, value, Site::default()) {
+                    Ok(Operand::Value(value)) => value,
+                    Ok(_) => unreachable!("value operands resolve to values"),
+                    Err(issue) => return Err(Issue { span: spans[1], ..issue }),
+                };
+                self.constants.insert(constant.clone(), value);
             }
-            "ARRAYLEN" => {
-                self.instructions.push(Instruction::ArrayLength);
-                Ok(())
+            ".data" | ".text" if !tokens.is_empty() => {
+                return Err(Issue::new(spans[0], format!("{} takes no operands", name)));
             }
-            "FREEARR" => {
-                self.instructions.push(Instruction::FreeArray);
-                Ok(())
+            ".data" => *section = Section::Data,
+            ".text" => *section = Section::Text,
+            _ => {
+                return Err(Issue::new(name_span, format!("Unknown directive: {}", name))
+                    .with_help("supported directives: .include, .macro, .endm, .const, .data, .text"));
             }
+        }
+        Ok(())
+    }
 
-            // String Operations
-            "NEWSTR" => {
-                if let Some(Token::String(s)) = line.operands.get(0) {
-                    self.instructions.push(Instruction::NewString(s.clone()));
-                    Ok(())
-                } else {
-                    Err("NEWSTR requires a string operand".to_string())
+    /// Handle a `name: "text"` or `name: 1, 2, 3` line in a `.data` section
+    fn data_entry(&mut self, line: &SourceLine, defined_on: &mut HashMap<String, usize>) -> Result<(), Issue> {
+        let text = line.text.as_str();
+        let start = text.len() - text.trim_start().len();
+        let Ok((rest, name)) = terminated(identifier, char(':'))(&text[start..]) else {
+            let end = start + text[start..].find(char::is_whitespace).unwrap_or(text.len() - start);
+            return Err(Issue::new(Span { start, end }, "Expected a data item")
+                .with_help("declare `name: \"text\"` or `name: 1, 2, 3`, or switch back to code with `.text`"));
+        };
+        let name_span = Span { start, end: start + name.len() };
+        let (tokens, spans) = trailing_operands(text, text.len() - rest.len())?;
+        self.define(name, name_span, line.number, defined_on)?;
+
+        let value = match tokens.as_slice() {
+            [] => return Err(Issue::new(name_span, format!("Missing value for {}", name))
+                .with_help("declare `name: \"text\"` or `name: 1, 2, 3`")),
+            [Token::String(string)] => HeapValue::String(string.clone()),
+            _ => {
+                let mut elements = Vec::new();
+                for (token, span) in tokens.iter().zip(&spans) {
+                    match self.resolve_operand(OperandKind::Value, token, Site::default()) {
+                        Ok(Operand::Value(value)) => elements.push(value),
+                        Ok(_) => unreachable!("value operands resolve to values"),
+                        Err(issue) => return Err(Issue { span: *span, ..issue }),
+                    }
                 }
+                HeapValue::Array(elements)
             }
-            "STRCAT" => {
-                self.instructions.push(Instruction::StringConcat);
-                Ok(())
-            }
-            "STRLEN" => {
-                self.instructions.push(Instruction::StringLength);
-                Ok(())
-            }
-            "FREESTR" => {
-                self.instructions.push(Instruction::FreeString);
+        };
+        self.data.push(DataItem { name: name.to_string(), value });
+        Ok(())
+    }
+
+    /// Claim a constant or data name, rejecting duplicates and reserved words
+    fn define(&self, name: &str, span: Span, line: usize, defined_on: &mut HashMap<String, usize>) -> Result<(), Issue> {
+        if matches!(name, "true" | "false" | "nil" | "inf" | "nan") {
+            return Err(Issue::new(span, format!("`{}` is reserved and cannot be redefined", name)));
+        }
+        match defined_on.entry(name.to_string()) {
+            Entry::Occupied(first) => Err(Issue::new(span, format!("Duplicate definition: {}", name))
+                .with_help(format!("`{}` is first defined on line {}", name, first.get()))),
+            Entry::Vacant(slot) => {
+                slot.insert(line);
                 Ok(())
             }
+        }
+    }
 
-            // Control Flow
-            "JMP" => {
-                if let Some(Token::Identifier(label)) = line.operands.get(0) {
-                    if let Some(&address) = self.labels.get(label) {
-                        self.instructions.push(Instruction::Jump(address));
-                        Ok(())
-                    } else {
-                        Err(format!("Label not found: {}", label))
-                    }
-                } else {
-                    Err("JMP requires a label operand".to_string())
-                }
-            }
-            "JMPZ" => {
-                if let Some(Token::Identifier(label)) = line.operands.get(0) {
-                    if let Some(&address) = self.labels.get(label) {
-                        self.instructions.push(Instruction::JumpIfZero(address));
-                        Ok(())
-                    } else {
-                        Err(format!("Label not found: {}", label))
-                    }
-                } else {
-                    Err("JMPZ requires a label operand".to_string())
-                }
+    /// Record a label defined at `address`. Local labels (`.name`) are stored
+    /// under their parent as `parent.name`; other labels become the parent,
+    /// except the `__name_N` labels that macros generate. Numeric labels may
+    /// be defined any number of times.
+    fn define_label(&mut self, label: &str, address: usize, line: &SourceLine, scope: &mut Option<String>,
+                    defined_on: &mut HashMap<String, usize>) -> Result<(), Issue> {
+        let start = line.text.len() - line.text.trim_start().len();
+        let span = Span { start, end: start + label.len() };
+
+        if label.bytes().all(|b| b.is_ascii_digit()) {
+            self.anonymous.push((label.to_string(), address));
+            return Ok(());
+        }
+        let name = if label.starts_with('.') {
+            match scope {
+                Some(parent) => format!("{}{}", parent, label),
+                None => return Err(Issue::new(span, format!("Local label {} has no parent label", label))
+                    .with_help("define a label without a leading `.` above it")),
             }
-            "JMPNZ" => {
-                if let Some(Token::Identifier(label)) = line.operands.get(0) {
-                    if let Some(&address) = self.labels.get(label) {
-                        self.instructions.push(Instruction::JumpIfNotZero(address));
-                        Ok(())
-                    } else {
-                        Err(format!("Label not found: {}", label))
-                    }
-                } else {
-                    Err("JMPNZ requires a label operand".to_string())
-                }
+        } else {
+            if !label.starts_with("__") {
+                *scope = Some(label.to_string());
             }
+            label.to_string()
+        };
 
-            // I/O Operations
-            "PRINT" => {
-                self.instructions.push(Instruction::Print);
+        match defined_on.entry(name.clone()) {
+            Entry::Occupied(first) => Err(Issue::new(span, format!("Duplicate label: {}", name))
+                .with_help(format!("`{}` is first defined on line {}", name, first.get()))),
+            Entry::Vacant(slot) => {
+                slot.insert(line.number);
+                self.labels.insert(name, address);
                 Ok(())
             }
-            "PRINTCHAR" => {
-                self.instructions.push(Instruction::PrintChar);
-                Ok(())
+        }
+    }
+
+    fn report(&mut self, line: &SourceLine, issue: Issue) {
+        self.diagnostics.push(line.diagnostic(
+            issue.span.start, issue.span.end, issue.severity, issue.message, issue.help));
+    }
+
+    fn process_instruction(&self, line: &AsmLine, site: Site) -> Result<Instruction, Issue> {
+        let opcode = Opcode::from_mnemonic(&line.instruction).ok_or_else(|| {
+            let issue = Issue::new(line.instruction_span, format!("Unknown instruction: {}", line.instruction));
+            let mnemonics = OPCODES.iter().map(|info| info.mnemonic);
+            match closest(&line.instruction, mnemonics) {
+                Some(suggestion) => issue.with_help(format!("did you mean `{}`?", suggestion)),
+                None => issue,
             }
-            "PRINTSTR" => {
-                if let Some(Token::String(s)) = line.operands.get(0) {
-                    self.instructions.push(Instruction::PrintStr(s.clone()));
-                    Ok(())
-                } else {
-                    Err("PRINTSTR requires a string operand".to_string())
+        })?;
+        let info = opcode.info();
+
+        if line.operands.len() != info.operands.len() {
+            let span = line.operand_spans.get(info.operands.len())
+                .copied()
+                .unwrap_or(line.instruction_span);
+            return Err(Issue::new(span, format!("{} expects {} operand(s), found {}",
+                                                info.mnemonic, info.operands.len(), line.operands.len()))
+                .with_help(format!("usage: {}", info.syntax())));
+        }
+
+        let mut operands = Vec::new();
+        for ((kind, token), span) in info.operands.iter().zip(&line.operands).zip(&line.operand_spans) {
+            let operand = self.resolve_operand(*kind, token, site).map_err(|issue| Issue {
+                span: *span,
+                help: issue.help.or_else(|| Some(format!("usage: {}", info.syntax()))),
+                ..issue
+            })?;
+            operands.push(operand);
+        }
+
+        Ok(Instruction::from_operands(opcode, &operands)
+            .expect("operands are resolved from the opcode table"))
+    }
+
+    /// Convert a parsed token into the operand kind the opcode table expects
+    fn resolve_operand(&self, kind: OperandKind, token: &Token, site: Site) -> Result<Operand, Issue> {
+        let unplaced = Span { start: 0, end: 0 };
+        match (kind, token) {
+            (OperandKind::Value, Token::Number(n)) => Ok(Operand::Value(Value::Int(*n))),
+            (OperandKind::Value, Token::Float(x)) => Ok(Operand::Value(Value::Float(*x))),
+            (OperandKind::Value, Token::Identifier(word)) => match word.as_str() {
+                "true" => Ok(Operand::Value(Value::Bool(true))),
+                "false" => Ok(Operand::Value(Value::Bool(false))),
+                "nil" => Ok(Operand::Value(Value::Nil)),
+                "inf" => Ok(Operand::Value(Value::Float(f64::INFINITY))),
+                "nan" => Ok(Operand::Value(Value::Float(f64::NAN))),
+                _ => match self.constants.get(word) {
+                    Some(value) => Ok(Operand::Value(value.clone())),
+                    None => {
+                        let issue = Issue::new(unplaced, format!("Expected a number, true, false, nil or constant, found {}", word));
+                        match closest(word, self.constants.keys().map(String::as_str)) {
+                            Some(suggestion) => Err(issue.with_help(format!("did you mean `{}`?", suggestion))),
+                            None => Err(issue),
+                        }
+                    }
+                },
+            },
+            (OperandKind::Name, Token::Identifier(name)) => Ok(Operand::Name(name.clone())),
+            (OperandKind::Text, Token::String(text)) => Ok(Operand::Text(text.clone())),
+            (OperandKind::Address, Token::Identifier(label)) => self.resolve_label(label, site).map(Operand::Address),
+            (OperandKind::Address, Token::Number(n)) if *n >= 0 => Ok(Operand::Address(*n as usize)),
+            (OperandKind::Address, Token::Relative(offset)) => match site.address.checked_add_signed(*offset as isize) {
+                Some(address) => Ok(Operand::Address(address)),
+                None => Err(Issue::new(unplaced, format!("Jump target ${:+} is before the start of the program", offset))),
+            },
+            (OperandKind::Count, Token::Number(n)) if *n >= 0 => Ok(Operand::Count(*n as usize)),
+            (OperandKind::Count, Token::Identifier(name)) => match self.constants.get(name) {
+                Some(&Value::Int(n)) if n >= 0 => Ok(Operand::Count(n as usize)),
+                Some(_) => Err(Issue::new(unplaced, format!("Constant {} is not a non-negative integer", name))),
+                None => Err(Issue::new(unplaced, format!("Expected a {} operand", kind.placeholder()))),
+            },
+            (kind, _) => Err(Issue::new(unplaced, format!("Expected a {} operand", kind.placeholder()))),
+        }
+    }
+}
+
+impl Assembler {
+    /// Address of a named, local (`.name`) or anonymous (`1f`, `1b`) label
+    fn resolve_label(&self, label: &str, site: Site) -> Result<usize, Issue> {
+        let unplaced = Span { start: 0, end: 0 };
+
+        if let Some((number, direction)) = anonymous_reference(label) {
+            let found = match direction {
+                'f' => self.anonymous.iter().find(|(n, address)| n == number && *address > site.address),
+                _ => self.anonymous.iter().rev().find(|(n, address)| n == number && *address <= site.address),
+            };
+            let side = if direction == 'f' { "after" } else { "before" };
+            return found.map(|&(_, address)| address)
+                .ok_or_else(|| Issue::new(unplaced, format!("No label {}: {} this line", number, side)));
+        }
+
+        let name = match (label.starts_with('.'), site.scope) {
+            (true, Some(parent)) => format!("{}{}", parent, label),
+            (true, None) => return Err(Issue::new(unplaced, format!("Local label {} used outside any parent label", label))),
+            (false, _) => label.to_string(),
+        };
+        match self.labels.get(&name) {
+            Some(&address) => Ok(address),
+            None => {
+                let issue = Issue::new(unplaced, format!("Label not found: {}", name));
+                match closest(&name, self.labels.keys().map(String::as_str)) {
+                    Some(suggestion) => Err(issue.with_help(format!("did you mean `{}`?", suggestion))),
+                    None => Err(issue.with_help("define it with `label:` at the start of a line")),
                 }
             }
-            "HALT" => {
-                self.instructions.push(Instruction::Halt);
-                Ok(())
-            }
+        }
+    }
+}
+
+/// Whether trimmed text starts a `//` or `;` comment
+pub(crate) fn is_comment(text: &str) -> bool {
+    text.starts_with("//") || text.starts_with(';')
+}
 
-            _ => Err(format!("Unknown instruction: {}", line.instruction))
+/// Split `1f` into `("1", 'f')`; `None` for anything but an anonymous label reference
+fn anonymous_reference(label: &str) -> Option<(&str, char)> {
+    let direction = label.chars().last().filter(|c| matches!(c, 'f' | 'b'))?;
+    let number = &label[..label.len() - 1];
+    (!number.is_empty() && number.bytes().all(|b| b.is_ascii_digit())).then_some((number, direction))
+}
+
+/// Closest candidate within two edits of `word`, ignoring case
+fn closest<'a>(word: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let word = word.to_lowercase();
+    candidates
+        .map(|candidate| (edit_distance(&word, &candidate.to_lowercase()), candidate))
+        .filter(|(distance, _)| *distance <= 2)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
         }
+        previous = current;
     }
+    previous[b.len()]
 }
 
 // Parser functions
@@ -272,61 +539,312 @@ pub fn identifier(input: &str) -> IResult<&str, &str> {
     )(input)
 }
 
+/// Integer literal: decimal, `0x` hexadecimal or `0b` binary, optionally
+/// negative, with `_` allowed between digits (`1_000`, `0xFF_FF`).
+/// Hexadecimal and binary literals may spell out all 64 bits, so
+/// `0xFFFFFFFFFFFFFFFF` is -1.
+///
+/// Once a digit is seen the whole word is the literal, so `0x`, `0b102` and
+/// `1__0` fail outright instead of stopping early and leaving the rest as
+/// another operand.
 pub fn number(input: &str) -> IResult<&str, i64> {
-    map_res(
-        recognize(
-            pair(
-                opt(char('-')),
-                digit1
-            )
+    let (rest, negative) = opt(char('-'))(input)?;
+    let (rest, word) = recognize(pair(satisfy(|c| c.is_ascii_digit()), literal_word))(rest)?;
+
+    let failure = |kind| nom::Err::Failure(nom::error::Error::new(input, kind));
+    let (radix, digits, is_digit): (u32, &str, fn(char) -> bool) = match radix(word) {
+        16 => (16, &word[2..], |c| c.is_ascii_hexdigit()),
+        2 => (2, &word[2..], |c| c == '0' || c == '1'),
+        _ => (10, word, |c| c.is_ascii_digit()),
+    };
+    if !matches!(separated_digits(is_digit)(digits), Ok(("", _))) {
+        return Err(failure(ErrorKind::Digit));
+    }
+
+    let magnitude = u64::from_str_radix(&digits.replace('_', ""), radix)
+        .map_err(|_| failure(ErrorKind::TooLarge))?;
+    let value = match (negative.is_some(), radix) {
+        (true, _) if magnitude <= i64::MIN.unsigned_abs() => (magnitude as i64).wrapping_neg(),
+        (false, 10) if magnitude <= i64::MAX as u64 => magnitude as i64,
+        (false, 2 | 16) => magnitude as i64,
+        _ => return Err(failure(ErrorKind::TooLarge)),
+    };
+    Ok((rest, value))
+}
+
+/// Letters, digits and underscores continuing a literal
+fn literal_word(input: &str) -> IResult<&str, &str> {
+    take_while(|c: char| c.is_alphanumeric() || c == '_')(input)
+}
+
+/// Radix an integer literal is written in, judged by its prefix
+fn radix(word: &str) -> u32 {
+    match word.get(..2).map(str::to_ascii_lowercase).as_deref() {
+        Some("0x") => 16,
+        Some("0b") => 2,
+        _ => 10,
+    }
+}
+
+/// Digits accepted by `is_digit`, with single underscores between them
+fn separated_digits(is_digit: fn(char) -> bool) -> impl Fn(&str) -> IResult<&str, &str> {
+    move |input| recognize(pair(
+        take_while1(is_digit),
+        many0(pair(char('_'), take_while1(is_digit))),
+    ))(input)
+}
+
+/// Floating-point literal: requires a fractional part or an exponent so that
+/// plain integers keep parsing as `number`. Digit separators are allowed.
+/// `-inf` is read here; `inf` and `nan` are words resolved like `true`.
+pub fn float(input: &str) -> IResult<&str, f64> {
+    let decimal = || separated_digits(|c| c.is_ascii_digit());
+    let negative_infinity = terminated(tag("-inf"), not(satisfy(|c: char| c.is_alphanumeric() || c == '_')));
+    alt((
+        map(negative_infinity, |_| f64::NEG_INFINITY),
+        map_res(
+            recognize(
+                tuple((
+                    opt(char('-')),
+                    decimal(),
+                    alt((
+                        recognize(pair(pair(char('.'), decimal()), opt(exponent))),
+                        exponent,
+                    )),
+                ))
+            ),
+            |text: &str| text.replace('_', "").parse::<f64>()
         ),
-        str::parse::<i64>
+    ))(input)
+}
+
+fn exponent(input: &str) -> IResult<&str, &str> {
+    recognize(
+        tuple((
+            one_of("eE"),
+            opt(one_of("+-")),
+            digit1
+        ))
     )(input)
 }
 
+/// One escape sequence starting at the backslash: `\n \t \r \0 \\ \" \'`
+/// or `\u{...}` with one to six hex digits. Anything else is a failure
+/// located at the backslash.
+fn escape(input: &str) -> IResult<&str, char> {
+    let invalid = || nom::Err::Failure(nom::error::Error::new(input, ErrorKind::Escaped));
+    let rest = input.strip_prefix('\\').ok_or_else(invalid)?;
+    let mut chars = rest.chars();
+    let c = match chars.next().ok_or_else(invalid)? {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        '0' => '\0',
+        c @ ('\\' | '"' | '\'') => c,
+        'u' => {
+            let (rest, code) = delimited(char('{'), take_while_m_n(1, 6, |c: char| c.is_ascii_hexdigit()), char('}'))(chars.as_str())
+                .map_err(|_: nom::Err<nom::error::Error<&str>>| invalid())?;
+            let c = u32::from_str_radix(code, 16).ok().and_then(char::from_u32).ok_or_else(invalid)?;
+            return Ok((rest, c));
+        }
+        _ => return Err(invalid()),
+    };
+    Ok((chars.as_str(), c))
+}
+
+/// Quoted string, possibly empty, with escapes resolved. An unknown escape or
+/// a missing closing quote is a failure rather than a non-match.
 pub fn string_literal(input: &str) -> IResult<&str, String> {
-    delimited(
-        char('"'),
-        map(
-            take_while1(|c| c != '"'),
-            String::from
-        ),
-        char('"')
-    )(input)
+    let (mut rest, _) = char('"')(input)?;
+    let mut value = String::new();
+    loop {
+        let mut chars = rest.chars();
+        match chars.next() {
+            Some('"') => return Ok((chars.as_str(), value)),
+            Some('\\') => {
+                let (after, c) = escape(rest)?;
+                value.push(c);
+                rest = after;
+                continue;
+            }
+            Some(c) => value.push(c),
+            None => break,
+        }
+        rest = chars.as_str();
+    }
+    Err(nom::Err::Failure(nom::error::Error::new(input, ErrorKind::Eof)))
 }
 
+/// Character literal such as `'A'` or `'\n'`, as its code point
+pub fn char_literal(input: &str) -> IResult<&str, i64> {
+    let (rest, _) = char('\'')(input)?;
+    let (rest, c) = match rest.chars().next() {
+        Some('\\') => escape(rest)?,
+        Some(c) if c != '\'' => (&rest[c.len_utf8()..], c),
+        _ => return Err(nom::Err::Failure(nom::error::Error::new(input, ErrorKind::Char))),
+    };
+    let (rest, _) = char('\'')(rest)
+        .map_err(|_: nom::Err<nom::error::Error<&str>>| nom::Err::Failure(nom::error::Error::new(input, ErrorKind::Char)))?;
+    Ok((rest, c as i64))
+}
+
+/// Describe a lexing failure from the parsers above, located in `line`
+fn lex_issue(line: &str, error: &nom::Err<nom::error::Error<&str>>) -> Option<Issue> {
+    let nom::Err::Failure(error) = error else { return None };
+    let start = line.len() - error.input.len();
+    let issue = match error.code {
+        ErrorKind::Escaped => {
+            let length = error.input.chars().take(2).map(char::len_utf8).sum::<usize>();
+            let end = error.input.find('}').filter(|_| error.input.starts_with("\\u"))
+                .map_or(start + length, |close| start + close + 1);
+            Issue::new(Span { start, end }, "Invalid escape sequence")
+                .with_help("supported escapes: \\n \\t \\r \\0 \\\\ \\\" \\' \\u{hex}")
+        }
+        ErrorKind::Eof => Issue::new(Span { start, end: line.len() }, "Unterminated string literal")
+            .with_help("close the string with `\"`"),
+        ErrorKind::Char => Issue::new(Span { start, end: line.len().min(start + 3) }, "Invalid character literal")
+            .with_help("a character literal holds exactly one character, as in 'A' or '\\n'"),
+        ErrorKind::Digit | ErrorKind::TooLarge => {
+            let sign = usize::from(error.input.starts_with('-'));
+            let (_, word) = literal_word(&error.input[sign..]).expect("take_while cannot fail");
+            let span = Span { start, end: start + sign + word.len() };
+            match (error.code, radix(word)) {
+                (ErrorKind::TooLarge, _) => Issue::new(span, "Integer literal out of range")
+                    .with_help("decimal integers run from -9223372036854775808 to 9223372036854775807; hexadecimal and binary ones may use all 64 bits"),
+                (_, 16) => Issue::new(span, "Invalid hexadecimal literal")
+                    .with_help("write `0x` followed by digits 0-9 and A-F, as in 0xFF_FF"),
+                (_, 2) => Issue::new(span, "Invalid binary literal")
+                    .with_help("write `0b` followed by digits 0 and 1, as in 0b1010_0101"),
+                _ => Issue::new(span, "Invalid integer literal")
+                    .with_help("digits may be separated by single underscores, as in 1_000"),
+            }
+        }
+        _ => return None,
+    };
+    Some(issue)
+}
+
+/// Label definition: `name:`, a local `.name:` or an anonymous `1:`
 pub fn label(input: &str) -> IResult<&str, Token> {
     map(
-        terminated(identifier, char(':')),
+        terminated(alt((recognize(pair(char('.'), identifier)), identifier, digit1)), char(':')),
         |s: &str| Token::Label(s.to_string())
     )(input)
 }
 
+/// A line holding just a label, returning its name
+pub fn label_line(line: &str) -> IResult<&str, String> {
+    map(preceded(multispace0, label), |token| match token {
+        Token::Label(s) => s,
+        _ => unreachable!(),
+    })(line)
+}
+
+/// Label reference: `name`, a local `.name`, a qualified `parent.name`,
+/// or `1f`/`1b` for the next or previous anonymous label `1:`
+pub fn label_reference(input: &str) -> IResult<&str, &str> {
+    let anonymous = terminated(
+        recognize(pair(digit1, one_of("fb"))),
+        not(satisfy(|c: char| c.is_alphanumeric() || c == '_')),
+    );
+    alt((
+        anonymous,
+        recognize(pair(opt(char('.')), separated_list1(char('.'), identifier))),
+    ))(input)
+}
+
+/// Whether `word` assembles back into the same name operand, as in `LOAD word`
+pub fn is_name(word: &str) -> bool {
+    matches!(operand(word), Ok(("", Token::Identifier(parsed))) if parsed == word)
+}
+
+/// Relative jump target: `$` is the instruction itself, `$+2` two past it
+pub fn relative(input: &str) -> IResult<&str, i64> {
+    let (rest, _) = char('$')(input)?;
+    let (rest, offset) = opt(pair(one_of("+-"), map_res(digit1, str::parse::<i64>)))(rest)?;
+    Ok((rest, match offset {
+        Some(('-', n)) => -n,
+        Some((_, n)) => n,
+        None => 0,
+    }))
+}
+
+/// Mnemonics may also start with a digit or a dash, as in `2DUP` and `-ROT`
+pub fn mnemonic(input: &str) -> IResult<&str, &str> {
+    recognize(
+        pair(
+            opt(char('-')),
+            take_while1(|c: char| c.is_alphanumeric() || c == '_')
+        )
+    )(input)
+}
+
 pub fn instruction(input: &str) -> IResult<&str, Token> {
     map(
-        identifier,
+        mnemonic,
         |s: &str| Token::Instruction(s.to_uppercase())
     )(input)
 }
 
 pub fn operand(input: &str) -> IResult<&str, Token> {
     alt((
+        map(float, Token::Float),
+        map(label_reference, |s: &str| Token::Identifier(s.to_string())),
         map(number, Token::Number),
+        map(char_literal, Token::Number),
         map(string_literal, Token::String),
-        map(identifier, |s: &str| Token::Identifier(s.to_string())),
+        map(relative, Token::Relative),
     ))(input)
 }
 
-pub fn parse_line(input: &str) -> IResult<&str, AsmLine> {
-    let (input, _) = multispace0(input)?;
+/// Comma- or space-separated operands, with spans relative to `line`
+pub fn operands<'a>(line: &str, mut input: &'a str) -> IResult<&'a str, (Vec<Token>, Vec<Span>)> {
+    let mut tokens = Vec::new();
+    let mut spans = Vec::new();
+    loop {
+        let start = line.len() - input.len();
+        match operand(input) {
+            Ok((rest, token)) => {
+                spans.push(Span { start, end: line.len() - rest.len() });
+                tokens.push(token);
+                let (rest, _) = delimited(multispace0, opt(char(',')), multispace0)(rest)?;
+                input = rest;
+            }
+            Err(nom::Err::Error(_)) => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok((input, (tokens, spans)))
+}
+
+/// Operands from byte `start` of `line` to its end, allowing a trailing comment
+fn trailing_operands(line: &str, start: usize) -> Result<(Vec<Token>, Vec<Span>), Issue> {
+    let (input, _) = multispace0::<_, nom::error::Error<&str>>(&line[start..])
+        .expect("multispace0 cannot fail");
+    let (rest, operands) = match operands(line, input) {
+        Ok(parsed) => parsed,
+        Err(error) => return Err(lex_issue(line, &error).unwrap_or_else(|| Issue::new(Span { start, end: line.len() }, "Could not parse operands"))),
+    };
+    let trailing = rest.trim_start();
+    if trailing.is_empty() || is_comment(trailing) {
+        return Ok(operands);
+    }
+    let start = line.len() - trailing.len();
+    let end = start + trailing.find(char::is_whitespace).unwrap_or(trailing.len());
+    Err(Issue::new(Span { start, end }, "Unexpected input")
+        .with_help("operands are numbers, names, labels or quoted strings; comments start with `//` or `;`"))
+}
+
+pub fn parse_line(line: &str) -> IResult<&str, AsmLine> {
+    let offset = |rest: &str| line.len() - rest.len();
+
+    let (input, _) = multispace0(line)?;
     let (input, label) = opt(terminated(label, multispace0))(input)?;
-    let (input, instr) = terminated(instruction, multispace0)(input)?;
-    let (input, operands) = many0(
-        terminated(
-            operand,
-            delimited(multispace0, opt(char(',')), multispace0)
-        )
-    )(input)?;
+    let instruction_start = offset(input);
+    let (input, instr) = instruction(input)?;
+    let instruction_span = Span { start: instruction_start, end: offset(input) };
+    let (input, _) = multispace0(input)?;
+    let (input, (operands, operand_spans)) = operands(line, input)?;
 
     Ok((input, AsmLine {
         label: label.map(|token| match token {
@@ -338,5 +856,7 @@ pub fn parse_line(input: &str) -> IResult<&str, AsmLine> {
             _ => unreachable!(),
         },
         operands,
+        instruction_span,
+        operand_spans,
     }))
-}
+}
\ No newline at end of file
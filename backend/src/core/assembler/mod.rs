@@ -1,2 +1,7 @@
+#[allow(clippy::module_inception)]
 pub mod assembler;
-pub use assembler::*;
\ No newline at end of file
+pub mod preprocessor;
+pub mod resolver;
+pub use assembler::*;
+pub use preprocessor::*;
+pub use resolver::*;
\ No newline at end of file
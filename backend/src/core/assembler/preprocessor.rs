@@ -0,0 +1,391 @@
+use std::collections::HashMap;
+use crate::core::error::{AssemblerError, Expansion, Severity};
+use crate::core::instruction::Opcode;
+use super::{is_comment, parse_line, string_literal, SourceResolver};
+
+/// Deepest chain of macro invocations before expansion gives up, which also
+/// stops runaway recursive macros
+const MAX_EXPANSION_DEPTH: usize = 32;
+
+/// One line of source after preprocessing
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceLine {
+    /// Included file the line comes from, `None` for the main source
+    pub file: Option<String>,
+    /// 1-based line number; inside a macro, the line of the macro body
+    pub number: usize,
+    pub text: String,
+    /// Macro invocations that produced this line, innermost first
+    pub expansion: Vec<Expansion>,
+}
+
+impl SourceLine {
+    /// Diagnostic for the text between byte offsets `start` and `end`
+    pub fn diagnostic(&self, start: usize, end: usize, severity: Severity, message: String, help: Option<String>) -> AssemblerError {
+        let start = start.min(self.text.len());
+        let end = end.clamp(start, self.text.len());
+        AssemblerError {
+            file: self.file.clone(),
+            line: self.number,
+            column: self.text[..start].chars().count() + 1,
+            token: self.text[start..end].to_string(),
+            severity,
+            message,
+            help,
+            expansion: collapse_cycles(&self.expansion),
+        }
+    }
+
+    pub(crate) fn is_blank(&self) -> bool {
+        let trimmed = self.text.trim();
+        trimmed.is_empty() || is_comment(trimmed)
+    }
+}
+
+/// Fold each run of frames that repeats back to back into one copy of the
+/// group, so a macro that invokes itself, directly or through others, is
+/// reported once with a count instead of once per level
+fn collapse_cycles(frames: &[Expansion]) -> Vec<Expansion> {
+    let mut collapsed = Vec::new();
+    let mut rest = frames;
+    while !rest.is_empty() {
+        let Some(cycle) = (1..=rest.len() / 2).find(|&k| rest[..k] == rest[k..2 * k]) else {
+            collapsed.push(rest[0].clone());
+            rest = &rest[1..];
+            continue;
+        };
+        let mut repeated = 1;
+        while rest.get((repeated + 1) * cycle..(repeated + 2) * cycle) == Some(&rest[..cycle]) {
+            repeated += 1;
+        }
+        collapsed.extend_from_slice(&rest[..cycle]);
+        let last = collapsed.last_mut().expect("a cycle has at least one frame");
+        last.repeated = repeated;
+        last.cycle = cycle;
+        rest = &rest[(repeated + 1) * cycle..];
+    }
+    collapsed
+}
+
+#[derive(Debug, Clone)]
+struct Macro {
+    name: String,
+    params: Vec<String>,
+    body: Vec<SourceLine>,
+}
+
+/// Splices in `.include "path"` files and expands `.macro NAME a, b ... .endm`
+/// definitions before assembly.
+///
+/// Inside a body `%a` is replaced by the argument for parameter `a`, and
+/// `%%name` by a label unique to each expansion. Macros may invoke other
+/// macros. A label on an invocation line is kept on a line of its own ahead
+/// of the expansion.
+#[derive(Default, Debug)]
+pub struct Preprocessor {
+    macros: HashMap<String, Macro>,
+    expansions: usize,
+    diagnostics: Vec<AssemblerError>,
+}
+
+impl Preprocessor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Expand a whole source file, returning the lines left to assemble.
+    /// Without a resolver every `.include` is an error.
+    pub fn process(&mut self, source: &str, resolver: Option<&dyn SourceResolver>) -> Vec<SourceLine> {
+        self.macros.clear();
+        self.expansions = 0;
+        self.diagnostics.clear();
+
+        let mut lines = Vec::new();
+        self.read(source, None, resolver, &mut Vec::new(), &mut lines);
+        let program = self.collect_macros(lines.into_iter());
+
+        let mut output = Vec::new();
+        for line in program {
+            self.expand(line, &mut output, 0);
+        }
+        output
+    }
+
+    /// Errors found while preprocessing
+    pub fn diagnostics(&self) -> &[AssemblerError] {
+        &self.diagnostics
+    }
+
+    /// Split `text` into lines, replacing each `.include` with the lines of the
+    /// included file. `including` holds the files currently being read.
+    fn read(&mut self, text: &str, file: Option<&str>, resolver: Option<&dyn SourceResolver>,
+            including: &mut Vec<String>, lines: &mut Vec<SourceLine>) {
+        for (index, text) in text.lines().enumerate() {
+            let line = SourceLine {
+                file: file.map(str::to_string),
+                number: index + 1,
+                text: text.to_string(),
+                expansion: Vec::new(),
+            };
+            match directive(text) {
+                Some((".include", start, rest)) => self.include(&line, start, rest, resolver, including, lines),
+                _ => lines.push(line),
+            }
+        }
+    }
+
+    fn include(&mut self, line: &SourceLine, start: usize, rest: &str, resolver: Option<&dyn SourceResolver>,
+               including: &mut Vec<String>, lines: &mut Vec<SourceLine>) {
+        let end = line.text.len();
+        let path = match string_literal(rest) {
+            Ok(("", path)) => path,
+            _ => {
+                self.error(line, start, end, "Expected a quoted path".to_string(),
+                           Some("usage: .include \"path.asm\"".to_string()));
+                return;
+            }
+        };
+        let Some(resolver) = resolver else {
+            self.error(line, start, end, "Includes are not available here".to_string(), None);
+            return;
+        };
+        let source = match resolver.resolve(&path, line.file.as_deref()) {
+            Ok(source) => source,
+            Err(reason) => {
+                self.error(line, start, end, format!("Cannot include \"{}\": {}", path, reason), None);
+                return;
+            }
+        };
+        if including.contains(&source.name) {
+            let cycle = including.iter()
+                .skip_while(|name| **name != source.name)
+                .chain(std::iter::once(&source.name))
+                .cloned()
+                .collect::<Vec<_>>();
+            self.error(line, start, end, format!("Include cycle: {}", cycle.join(" -> ")), None);
+            return;
+        }
+
+        including.push(source.name.clone());
+        self.read(&source.text, Some(&source.name), Some(resolver), including, lines);
+        including.pop();
+    }
+
+    fn error(&mut self, line: &SourceLine, start: usize, end: usize, message: String, help: Option<String>) {
+        self.diagnostics.push(line.diagnostic(start, end, Severity::Error, message, help));
+    }
+
+    /// Remove macro definitions from the source, keeping everything else
+    fn collect_macros(&mut self, lines: impl Iterator<Item = SourceLine>) -> Vec<SourceLine> {
+        let mut program = Vec::new();
+        // A definition with a bad header is still read up to its `.endm`, then dropped
+        let mut current: Option<(Option<Macro>, SourceLine)> = None;
+
+        for line in lines {
+            let directive = directive(&line.text);
+            match (directive, current.as_mut()) {
+                (Some((".macro", start, _)), Some(_)) => {
+                    self.error(&line, start, start + 6, "Nested macro definition".to_string(),
+                               Some("close the current macro with `.endm` first".to_string()));
+                }
+                (Some((".macro", start, rest)), None) => {
+                    let definition = self.macro_header(&line, start, rest);
+                    current = Some((definition, line));
+                }
+                (Some((".endm", _, _)), Some(_)) => {
+                    if let Some((Some(definition), _)) = current.take() {
+                        self.macros.insert(definition.name.clone(), definition);
+                    }
+                }
+                (Some((".endm", start, _)), None) => {
+                    self.error(&line, start, start + 5, "`.endm` without a matching `.macro`".to_string(), None);
+                }
+                (_, Some((definition, _))) => {
+                    if let Some(definition) = definition {
+                        definition.body.push(line);
+                    }
+                }
+                // Other directives are left for the assembler
+                (_, None) => program.push(line),
+            }
+        }
+
+        if let Some((_, line)) = current {
+            let start = line.text.len() - line.text.trim_start().len();
+            self.error(&line, start, line.text.len(), "Macro definition is never closed".to_string(),
+                       Some("end the definition with `.endm`".to_string()));
+        }
+        program
+    }
+
+    /// Parse `.macro NAME a, b`
+    fn macro_header(&mut self, line: &SourceLine, start: usize, rest: &str) -> Option<Macro> {
+        let mut words = rest.split(|c: char| c == ',' || c.is_whitespace()).filter(|w| !w.is_empty());
+        let Some(name) = words.next() else {
+            self.error(line, start, line.text.len(), "Missing macro name".to_string(),
+                       Some("usage: .macro NAME param1, param2".to_string()));
+            return None;
+        };
+        let params: Vec<String> = words.map(str::to_string).collect();
+        let name_start = line.text.find(name).unwrap_or(start);
+        let name_end = name_start + name.len();
+
+        if !is_identifier(name) {
+            self.error(line, name_start, name_end, format!("Invalid macro name: {}", name), None);
+            return None;
+        }
+        let upper = name.to_uppercase();
+        if Opcode::from_mnemonic(&upper).is_some() {
+            self.error(line, name_start, name_end, format!("Macro {} would shadow an instruction", name), None);
+            return None;
+        }
+        if self.macros.contains_key(&upper) {
+            self.error(line, name_start, name_end, format!("Macro {} is already defined", name), None);
+            return None;
+        }
+        if let Some(bad) = params.iter().find(|param| !is_identifier(param)) {
+            let bad_start = line.text.rfind(bad.as_str()).unwrap_or(start);
+            self.error(line, bad_start, bad_start + bad.len(), format!("Invalid macro parameter: {}", bad), None);
+            return None;
+        }
+
+        Some(Macro { name: upper, params, body: Vec::new() })
+    }
+
+    fn expand(&mut self, line: SourceLine, output: &mut Vec<SourceLine>, depth: usize) {
+        let Ok((_, parsed)) = parse_line(&line.text) else {
+            output.push(line);
+            return;
+        };
+        let Some(definition) = self.macros.get(&parsed.instruction).cloned() else {
+            output.push(line);
+            return;
+        };
+        let (start, end) = (parsed.instruction_span.start, parsed.instruction_span.end);
+
+        if parsed.operand_spans.len() != definition.params.len() {
+            let message = format!("Macro {} expects {} argument(s), found {}",
+                                  definition.name, definition.params.len(), parsed.operand_spans.len());
+            let usage = format!("usage: {} {}", definition.name, definition.params.join(", "));
+            self.error(&line, start, end, message, Some(usage.trim_end().to_string()));
+            return;
+        }
+        if depth >= MAX_EXPANSION_DEPTH {
+            self.error(&line, start, end, format!("Macro {} expands too deeply", definition.name),
+                       Some("a macro probably invokes itself".to_string()));
+            return;
+        }
+
+        let args: HashMap<&str, &str> = definition.params.iter()
+            .map(String::as_str)
+            .zip(parsed.operand_spans.iter().map(|span| &line.text[span.start..span.end]))
+            .collect();
+        self.expansions += 1;
+        let id = self.expansions;
+
+        let mut expansion = vec![Expansion {
+            name: definition.name.clone(),
+            file: line.file.clone(),
+            line: line.number,
+            repeated: 0,
+            cycle: 0,
+        }];
+        expansion.extend(line.expansion.iter().cloned());
+
+        if let Some(label) = parsed.label {
+            output.push(SourceLine { text: format!("{}:", label), ..line.clone() });
+        }
+        for body in definition.body.iter().filter(|body| !body.is_blank()) {
+            let mut expanded = SourceLine {
+                file: body.file.clone(),
+                number: body.number,
+                text: body.text.clone(),
+                expansion: expansion.clone(),
+            };
+            let Some(text) = self.substitute(&expanded, &definition, &args, id) else { continue };
+            expanded.text = text;
+            self.expand(expanded, output, depth + 1);
+        }
+    }
+
+    /// Replace `%param` and `%%label` in a body line
+    fn substitute(&mut self, line: &SourceLine, definition: &Macro, args: &HashMap<&str, &str>, id: usize) -> Option<String> {
+        let text = &line.text;
+        let mut result = String::with_capacity(text.len());
+        let mut rest = text.as_str();
+
+        while let Some(position) = rest.find('%') {
+            result.push_str(&rest[..position]);
+            let after = &rest[position + 1..];
+            let (local, name_start) = match after.strip_prefix('%') {
+                Some(name) => (true, name),
+                None => (false, after),
+            };
+            let length = name_start.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(name_start.len());
+            let name = &name_start[..length];
+
+            if name.is_empty() || !is_identifier(name) {
+                result.push('%');
+                rest = after;
+                continue;
+            }
+            if local {
+                result.push_str(&format!("__{}_{}", name, id));
+            } else if let Some(arg) = args.get(name) {
+                result.push_str(arg);
+            } else {
+                let start = text.len() - rest.len() + position;
+                let help = if definition.params.is_empty() {
+                    format!("macro {} takes no parameters", definition.name)
+                } else {
+                    format!("parameters of {}: {}", definition.name, definition.params.join(", "))
+                };
+                self.error(line, start, start + 1 + name.len(), format!("Unknown macro parameter: %{}", name), Some(help));
+                return None;
+            }
+            rest = &name_start[length..];
+        }
+        result.push_str(rest);
+        Some(result)
+    }
+}
+
+/// `(directive, byte offset, rest of line)` for lines such as `.macro NAME a`.
+/// A trailing `//` or `;` comment is dropped. Labels ending in `:` are not directives.
+pub(crate) fn directive(text: &str) -> Option<(&str, usize, &str)> {
+    let trimmed = text.trim_start();
+    let start = text.len() - trimmed.len();
+    let body = trimmed.strip_prefix('.')?;
+    let length = body.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(body.len());
+    if length == 0 || body[length..].starts_with(':') {
+        return None;
+    }
+    let rest = &body[length..];
+    let rest = strip_comment(rest).trim();
+    Some((&trimmed[..length + 1], start, rest))
+}
+
+fn is_identifier(word: &str) -> bool {
+    let mut chars = word.chars();
+    matches!(chars.next(), Some(c) if c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+
+/// `text` up to a `//` or `;` comment outside string and character literals
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (index, c) in text.char_indices() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(open) if c == open => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == ';' || text[index..].starts_with("//") => return &text[..index],
+            None => {}
+        }
+    }
+    text
+}
\ No newline at end of file
@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+
+/// Source text found for an `.include`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedSource {
+    /// Name used in diagnostics and to detect include cycles
+    pub name: String,
+    pub text: String,
+}
+
+/// Supplies the files named by `.include "path"`.
+///
+/// `from` is the name of the including file, or `None` when the include is in
+/// the main source, so relative paths can be resolved against it.
+pub trait SourceResolver: fmt::Debug {
+    fn resolve(&self, path: &str, from: Option<&str>) -> Result<ResolvedSource, String>;
+}
+
+/// Reads included files from disk, relative to `root` or to the including file.
+/// Includes are not confined to `root`: `..` segments can climb above it and
+/// absolute paths are read as given, like any file the user could open.
+#[derive(Debug, Clone)]
+pub struct FileResolver {
+    root: PathBuf,
+}
+
+impl FileResolver {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        FileResolver { root: root.into() }
+    }
+}
+
+impl SourceResolver for FileResolver {
+    fn resolve(&self, path: &str, from: Option<&str>) -> Result<ResolvedSource, String> {
+        let name = include_name(path, from);
+        let text = std::fs::read_to_string(self.root.join(&name)).map_err(|e| e.to_string())?;
+        Ok(ResolvedSource { name, text })
+    }
+}
+
+/// Serves included files from memory, keyed by path. `/api/load` builds one
+/// from the `files` bundle uploaded with the program.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryResolver {
+    files: HashMap<String, String>,
+}
+
+impl MemoryResolver {
+    pub fn new(files: HashMap<String, String>) -> Self {
+        MemoryResolver { files }
+    }
+
+    /// Add or replace a file
+    pub fn insert(&mut self, path: impl Into<String>, text: impl Into<String>) {
+        self.files.insert(path.into(), text.into());
+    }
+}
+
+impl SourceResolver for MemoryResolver {
+    fn resolve(&self, path: &str, from: Option<&str>) -> Result<ResolvedSource, String> {
+        let name = include_name(path, from);
+        match self.files.get(&name) {
+            Some(text) => Ok(ResolvedSource { name, text: text.clone() }),
+            None => Err("no such file".to_string()),
+        }
+    }
+}
+
+/// `path` relative to the directory of `from`, with `.` and `..` segments removed
+pub fn include_name(path: &str, from: Option<&str>) -> String {
+    let mut segments: Vec<&str> = match from {
+        Some(from) if !path.starts_with('/') => {
+            let mut segments: Vec<&str> = from.split('/').collect();
+            segments.pop();
+            segments
+        }
+        _ => Vec::new(),
+    };
+    for segment in path.split('/') {
+        match segment {
+            "." => {}
+            ".." if segments.last().is_some_and(|last| !last.is_empty() && *last != "..") => {
+                segments.pop();
+            }
+            "" if !segments.is_empty() => {}
+            _ => segments.push(segment),
+        }
+    }
+    segments.join("/")
+}
@@ -0,0 +1,254 @@
+use bytes::{Buf, BufMut};
+use std::collections::HashMap;
+use std::path::Path;
+use crate::core::assembler::is_name;
+use crate::core::error::BytecodeError;
+use crate::core::instruction::{Instruction, Opcode, Operand, OperandKind};
+use crate::core::value::Value;
+
+/// File signature at the start of every bytecode file
+pub const MAGIC: &[u8; 4] = b"SVMB";
+
+/// Current format version; files with any other version are rejected
+pub const VERSION: u16 = 1;
+
+// Tags for `Value` operands
+const TAG_INT: u8 = 0;
+const TAG_FLOAT: u8 = 1;
+const TAG_BOOL: u8 = 2;
+const TAG_NIL: u8 = 3;
+
+// Layout, all integers little-endian:
+//
+//   magic     4 bytes  "SVMB"
+//   version   u16
+//   pool      varint count, then per string: varint length + UTF-8 bytes
+//   code      varint count, then per instruction: u8 opcode + operands
+//   checksum  u32      FNV-1a of every preceding byte
+//
+// Opcodes are indexes into `OPCODES`. Name and text operands are varint indexes
+// into the string pool, counts and addresses are varints, and values are a tag
+// byte followed by a zigzag varint (int), 8-byte float, bool byte or nothing (nil).
+// Heap references only exist at runtime, so they have no tag.
+
+/// Encode a program into the binary bytecode format
+pub fn encode(instructions: &[Instruction]) -> Result<Vec<u8>, BytecodeError> {
+    let mut pool = StringPool::default();
+    let mut code = Vec::new();
+    for instruction in instructions {
+        code.put_u8(instruction.opcode() as u8);
+        for operand in instruction.operands() {
+            match operand {
+                Operand::Value(value) => put_value(&mut code, &value)?,
+                Operand::Name(text) | Operand::Text(text) => put_varint(&mut code, pool.index(text) as u64),
+                Operand::Address(n) | Operand::Count(n) => put_varint(&mut code, n as u64),
+            }
+        }
+    }
+
+    let mut bytes = Vec::with_capacity(code.len() + 16);
+    bytes.put_slice(MAGIC);
+    bytes.put_u16_le(VERSION);
+    put_varint(&mut bytes, pool.strings.len() as u64);
+    for string in &pool.strings {
+        put_varint(&mut bytes, string.len() as u64);
+        bytes.put_slice(string.as_bytes());
+    }
+    put_varint(&mut bytes, instructions.len() as u64);
+    bytes.extend_from_slice(&code);
+    let checksum = fnv1a(&bytes);
+    bytes.put_u32_le(checksum);
+    Ok(bytes)
+}
+
+/// Decode a program from the binary bytecode format
+pub fn decode(bytes: &[u8]) -> Result<Vec<Instruction>, BytecodeError> {
+    let mut header = bytes;
+    if header.remaining() < MAGIC.len() {
+        return Err(BytecodeError::UnexpectedEnd);
+    }
+    if &header[..MAGIC.len()] != MAGIC {
+        return Err(BytecodeError::BadMagic);
+    }
+    header.advance(MAGIC.len());
+    if header.remaining() < 2 {
+        return Err(BytecodeError::UnexpectedEnd);
+    }
+    let version = header.get_u16_le();
+    if version != VERSION {
+        return Err(BytecodeError::UnsupportedVersion(version, VERSION));
+    }
+
+    let body_len = bytes.len().checked_sub(4).filter(|&len| len >= MAGIC.len() + 2)
+        .ok_or(BytecodeError::UnexpectedEnd)?;
+    let (body, mut trailer) = bytes.split_at(body_len);
+    let expected = trailer.get_u32_le();
+    let found = fnv1a(body);
+    if expected != found {
+        return Err(BytecodeError::ChecksumMismatch { expected, found });
+    }
+
+    let mut input = &body[MAGIC.len() + 2..];
+    let pool_len = get_usize(&mut input)?;
+    let mut pool = Vec::new();
+    for _ in 0..pool_len {
+        let len = get_usize(&mut input)?;
+        let raw = take(&mut input, len)?;
+        let string = std::str::from_utf8(raw).map_err(|_| BytecodeError::InvalidUtf8)?;
+        pool.push(string.to_string());
+    }
+
+    let count = get_usize(&mut input)?;
+    let mut instructions = Vec::new();
+    for _ in 0..count {
+        let byte = take(&mut input, 1)?[0];
+        let opcode = *Opcode::ALL.get(byte as usize).ok_or(BytecodeError::InvalidOpcode(byte))?;
+        let mut operands = Vec::new();
+        for kind in opcode.info().operands {
+            let operand = match kind {
+                OperandKind::Value => Operand::Value(get_value(&mut input)?),
+                OperandKind::Name => Operand::Name(get_name(&mut input, &pool)?),
+                OperandKind::Text => Operand::Text(get_string(&mut input, &pool)?),
+                OperandKind::Address => Operand::Address(get_usize(&mut input)?),
+                OperandKind::Count => Operand::Count(get_count(&mut input)?),
+            };
+            operands.push(operand);
+        }
+        instructions.push(Instruction::from_operands(opcode, &operands)
+            .expect("operands are decoded from the opcode table"));
+    }
+
+    if input.has_remaining() {
+        return Err(BytecodeError::TrailingBytes);
+    }
+    Ok(instructions)
+}
+
+/// Write a program to a bytecode file
+pub fn save<P: AsRef<Path>>(path: P, instructions: &[Instruction]) -> Result<(), BytecodeError> {
+    std::fs::write(path, encode(instructions)?).map_err(|e| BytecodeError::IOError(e.to_string()))
+}
+
+/// Read a program from a bytecode file
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<Instruction>, BytecodeError> {
+    let bytes = std::fs::read(path).map_err(|e| BytecodeError::IOError(e.to_string()))?;
+    decode(&bytes)
+}
+
+/// Deduplicated strings referenced by name and text operands
+#[derive(Default)]
+struct StringPool {
+    strings: Vec<String>,
+    indexes: HashMap<String, usize>,
+}
+
+impl StringPool {
+    fn index(&mut self, string: String) -> usize {
+        if let Some(&index) = self.indexes.get(&string) {
+            return index;
+        }
+        self.strings.push(string.clone());
+        self.indexes.insert(string, self.strings.len() - 1);
+        self.strings.len() - 1
+    }
+}
+
+fn put_varint(bytes: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        bytes.put_u8((n as u8) | 0x80);
+        n >>= 7;
+    }
+    bytes.put_u8(n as u8);
+}
+
+fn put_value(bytes: &mut Vec<u8>, value: &Value) -> Result<(), BytecodeError> {
+    match value {
+        Value::Int(n) => {
+            bytes.put_u8(TAG_INT);
+            put_varint(bytes, ((n << 1) ^ (n >> 63)) as u64);
+        }
+        Value::Float(x) => {
+            bytes.put_u8(TAG_FLOAT);
+            bytes.put_f64_le(*x);
+        }
+        Value::Bool(b) => {
+            bytes.put_u8(TAG_BOOL);
+            bytes.put_u8(*b as u8);
+        }
+        Value::Nil => bytes.put_u8(TAG_NIL),
+        Value::Ref(_) => return Err(BytecodeError::HeapReference),
+    }
+    Ok(())
+}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8], BytecodeError> {
+    if input.len() < len {
+        return Err(BytecodeError::UnexpectedEnd);
+    }
+    let (taken, rest) = input.split_at(len);
+    *input = rest;
+    Ok(taken)
+}
+
+/// The tenth byte holds only bit 63, so any higher bit there is lost and rejected
+fn get_varint(input: &mut &[u8]) -> Result<u64, BytecodeError> {
+    let mut n = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = take(input, 1)?[0];
+        let bits = u64::from(byte & 0x7f);
+        if (bits << shift) >> shift != bits {
+            return Err(BytecodeError::NumberTooLarge);
+        }
+        n |= bits << shift;
+        if byte & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+    Err(BytecodeError::NumberTooLarge)
+}
+
+fn get_usize(input: &mut &[u8]) -> Result<usize, BytecodeError> {
+    usize::try_from(get_varint(input)?).map_err(|_| BytecodeError::NumberTooLarge)
+}
+
+/// Counts are capped at `i64::MAX`, the largest the assembler accepts
+fn get_count(input: &mut &[u8]) -> Result<usize, BytecodeError> {
+    let count = get_usize(input)?;
+    if count > i64::MAX as usize {
+        return Err(BytecodeError::CountTooLarge(count));
+    }
+    Ok(count)
+}
+
+fn get_string(input: &mut &[u8], pool: &[String]) -> Result<String, BytecodeError> {
+    let index = get_usize(input)?;
+    pool.get(index).cloned().ok_or(BytecodeError::InvalidPoolIndex(index))
+}
+
+/// Names must be identifiers, as the assembler would accept them
+fn get_name(input: &mut &[u8], pool: &[String]) -> Result<String, BytecodeError> {
+    let name = get_string(input, pool)?;
+    if !is_name(&name) {
+        return Err(BytecodeError::InvalidName(name));
+    }
+    Ok(name)
+}
+
+fn get_value(input: &mut &[u8]) -> Result<Value, BytecodeError> {
+    let tag = take(input, 1)?[0];
+    match tag {
+        TAG_INT => {
+            let n = get_varint(input)?;
+            Ok(Value::Int(((n >> 1) as i64) ^ -((n & 1) as i64)))
+        }
+        TAG_FLOAT => Ok(Value::Float(take(input, 8)?.get_f64_le())),
+        TAG_BOOL => Ok(Value::Bool(take(input, 1)?[0] != 0)),
+        TAG_NIL => Ok(Value::Nil),
+        tag => Err(BytecodeError::InvalidValueTag(tag)),
+    }
+}
+
+/// 32-bit FNV-1a hash, used as the file checksum
+fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, &byte| (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193))
+}
@@ -0,0 +1,3 @@
+#[allow(clippy::module_inception)]
+pub mod bytecode;
+pub use bytecode::*;
\ No newline at end of file
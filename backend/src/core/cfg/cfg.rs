@@ -0,0 +1,328 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use crate::core::disassembler::Disassembler;
+use crate::core::instruction::{Instruction, Operand};
+use crate::core::state::Function;
+
+/// A straight-line run of instructions entered only at the top
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicBlock {
+    pub id: usize,
+    /// Address of the first instruction
+    pub start: usize,
+    /// Address one past the last instruction
+    pub end: usize,
+    pub successors: Vec<usize>,
+    pub predecessors: Vec<usize>,
+    pub reachable: bool,
+}
+
+/// A natural loop: the header plus every block that reaches a back edge to it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Loop {
+    pub header: usize,
+    /// Blocks whose edge back to the header closes the loop
+    pub latches: Vec<usize>,
+    /// Every block in the loop, header included, in order
+    pub blocks: Vec<usize>,
+}
+
+/// Control-flow graph of an assembled program
+#[derive(Debug, Clone)]
+pub struct ControlFlowGraph {
+    pub blocks: Vec<BasicBlock>,
+    pub loops: Vec<Loop>,
+    instructions: Vec<Instruction>,
+    /// Label name for each labelled address
+    names: BTreeMap<usize, String>,
+    block_of: Vec<usize>,
+}
+
+impl ControlFlowGraph {
+    /// Split a program into basic blocks. Blocks start at the entry point,
+    /// labels, jump targets and function bodies, and end after jumps, `HALT`,
+    /// returns and function definitions. `labels` are the assembler's label
+    /// addresses and only affect block boundaries and names.
+    pub fn build(instructions: &[Instruction], labels: &HashMap<String, usize>) -> Self {
+        let len = instructions.len();
+        let functions = Function::scan(instructions);
+
+        let mut names = BTreeMap::new();
+        for (name, &address) in labels {
+            let entry = names.entry(address).or_insert_with(|| name.clone());
+            if name < entry {
+                *entry = name.clone();
+            }
+        }
+
+        let mut leaders = BTreeSet::new();
+        if len > 0 {
+            leaders.insert(0);
+        }
+        for (address, instruction) in instructions.iter().enumerate() {
+            if let Some(target) = instruction.jump_target() {
+                leaders.insert(target);
+            }
+            if ends_block(instruction) {
+                leaders.insert(address + 1);
+            }
+        }
+        leaders.extend(names.keys().copied());
+        leaders.retain(|&address| address < len);
+
+        let starts: Vec<usize> = leaders.into_iter().collect();
+        let mut block_of = vec![0; len];
+        let mut blocks = Vec::new();
+        for (id, &start) in starts.iter().enumerate() {
+            let end = starts.get(id + 1).copied().unwrap_or(len);
+            block_of[start..end].fill(id);
+            blocks.push(BasicBlock {
+                id,
+                start,
+                end,
+                successors: Vec::new(),
+                predecessors: Vec::new(),
+                reachable: false,
+            });
+        }
+
+        for id in 0..blocks.len() {
+            let last = blocks[id].end - 1;
+            let successors: Vec<usize> = successor_addresses(&instructions[last], last, &functions)
+                .into_iter()
+                .filter(|&address| address < len)
+                .map(|address| block_of[address])
+                .fold(Vec::new(), |mut unique, block| {
+                    if !unique.contains(&block) {
+                        unique.push(block);
+                    }
+                    unique
+                });
+            for &successor in &successors {
+                blocks[successor].predecessors.push(id);
+            }
+            blocks[id].successors = successors;
+        }
+
+        let mut cfg = ControlFlowGraph {
+            blocks,
+            loops: Vec::new(),
+            instructions: instructions.to_vec(),
+            names,
+            block_of,
+        };
+        cfg.mark_reachable(&functions);
+        cfg.find_loops(&functions);
+        cfg
+    }
+
+    /// Block containing the instruction at `address`
+    pub fn block_at(&self, address: usize) -> Option<&BasicBlock> {
+        self.block_of.get(address).map(|&id| &self.blocks[id])
+    }
+
+    /// Blocks that can never execute
+    pub fn unreachable_blocks(&self) -> Vec<usize> {
+        self.blocks.iter().filter(|block| !block.reachable).map(|block| block.id).collect()
+    }
+
+    /// Display name of a block: its label, or `B<id>` if it has none
+    pub fn block_name(&self, id: usize) -> String {
+        match self.names.get(&self.blocks[id].start) {
+            Some(name) => name.clone(),
+            None => format!("B{}", id),
+        }
+    }
+
+    /// Graphviz rendering of the graph. Jump operands use the original label
+    /// names; unreachable blocks are dashed and loop back edges are bold.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n");
+
+        for block in &self.blocks {
+            let mut text = format!("{}:\\l", dot_escape(&self.block_name(block.id)));
+            for address in block.start..block.end {
+                let line = format!("{:>4}  {}", address, self.instruction_text(address));
+                text.push_str(&dot_escape(&line));
+                text.push_str("\\l");
+            }
+            let style = if block.reachable { "" } else { ", style=dashed, color=gray" };
+            dot.push_str(&format!("    b{} [label=\"{}\"{}];\n", block.id, text, style));
+        }
+
+        for block in &self.blocks {
+            let last = &self.instructions[block.end - 1];
+            for &successor in &block.successors {
+                let mut attributes = Vec::new();
+                let taken = last.jump_target() == Some(self.blocks[successor].start);
+                if let Some(label) = edge_label(last, taken) {
+                    attributes.push(format!("label=\"{}\"", label));
+                }
+                if self.loops.iter().any(|l| l.header == successor && l.latches.contains(&block.id)) {
+                    attributes.push("style=bold".to_string());
+                }
+                let attributes = if attributes.is_empty() {
+                    String::new()
+                } else {
+                    format!(" [{}]", attributes.join(", "))
+                };
+                dot.push_str(&format!("    b{} -> b{}{};\n", block.id, successor, attributes));
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Instruction text with jump targets written as label names
+    fn instruction_text(&self, address: usize) -> String {
+        let instruction = &self.instructions[address];
+        let mut text = instruction.info().mnemonic.to_string();
+        for operand in instruction.operands() {
+            match operand {
+                Operand::Address(target) => match self.names.get(&target) {
+                    Some(name) => text.push_str(&format!(" {}", name)),
+                    None if target < self.instructions.len() => {
+                        text.push_str(&format!(" {}", Disassembler::label_for(target)));
+                    }
+                    None => text.push_str(&format!(" {}", target)),
+                },
+                operand => text.push_str(&format!(" {}", operand)),
+            }
+        }
+        text
+    }
+
+    /// Reachable from the entry point, following calls into function bodies
+    fn mark_reachable(&mut self, functions: &HashMap<String, Function>) {
+        let mut worklist: Vec<usize> = if self.blocks.is_empty() { vec![] } else { vec![0] };
+        while let Some(id) = worklist.pop() {
+            if self.blocks[id].reachable {
+                continue;
+            }
+            self.blocks[id].reachable = true;
+            worklist.extend(self.blocks[id].successors.iter().copied());
+            worklist.extend(self.called_entries(id, functions));
+        }
+    }
+
+    /// Entry blocks of the functions called from a block
+    fn called_entries(&self, id: usize, functions: &HashMap<String, Function>) -> Vec<usize> {
+        let block = &self.blocks[id];
+        self.instructions[block.start..block.end].iter()
+            .filter_map(|instruction| match instruction {
+                Instruction::Call(name) => functions.get(name),
+                _ => None,
+            })
+            .filter_map(|function| self.block_of.get(function.address + 1).copied())
+            .collect()
+    }
+
+    /// Natural loops from back edges, i.e. edges whose target dominates their source
+    fn find_loops(&mut self, functions: &HashMap<String, Function>) {
+        let count = self.blocks.len();
+        let mut roots: BTreeSet<usize> = BTreeSet::new();
+        if count > 0 {
+            roots.insert(0);
+        }
+        for function in functions.values() {
+            if let Some(&entry) = self.block_of.get(function.address + 1) {
+                roots.insert(entry);
+            }
+        }
+
+        // Iterative dominator sets over reachable blocks; roots dominate only themselves
+        let reachable: Vec<usize> = (0..count).filter(|&id| self.blocks[id].reachable).collect();
+        let all: BTreeSet<usize> = reachable.iter().copied().collect();
+        let mut dominators: Vec<BTreeSet<usize>> = (0..count)
+            .map(|id| if roots.contains(&id) { BTreeSet::from([id]) } else { all.clone() })
+            .collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &id in reachable.iter().filter(|id| !roots.contains(id)) {
+                let mut incoming = self.blocks[id].predecessors.iter()
+                    .filter(|&&p| self.blocks[p].reachable)
+                    .map(|&p| &dominators[p]);
+                let mut next = match incoming.next() {
+                    Some(first) => first.clone(),
+                    None => BTreeSet::new(),
+                };
+                for set in incoming {
+                    next = next.intersection(set).copied().collect();
+                }
+                next.insert(id);
+                if next != dominators[id] {
+                    dominators[id] = next;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut loops: BTreeMap<usize, (Vec<usize>, BTreeSet<usize>)> = BTreeMap::new();
+        for &latch in &reachable {
+            for &header in &self.blocks[latch].successors {
+                if !dominators[latch].contains(&header) {
+                    continue;
+                }
+                let (latches, body) = loops.entry(header).or_default();
+                latches.push(latch);
+                body.insert(header);
+                let mut worklist = vec![latch];
+                while let Some(id) = worklist.pop() {
+                    if body.insert(id) {
+                        worklist.extend(self.blocks[id].predecessors.iter().copied());
+                    }
+                }
+            }
+        }
+
+        self.loops = loops.into_iter()
+            .map(|(header, (latches, blocks))| Loop { header, latches, blocks: blocks.into_iter().collect() })
+            .collect();
+    }
+}
+
+/// Whether control can leave the instruction other than by falling through
+fn ends_block(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::Jump(_)
+            | Instruction::JumpIf(_)
+            | Instruction::JumpIfZero(_)
+            | Instruction::JumpIfNotZero(_)
+            | Instruction::Halt
+            | Instruction::Return
+            | Instruction::EndFunction
+            | Instruction::DefineFunction(..)
+    )
+}
+
+/// Addresses control can move to after the instruction at `address`
+pub(crate) fn successor_addresses(instruction: &Instruction, address: usize, functions: &HashMap<String, Function>) -> Vec<usize> {
+    match instruction {
+        Instruction::Jump(target) => vec![*target],
+        Instruction::JumpIf(target)
+        | Instruction::JumpIfZero(target)
+        | Instruction::JumpIfNotZero(target) => vec![*target, address + 1],
+        Instruction::Halt | Instruction::Return | Instruction::EndFunction => vec![],
+        // Reached inline, a definition skips over its body
+        Instruction::DefineFunction(name, _) => functions.get(name)
+            .map(|function| vec![function.end_address + 1])
+            .unwrap_or_default(),
+        _ => vec![address + 1],
+    }
+}
+
+/// Condition under which a conditional jump takes an edge
+fn edge_label(instruction: &Instruction, taken: bool) -> Option<&'static str> {
+    let jumps_when = match instruction {
+        Instruction::JumpIfZero(_) => false,
+        Instruction::JumpIf(_) | Instruction::JumpIfNotZero(_) => true,
+        _ => return None,
+    };
+    Some(if taken == jumps_when { "true" } else { "false" })
+}
+
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
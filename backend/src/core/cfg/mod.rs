@@ -0,0 +1,3 @@
+#[allow(clippy::module_inception)]
+pub mod cfg;
+pub use cfg::*;
\ No newline at end of file
@@ -0,0 +1,491 @@
+use std::collections::{BTreeMap, HashMap};
+use crate::core::instruction::Instruction;
+use crate::core::state::{ArithmeticMode, DataItem, Function, HistoryOptions, StopReason, Watch};
+use crate::core::value::Value;
+use crate::core::vm::VM;
+
+/// Instructions shown on each side of the target by `list`
+const LIST_CONTEXT: usize = 5;
+
+const HELP: &str = "\
+  break LABEL|PC      stop before the instruction at a label, function or address
+  watch NAME          stop when a global changes
+  watch &ID           stop when a heap id is allocated, changed or freed
+  watch depth N       stop when the stack grows to N values
+  delete N            remove breakpoint or watchpoint N
+  info breakpoints    list breakpoints and watchpoints
+  run                 start the program from the beginning
+  step                execute one instruction, entering calls
+  next                execute one instruction, running calls to completion
+  continue            run until a breakpoint, watchpoint or the end of the program
+  reverse-step        undo the last instruction
+  reverse-continue    run backwards to a breakpoint or the last change to a watch
+  print NAME          show a local or global variable
+  stack               show the stack
+  heap ID             show a heap allocation
+  backtrace           show the call stack
+  list [LABEL|PC]     show the instructions around the current one
+  quit                leave
+An empty line repeats step, next, continue, their reverse forms or list.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    /// Positioned at the first instruction, nothing executed yet
+    Ready,
+    /// Stopped between instructions
+    Stopped,
+    /// Halted, ran off the end or failed
+    Exited,
+}
+
+/// A numbered breakpoint or watchpoint
+#[derive(Debug, Clone)]
+enum Point {
+    Break(usize),
+    /// The watch and its id in the current VM
+    Watch(Watch, usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Resume {
+    Step,
+    Next,
+    Continue,
+}
+
+/// Line-mode debugger over the `VM`'s breakpoints and watchpoints.
+///
+/// Addresses are described through the assembler's labels and the program's
+/// functions, e.g. `12 <loop+2>`, and breakpoints accept the same names.
+pub struct Debugger {
+    vm: VM,
+    instructions: Vec<Instruction>,
+    data: Vec<DataItem>,
+    arithmetic_mode: ArithmeticMode,
+    labels: HashMap<String, usize>,
+    /// Preferred name for each labelled address
+    symbols: BTreeMap<usize, String>,
+    functions: HashMap<String, Function>,
+    /// Breakpoints and watchpoints by number
+    points: BTreeMap<usize, Point>,
+    next_point: usize,
+    status: Status,
+    last_command: String,
+    finished: bool,
+}
+
+impl Debugger {
+    /// Debug a verified program. `labels` are the assembler's symbols, or an
+    /// empty map for bytecode; function names are always available.
+    pub fn new(instructions: Vec<Instruction>, data: Vec<DataItem>, labels: HashMap<String, usize>) -> Self {
+        let functions = Function::scan(&instructions);
+
+        // Functions win over labels at the same address; otherwise prefer
+        // names written in the source over macro-generated `__` ones, then
+        // the shortest
+        let mut symbols: BTreeMap<usize, String> = BTreeMap::new();
+        for (name, &address) in &labels {
+            let better = match symbols.get(&address) {
+                None => true,
+                Some(current) => {
+                    let rank = |n: &str| (n.starts_with("__"), n.len(), n.to_string());
+                    rank(name) < rank(current)
+                }
+            };
+            if better {
+                symbols.insert(address, name.clone());
+            }
+        }
+        for function in functions.values() {
+            symbols.insert(function.address, function.name.clone());
+        }
+        let mut vm = VM::new(instructions.clone());
+        vm.load_data(&data);
+        vm.enable_history(HistoryOptions::default());
+        Debugger {
+            vm,
+            instructions,
+            data,
+            arithmetic_mode: ArithmeticMode::default(),
+            labels,
+            symbols,
+            functions,
+            points: BTreeMap::new(),
+            next_point: 1,
+            status: Status::Ready,
+            last_command: String::new(),
+            finished: false,
+        }
+    }
+
+    pub fn set_arithmetic_mode(&mut self, mode: ArithmeticMode) {
+        self.arithmetic_mode = mode;
+        self.vm.set_arithmetic_mode(mode);
+    }
+
+    pub fn vm(&self) -> &VM {
+        &self.vm
+    }
+
+    /// Whether `quit` has been entered
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Run one debugger command and return the text to show for it
+    pub fn execute(&mut self, line: &str) -> String {
+        let mut line = line.trim().to_string();
+        if line.is_empty() {
+            line = self.last_command.clone();
+        }
+        let (command, argument) = line.split_once(char::is_whitespace).unwrap_or((&line, ""));
+        let argument = argument.trim();
+
+        let reply = match command {
+            "" => String::new(),
+            "break" | "b" => self.add_breakpoint(argument),
+            "watch" | "w" => self.add_watch(argument),
+            "delete" | "d" => self.delete_point(argument),
+            "info" | "i" => match argument {
+                "breakpoints" | "break" | "b" => self.info_breakpoints(),
+                _ => "usage: info breakpoints".to_string(),
+            },
+            "run" | "r" => {
+                self.restart();
+                self.resume(Resume::Continue)
+            }
+            "step" | "s" => self.resume(Resume::Step),
+            "next" | "n" => self.resume(Resume::Next),
+            "continue" | "c" => self.resume(Resume::Continue),
+            "reverse-step" | "rs" => self.reverse(false),
+            "reverse-continue" | "rc" => self.reverse(true),
+            "print" | "p" => self.print(argument),
+            "stack" => {
+                let values: Vec<String> = self.vm.get_state().stack.iter().map(Value::to_string).collect();
+                format!("stack: [{}]", values.join(", "))
+            }
+            "heap" => self.heap(argument),
+            "backtrace" | "bt" => self.backtrace(),
+            "list" | "l" => self.list(argument),
+            "help" | "h" => HELP.to_string(),
+            "quit" | "q" => {
+                self.finished = true;
+                String::new()
+            }
+            _ => format!("unknown command {}; try help", command),
+        };
+
+        // Only movement and listing repeat on an empty line
+        let repeatable = [
+            "step", "s", "next", "n", "continue", "c", "reverse-step", "rs", "reverse-continue", "rc", "list", "l",
+        ];
+        if repeatable.contains(&command) {
+            self.last_command = command.to_string();
+        } else {
+            self.last_command.clear();
+        }
+        reply
+    }
+
+    /// `12 <loop+2>` for an address inside or after a labelled one, else `12`
+    pub fn describe(&self, address: usize) -> String {
+        // A function's name only covers its own body
+        let symbol = self.symbols.range(..=address).rev().find(|(_, name)| {
+            self.functions.get(*name).is_none_or(|f| address <= f.end_address)
+        });
+        match symbol {
+            Some((&start, name)) if start == address => format!("{} <{}>", address, name),
+            Some((&start, name)) => format!("{} <{}+{}>", address, name, address - start),
+            None => address.to_string(),
+        }
+    }
+
+    /// Address of a label, function body or number
+    fn resolve(&self, target: &str) -> Result<usize, String> {
+        let address = if let Ok(address) = target.parse::<usize>() {
+            address
+        } else if let Some(function) = self.functions.get(target) {
+            // The call lands on BEGIN, just after FUNC
+            function.address + 1
+        } else if let Some(&address) = self.labels.get(target) {
+            address
+        } else {
+            return Err(format!("No label or function named {}", target));
+        };
+        if address >= self.instructions.len() {
+            return Err(format!("Address {} is outside the program (0..{})", address, self.instructions.len()));
+        }
+        Ok(address)
+    }
+
+    fn add_breakpoint(&mut self, target: &str) -> String {
+        if target.is_empty() {
+            return "usage: break LABEL|PC".to_string();
+        }
+        match self.resolve(target) {
+            Ok(address) => {
+                self.vm.add_breakpoint(address);
+                let number = self.add_point(Point::Break(address));
+                format!("Breakpoint {} at {}", number, self.describe(address))
+            }
+            Err(message) => message,
+        }
+    }
+
+    fn add_watch(&mut self, target: &str) -> String {
+        let watch = if let Some(id) = target.strip_prefix('&') {
+            match id.parse() {
+                Ok(id) => Watch::Heap(id),
+                Err(_) => return "usage: watch NAME | watch &ID | watch depth N".to_string(),
+            }
+        } else if let Some(depth) = target.strip_prefix("depth") {
+            match depth.trim().parse() {
+                Ok(depth) => Watch::StackDepth(depth),
+                Err(_) => return "usage: watch NAME | watch &ID | watch depth N".to_string(),
+            }
+        } else if !target.is_empty() {
+            Watch::Memory(target.to_string())
+        } else {
+            return "usage: watch NAME | watch &ID | watch depth N".to_string();
+        };
+        let id = self.vm.add_watch(watch.clone());
+        let number = self.add_point(Point::Watch(watch.clone(), id));
+        format!("Watchpoint {}: {}", number, describe_watch(&watch))
+    }
+
+    fn add_point(&mut self, point: Point) -> usize {
+        let number = self.next_point;
+        self.next_point += 1;
+        self.points.insert(number, point);
+        number
+    }
+
+    fn delete_point(&mut self, argument: &str) -> String {
+        let Ok(number) = argument.parse::<usize>() else {
+            return "usage: delete N".to_string();
+        };
+        match self.points.remove(&number) {
+            Some(Point::Break(address)) => {
+                // Other breakpoints may share the address
+                if self.breakpoint_at(address).is_none() {
+                    self.vm.remove_breakpoint(address);
+                }
+                format!("Deleted breakpoint {}", number)
+            }
+            Some(Point::Watch(_, id)) => {
+                self.vm.remove_watch(id);
+                format!("Deleted watchpoint {}", number)
+            }
+            None => format!("No breakpoint or watchpoint number {}", number),
+        }
+    }
+
+    fn info_breakpoints(&self) -> String {
+        if self.points.is_empty() {
+            return "No breakpoints or watchpoints".to_string();
+        }
+        let mut lines = vec!["Num  Type        What".to_string()];
+        for (number, point) in &self.points {
+            let (kind, what) = match point {
+                Point::Break(address) => ("breakpoint", self.describe(*address)),
+                Point::Watch(watch, _) => ("watchpoint", describe_watch(watch)),
+            };
+            lines.push(format!("{:<4} {:<11} {}", number, kind, what));
+        }
+        lines.join("\n")
+    }
+
+    fn breakpoint_at(&self, address: usize) -> Option<usize> {
+        self.points.iter()
+            .find(|(_, point)| matches!(point, Point::Break(at) if *at == address))
+            .map(|(&number, _)| number)
+    }
+
+    /// Throw away the running program and start over with a fresh VM, keeping
+    /// every breakpoint and watchpoint
+    fn restart(&mut self) {
+        self.vm = VM::new(self.instructions.clone());
+        self.vm.load_data(&self.data);
+        self.vm.set_arithmetic_mode(self.arithmetic_mode);
+        self.vm.enable_history(HistoryOptions::default());
+        for point in self.points.values_mut() {
+            match point {
+                Point::Break(address) => {
+                    self.vm.add_breakpoint(*address);
+                }
+                Point::Watch(watch, id) => *id = self.vm.add_watch(watch.clone()),
+            }
+        }
+        self.status = Status::Ready;
+    }
+
+    /// Execute until the movement is complete, a breakpoint or watchpoint
+    /// stops the program or it ends, then report where it stopped
+    fn resume(&mut self, how: Resume) -> String {
+        if self.status == Status::Exited {
+            return "The program is not being run; use run to start it again".to_string();
+        }
+        self.status = Status::Stopped;
+
+        let depth = self.vm.call_stack_depth();
+        let stop = loop {
+            if let Some(reason) = self.vm.step_checked() {
+                break self.describe_stop(reason);
+            }
+            // `next` runs a call until it returns to this frame (or above)
+            match how {
+                Resume::Step => break self.here(),
+                Resume::Next if self.vm.call_stack_depth() <= depth => break self.here(),
+                _ => {}
+            }
+        };
+
+        let output = self.vm.take_output().concat();
+        if output.is_empty() {
+            stop
+        } else {
+            format!("{}\n{}", output.trim_end_matches('\n'), stop)
+        }
+    }
+
+    /// Undo one instruction, or run backwards until something stops us
+    fn reverse(&mut self, to_stop: bool) -> String {
+        let stop = if to_stop {
+            let reason = self.vm.reverse_continue();
+            self.describe_stop(reason)
+        } else if self.vm.step_back() {
+            self.here()
+        } else {
+            self.describe_stop(StopReason::HistoryStart)
+        };
+        // Going back leaves a finished or failed program runnable again
+        self.status = Status::Stopped;
+        stop
+    }
+
+    fn describe_stop(&mut self, reason: StopReason) -> String {
+        match reason {
+            StopReason::Breakpoint { address } => {
+                let number = self.breakpoint_at(address).unwrap_or_default();
+                format!("Breakpoint {}, {}", number, self.here())
+            }
+            StopReason::Watch { id, event, .. } => {
+                let number = self.points.iter()
+                    .find(|(_, point)| matches!(point, Point::Watch(_, watch) if *watch == id))
+                    .map_or(0, |(&number, _)| number);
+                format!("Watchpoint {}: {}\n{}", number, event, self.here())
+            }
+            StopReason::Halted | StopReason::StepLimit => {
+                self.status = Status::Exited;
+                "Program finished".to_string()
+            }
+            StopReason::Error { error } => {
+                self.status = Status::Exited;
+                format!("error: {}\n{}", error, self.here())
+            }
+            StopReason::HistoryStart => format!("No earlier history\n{}", self.here()),
+        }
+    }
+
+    /// The next instruction to execute, with its location
+    fn here(&self) -> String {
+        let pc = self.vm.get_state().program_counter;
+        match self.instructions.get(pc) {
+            Some(instruction) => format!("{}: {}", self.describe(pc), instruction),
+            None => self.describe(pc),
+        }
+    }
+
+    fn print(&self, name: &str) -> String {
+        if name.is_empty() {
+            return "usage: print NAME".to_string();
+        }
+        let state = self.vm.get_state();
+        let local = state.call_stack.last().and_then(|frame| frame.local_vars.get(name));
+        let Some(value) = local.or_else(|| state.memory.get(name)) else {
+            return format!("No variable named {} in the current frame or globals", name);
+        };
+        match value {
+            Value::Ref(id) => match state.heap.get(*id) {
+                Some(object) => format!("{} = {} {}", name, value, object),
+                None => format!("{} = {} (freed)", name, value),
+            },
+            _ => format!("{} = {}", name, value),
+        }
+    }
+
+    fn heap(&self, argument: &str) -> String {
+        let Ok(id) = argument.trim_start_matches('&').parse::<usize>() else {
+            return "usage: heap ID".to_string();
+        };
+        match self.vm.get_state().heap.get(id) {
+            Some(object) => format!("&{} = {}", id, object),
+            None => format!("No heap allocation &{}", id),
+        }
+    }
+
+    /// Name of the function whose body holds `address`
+    fn function_at(&self, address: usize) -> Option<&str> {
+        self.functions.values()
+            .find(|f| f.address < address && address <= f.end_address)
+            .map(|f| f.name.as_str())
+    }
+
+    fn backtrace(&self) -> String {
+        let state = self.vm.get_state();
+        // Frame 0 is where we stopped; each caller is at its CALL instruction
+        let mut addresses = vec![state.program_counter];
+        addresses.extend(state.call_stack.iter().rev().map(|frame| frame.return_address - 1));
+
+        let mut lines = Vec::new();
+        for (index, &address) in addresses.iter().enumerate() {
+            let frame = match state.call_stack.len().checked_sub(index + 1) {
+                Some(frame) => {
+                    let params: Vec<String> = state.call_stack[frame].params.iter().map(Value::to_string).collect();
+                    let name = self.function_at(address).unwrap_or("?");
+                    format!("{}({})", name, params.join(", "))
+                }
+                None => "<top level>".to_string(),
+            };
+            lines.push(format!("#{}  {} at {}", index, frame, self.describe(address)));
+        }
+        lines.join("\n")
+    }
+
+    fn list(&self, argument: &str) -> String {
+        let pc = self.vm.get_state().program_counter;
+        let center = if argument.is_empty() {
+            pc
+        } else {
+            match self.resolve(argument) {
+                Ok(address) => address,
+                Err(message) => return message,
+            }
+        };
+        if self.instructions.is_empty() {
+            return "The program is empty".to_string();
+        }
+
+        let start = center.saturating_sub(LIST_CONTEXT);
+        let end = (center + LIST_CONTEXT + 1).min(self.instructions.len());
+        let width = (self.instructions.len() - 1).to_string().len();
+        let mut lines = Vec::new();
+        for address in start..end {
+            if let Some(name) = self.symbols.get(&address) {
+                lines.push(format!("{}:", name));
+            }
+            let current = if address == pc && self.status != Status::Exited { "=>" } else { "  " };
+            let breakpoint = if self.breakpoint_at(address).is_some() { "*" } else { " " };
+            lines.push(format!("{}{} {:>width$}  {}", current, breakpoint, address, self.instructions[address], width = width));
+        }
+        lines.join("\n")
+    }
+}
+
+fn describe_watch(watch: &Watch) -> String {
+    match watch {
+        Watch::Memory(name) => name.clone(),
+        Watch::Heap(id) => format!("&{}", id),
+        Watch::StackDepth(depth) => format!("stack depth {}", depth),
+    }
+}
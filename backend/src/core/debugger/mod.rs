@@ -0,0 +1,3 @@
+#[allow(clippy::module_inception)]
+pub mod debugger;
+pub use debugger::*;
\ No newline at end of file
@@ -0,0 +1,82 @@
+use std::collections::BTreeSet;
+use crate::core::assembler::is_name;
+use crate::core::error::DisassemblyError;
+use crate::core::instruction::{Instruction, Operand};
+use crate::core::value::Value;
+
+/// Turns instructions back into assembly source that re-assembles to the same program
+#[derive(Default, Debug)]
+pub struct Disassembler {
+    /// Append each instruction's address as a trailing comment
+    pub show_addresses: bool,
+}
+
+impl Disassembler {
+    /// Create a new disassembler instance
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a disassembler that annotates every line with its address
+    pub fn with_addresses() -> Self {
+        Disassembler { show_addresses: true }
+    }
+
+    /// Label synthesized for a jump target
+    pub fn label_for(address: usize) -> String {
+        format!("L{}", address)
+    }
+
+    /// Disassemble a program into source, one instruction per line.
+    /// Every jump target inside the program gets a label; targets past the
+    /// end are kept as numeric addresses. Heap references and names that are
+    /// not identifiers have no source form, so they are rejected.
+    pub fn disassemble(&self, instructions: &[Instruction]) -> Result<String, DisassemblyError> {
+        let targets: BTreeSet<usize> = instructions.iter()
+            .filter_map(Instruction::jump_target)
+            .filter(|&target| target < instructions.len())
+            .collect();
+        let label_width = targets.iter()
+            .map(|&target| Self::label_for(target).len() + 1)
+            .max()
+            .unwrap_or(0);
+        let address_width = instructions.len().saturating_sub(1).to_string().len();
+
+        let mut source = String::new();
+        for (address, instruction) in instructions.iter().enumerate() {
+            let label = if targets.contains(&address) {
+                format!("{}:", Self::label_for(address))
+            } else {
+                String::new()
+            };
+            let mut line = if label_width > 0 {
+                format!("{:<width$} {}", label, instruction.info().mnemonic, width = label_width)
+            } else {
+                instruction.info().mnemonic.to_string()
+            };
+
+            for operand in instruction.operands() {
+                match operand {
+                    Operand::Address(target) if targets.contains(&target) => {
+                        line.push(' ');
+                        line.push_str(&Self::label_for(target));
+                    }
+                    Operand::Value(value @ Value::Ref(_)) => {
+                        return Err(DisassemblyError::HeapReference { address, value: value.to_string() });
+                    }
+                    Operand::Name(name) if !is_name(&name) => {
+                        return Err(DisassemblyError::InvalidName { address, name });
+                    }
+                    operand => line.push_str(&format!(" {}", operand)),
+                }
+            }
+
+            if self.show_addresses {
+                line = format!("{:<28} // {:>width$}", line, address, width = address_width);
+            }
+            source.push_str(line.trim_end());
+            source.push('\n');
+        }
+        Ok(source)
+    }
+}
@@ -0,0 +1,3 @@
+#[allow(clippy::module_inception)]
+pub mod disassembler;
+pub use disassembler::*;
\ No newline at end of file
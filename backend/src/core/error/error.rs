@@ -1,3 +1,5 @@
+use serde::Serialize;
+use std::fmt;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -41,6 +43,241 @@ pub enum VMError {
     #[error("Array bounds error: index {0} out of bounds {1}")]
     ArrayBoundsError(i64, usize),
 
+    #[error("Arithmetic overflow at {0}")]
+    ArithmeticOverflow(usize),
+
+    #[error("Negative exponent: {0}")]
+    NegativeExponent(i64),
+
+    #[error("Cannot convert {0} to an integer")]
+    InvalidConversion(f64),
+
     #[error("Type error: expected {0}, found {1}")]
     TypeError(String, String),
-}
\ No newline at end of file
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum BytecodeError {
+    #[error("Not a bytecode file: bad magic number")]
+    BadMagic,
+
+    #[error("Unsupported bytecode version {0}, expected {1}")]
+    UnsupportedVersion(u16, u16),
+
+    #[error("Checksum mismatch: expected {expected:#010x}, found {found:#010x}")]
+    ChecksumMismatch { expected: u32, found: u32 },
+
+    #[error("Unexpected end of bytecode")]
+    UnexpectedEnd,
+
+    #[error("Unexpected trailing bytes after the instructions")]
+    TrailingBytes,
+
+    #[error("Invalid opcode: {0}")]
+    InvalidOpcode(u8),
+
+    #[error("Invalid value tag: {0}")]
+    InvalidValueTag(u8),
+
+    #[error("Heap references cannot be stored in bytecode")]
+    HeapReference,
+
+    #[error("Invalid name: {0:?}")]
+    InvalidName(String),
+
+    #[error("Invalid string pool index: {0}")]
+    InvalidPoolIndex(usize),
+
+    #[error("String pool entry is not valid UTF-8")]
+    InvalidUtf8,
+
+    #[error("Number is too large for its field")]
+    NumberTooLarge,
+
+    #[error("Count {0} is larger than the assembler allows")]
+    CountTooLarge(usize),
+
+    #[error("I/O error: {0}")]
+    IOError(String),
+}
+
+/// An operand the disassembler cannot write as source
+#[derive(Error, Debug, PartialEq)]
+pub enum DisassemblyError {
+    #[error("{address}: heap reference {value} has no source form")]
+    HeapReference { address: usize, value: String },
+
+    #[error("{address}: {name:?} is not a valid name")]
+    InvalidName { address: usize, name: String },
+}
+
+/// A problem the verifier found in a program, located by instruction address
+#[derive(Error, Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum VerifyError {
+    #[error("{address}: jump target {target} is outside the program (0..{len})")]
+    JumpOutOfRange { address: usize, target: usize, len: usize },
+
+    #[error("{address}: jump target {target} is in a different function body")]
+    JumpAcrossFunction { address: usize, target: usize },
+
+    #[error("{address}: call to unknown function {name}")]
+    UnknownFunction { address: usize, name: String },
+
+    #[error("{address}: function {name} is already defined at {first}")]
+    DuplicateFunction { address: usize, name: String, first: usize },
+
+    #[error("{address}: function {name} has no BEGIN")]
+    MissingBegin { address: usize, name: String },
+
+    #[error("{address}: BEGIN without a preceding FUNC")]
+    UnexpectedBegin { address: usize },
+
+    #[error("{address}: END without a matching function")]
+    UnmatchedEnd { address: usize },
+
+    #[error("{address}: function {name} has no END")]
+    UnterminatedFunction { address: usize, name: String },
+}
+
+impl VerifyError {
+    /// Address of the offending instruction
+    pub fn address(&self) -> usize {
+        match self {
+            VerifyError::JumpOutOfRange { address, .. }
+            | VerifyError::JumpAcrossFunction { address, .. }
+            | VerifyError::UnknownFunction { address, .. }
+            | VerifyError::DuplicateFunction { address, .. }
+            | VerifyError::MissingBegin { address, .. }
+            | VerifyError::UnexpectedBegin { address }
+            | VerifyError::UnmatchedEnd { address }
+            | VerifyError::UnterminatedFunction { address, .. } => *address,
+        }
+    }
+}
+
+/// A stack problem found by static analysis, located by instruction address
+#[derive(Error, Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StackWarning {
+    #[error("stack underflow: needs {needed} value(s) but the stack holds {depth}")]
+    Underflow { address: usize, depth: usize, needed: usize },
+
+    #[error("stack depth differs between paths: {expected} on one, {found} on another")]
+    DepthMismatch { address: usize, expected: isize, found: isize },
+}
+
+impl StackWarning {
+    /// Address of the offending instruction
+    pub fn address(&self) -> usize {
+        match self {
+            StackWarning::Underflow { address, .. }
+            | StackWarning::DepthMismatch { address, .. } => *address,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A macro invocation that produced the line a diagnostic points at
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Expansion {
+    /// Name of the expanded macro
+    pub name: String,
+    /// Included file holding the invocation, `None` for the main source
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    /// Line of the invocation
+    pub line: usize,
+    /// How many more times the last `cycle` frames, ending with this one,
+    /// repeat below it, as when macros invoke each other without end
+    #[serde(skip_serializing_if = "is_zero")]
+    pub repeated: usize,
+    /// Number of frames in the repeating group, 0 when nothing repeats
+    #[serde(skip_serializing_if = "is_zero")]
+    pub cycle: usize,
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+/// A problem found while assembling, located in the source.
+/// `line` and `column` are 1-based; `token` is the offending source text.
+/// `file` names the included file the line comes from, or is `None` for the
+/// main source. Inside a macro they point into the macro body, and `expansion`
+/// lists the invocations that led there, innermost first.
+#[derive(Error, Debug, Clone, PartialEq, Serialize)]
+pub struct AssemblerError {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    pub line: usize,
+    pub column: usize,
+    pub token: String,
+    pub severity: Severity,
+    pub message: String,
+    pub help: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub expansion: Vec<Expansion>,
+}
+
+impl fmt::Display for AssemblerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
+        }
+        write!(f, "{}:{}: {}: {}", self.line, self.column, self.severity, self.message)?;
+        for expansion in &self.expansion {
+            match &expansion.file {
+                Some(file) => write!(f, "\n    in macro `{}` invoked at {}:{}", expansion.name, file, expansion.line)?,
+                None => write!(f, "\n    in macro `{}` invoked at line {}", expansion.name, expansion.line)?,
+            }
+            match expansion.cycle {
+                0 => {}
+                1 => write!(f, "\n    … (repeated {} times)", expansion.repeated)?,
+                cycle => write!(f, "\n    … (last {} frames repeated {} times)", cycle, expansion.repeated)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Every diagnostic produced by a failed assembly, in source order
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct AssemblerErrors(pub Vec<AssemblerError>);
+
+impl AssemblerErrors {
+    /// Only the diagnostics with `Severity::Error`
+    pub fn errors(&self) -> impl Iterator<Item = &AssemblerError> {
+        self.0.iter().filter(|d| d.severity == Severity::Error)
+    }
+}
+
+impl fmt::Display for AssemblerErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, diagnostic) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for AssemblerErrors {}
\ No newline at end of file
@@ -1,2 +1,3 @@
+#[allow(clippy::module_inception)]
 pub mod error;
 pub use error::*;
\ No newline at end of file
@@ -1,12 +1,28 @@
 use std::collections::HashMap;
+use std::fmt;
 use serde::{Serialize, Deserialize};
+use crate::core::instruction::escape_string;
+use crate::core::value::Value;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum HeapValue {
-    Array(Vec<i64>),
+    Array(Vec<Value>),
     String(String),
 }
 
+/// Arrays print as `[1, 2]`, strings as quoted literals with escapes
+impl fmt::Display for HeapValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeapValue::Array(values) => {
+                let values: Vec<String> = values.iter().map(Value::to_string).collect();
+                write!(f, "[{}]", values.join(", "))
+            }
+            HeapValue::String(text) => write!(f, "\"{}\"", escape_string(text)),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HeapManager {
     heap: HashMap<usize, HeapValue>,
@@ -43,4 +59,36 @@ impl HeapManager {
     pub fn is_valid_address(&self, id: usize) -> bool {
         self.heap.contains_key(&id)
     }
+
+    /// Id the next allocation will get
+    pub(crate) fn next_id(&self) -> usize {
+        self.next_id
+    }
+
+    /// Put back an allocation as it was, or remove it if it did not exist
+    pub(crate) fn restore(&mut self, id: usize, value: Option<HeapValue>) {
+        match value {
+            Some(value) => self.heap.insert(id, value),
+            None => self.heap.remove(&id),
+        };
+    }
+
+    /// Undo every allocation made since `next_id` was current
+    pub(crate) fn rewind(&mut self, next_id: usize) {
+        self.heap.retain(|&id, _| id < next_id);
+        self.next_id = next_id;
+    }
+
+    /// Every live allocation, in allocation order
+    pub fn entries(&self) -> Vec<(usize, &HeapValue)> {
+        let mut entries: Vec<(usize, &HeapValue)> = self.heap.iter().map(|(&id, value)| (id, value)).collect();
+        entries.sort_by_key(|&(id, _)| id);
+        entries
+    }
+}
+
+impl Default for HeapManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
\ No newline at end of file
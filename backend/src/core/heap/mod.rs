@@ -1,2 +1,3 @@
+#[allow(clippy::module_inception)]
 pub mod heap;
 pub use heap::*;
\ No newline at end of file
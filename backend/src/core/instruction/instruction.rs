@@ -1,106 +1,180 @@
 use serde::{Serialize, Deserialize};
 use std::fmt;
+use crate::core::value::Value;
+use super::opcode::{
+    opcodes, operand_type, fixed, Category, OpcodeInfo, Operand, OperandKind, StackEffect,
+};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum Instruction {
+opcodes! {
     // Stack Operations
-    Push(i64),
-    Pop,
-    Dup,
-    Swap,
+    Push(value: Value) => "PUSH", Stack, fixed(0, 1), "Push a number, true, false or nil onto the stack";
+    Pop => "POP", Stack, fixed(1, 0), "Remove and discard the top value";
+    Dup => "DUP", Stack, fixed(1, 2), "Duplicate the top value";
+    Swap => "SWAP", Stack, fixed(2, 2), "Swap the top two values";
+    Over => "OVER", Stack, fixed(2, 3), "Copy the second value to the top (a b -- a b a)";
+    Rot => "ROT", Stack, fixed(3, 3), "Rotate the third value to the top (a b c -- b c a)";
+    RotBack => "-ROT", Stack, fixed(3, 3), "Rotate the top value below the next two (a b c -- c a b)";
+    Nip => "NIP", Stack, fixed(2, 1), "Drop the second value (a b -- b)";
+    Tuck => "TUCK", Stack, fixed(2, 3), "Copy the top value below the second (a b -- b a b)";
+    Pick(depth: Count) => "PICK", Stack, StackEffect::Counted, "Copy the value n slots below the top; PICK 0 is DUP";
+    Roll(depth: Count) => "ROLL", Stack, StackEffect::Counted, "Move the value n slots below the top to the top; ROLL 1 is SWAP";
+    TwoDup => "2DUP", Stack, fixed(2, 4), "Duplicate the top pair (a b -- a b a b)";
+    TwoDrop => "2DROP", Stack, fixed(2, 0), "Drop the top pair";
+    Depth => "DEPTH", Stack, fixed(0, 1), "Push the number of values on the stack";
 
     // Arithmetic
-    Add,
-    Sub,
-    Mul,
-    Div,
+    Add => "ADD", Arithmetic, fixed(2, 1), "Add the top two values";
+    Sub => "SUB", Arithmetic, fixed(2, 1), "Subtract the top value from the second";
+    Mul => "MUL", Arithmetic, fixed(2, 1), "Multiply the top two values";
+    Div => "DIV", Arithmetic, fixed(2, 1), "Divide the second value by the top, truncating toward zero";
+    Mod => "MOD", Arithmetic, fixed(2, 1), "Euclidean remainder of the second value by the top; never negative";
+    DivMod => "DIVMOD", Arithmetic, fixed(2, 2), "Push the Euclidean quotient and then the remainder";
+    Neg => "NEG", Arithmetic, fixed(1, 1), "Negate the top value";
+    Abs => "ABS", Arithmetic, fixed(1, 1), "Absolute value of the top value";
+    Min => "MIN", Arithmetic, fixed(2, 1), "Smaller of the top two values";
+    Max => "MAX", Arithmetic, fixed(2, 1), "Larger of the top two values";
+    Pow => "POW", Arithmetic, fixed(2, 1), "Raise the second value to the power of the top";
+
+    // Bitwise operations
+    BitAnd => "BAND", Bitwise, fixed(2, 1), "Bitwise and of the top two values";
+    BitOr => "BOR", Bitwise, fixed(2, 1), "Bitwise or of the top two values";
+    BitXor => "BXOR", Bitwise, fixed(2, 1), "Bitwise exclusive or of the top two values";
+    BitNot => "BNOT", Bitwise, fixed(1, 1), "Bitwise complement of the top value";
+    ShiftLeft => "SHL", Bitwise, fixed(2, 1), "Shift the second value left by the top value";
+    ShiftRight => "SHR", Bitwise, fixed(2, 1), "Logical shift right of the second value by the top value";
+    ShiftRightArithmetic => "SAR", Bitwise, fixed(2, 1), "Arithmetic shift right of the second value by the top value";
+
+    // Floating-point arithmetic
+    FAdd => "FADD", Float, fixed(2, 1), "Add the top two floats";
+    FSub => "FSUB", Float, fixed(2, 1), "Subtract the top float from the second";
+    FMul => "FMUL", Float, fixed(2, 1), "Multiply the top two floats";
+    FDiv => "FDIV", Float, fixed(2, 1), "Divide the second float by the top";
+    FLessThan => "FLT", Float, fixed(2, 1), "Float less than";
+    FLessEqual => "FLE", Float, fixed(2, 1), "Float less than or equal";
+    FGreaterThan => "FGT", Float, fixed(2, 1), "Float greater than";
+    FGreaterEqual => "FGE", Float, fixed(2, 1), "Float greater than or equal";
+    IntToFloat => "ITOF", Float, fixed(1, 1), "Convert an integer to a float";
+    FloatToInt => "FTOI", Float, fixed(1, 1), "Convert a float to an integer, truncating toward zero";
 
     // Memory
-    Load(String),
-    Store(String),
+    Load(name: Name) => "LOAD", Memory, fixed(0, 1), "Push the value of a global variable";
+    Store(name: Name) => "STORE", Memory, fixed(1, 0), "Pop the top value into a global variable";
 
     // Control Flow
-    Jump(usize),
-    JumpIf(usize),
-    JumpIfZero(usize),
-    JumpIfNotZero(usize),
+    Jump(target: Address) => "JMP", ControlFlow, fixed(0, 0), "Jump to a label";
+    JumpIf(target: Address) => "JMP_IF", ControlFlow, fixed(1, 0), "Jump to a label if the top value is true";
+    JumpIfZero(target: Address) => "JMPZ", ControlFlow, fixed(1, 0), "Jump to a label if the top value is zero or false";
+    JumpIfNotZero(target: Address) => "JMPNZ", ControlFlow, fixed(1, 0), "Jump to a label if the top value is non-zero or true";
 
     // Comparison operations
-    Equal,
-    NotEqual,
-    LessThan,
-    LessEqual,
-    GreaterThan,
-    GreaterEqual,
+    Equal => "EQ", Comparison, fixed(2, 1), "Test the top two values for equality";
+    NotEqual => "NE", Comparison, fixed(2, 1), "Test the top two values for inequality";
+    LessThan => "LT", Comparison, fixed(2, 1), "Second value less than the top";
+    LessEqual => "LE", Comparison, fixed(2, 1), "Second value less than or equal to the top";
+    GreaterThan => "GT", Comparison, fixed(2, 1), "Second value greater than the top";
+    GreaterEqual => "GE", Comparison, fixed(2, 1), "Second value greater than or equal to the top";
 
     // Boolean operations
-    And,
-    Or,
-    Not,
+    And => "AND", Logic, fixed(2, 1), "Logical and of the top two values";
+    Or => "OR", Logic, fixed(2, 1), "Logical or of the top two values";
+    Not => "NOT", Logic, fixed(1, 1), "Logical negation of the top value";
 
     // Function instructions
-    DefineFunction(String, usize),
-    BeginFunction,
-    EndFunction,
-    CreateLocal(String),
-    LoadLocal(String),
-    StoreLocal(String),
-    PushParam(usize),
-    Call(String),
-    Return,
+    DefineFunction(name: Name, params: Count) => "FUNC", Function, fixed(0, 0), "Define a function taking a number of arguments";
+    BeginFunction => "BEGIN", Function, fixed(0, 0), "Start of a function body";
+    EndFunction => "END", Function, fixed(0, 0), "End of a function body; returns implicitly";
+    CreateLocal(name: Name) => "LOCAL", Function, fixed(0, 0), "Declare a frame-local variable";
+    LoadLocal(name: Name) => "LOADL", Function, fixed(0, 1), "Push the value of a local variable";
+    StoreLocal(name: Name) => "STOREL", Function, fixed(1, 0), "Pop the top value into a local variable";
+    PushParam(index: Count) => "PARAM", Function, fixed(0, 1), "Push an argument of the current frame";
+    Call(name: Name) => "CALL", Function, StackEffect::Call, "Call a function, moving its arguments into a new frame";
+    Return => "RET", Function, fixed(0, 0), "Return to the caller";
 
     // Array operations
-    NewArray,
-    ArrayGet,
-    ArraySet,
-    ArrayLength,
-    FreeArray,
+    NewArray => "NEWARRAY", Array, fixed(1, 1), "Create an array of the size on top of the stack";
+    ArrayGet => "ARRAYGET", Array, fixed(2, 1), "Get the element at index (top) of array (second)";
+    ArraySet => "ARRAYSET", Array, fixed(3, 0), "Set array (third) at index (second) to value (top)";
+    ArrayLength => "ARRAYLEN", Array, fixed(1, 1), "Length of the array on top of the stack";
+    FreeArray => "FREEARR", Array, fixed(1, 0), "Free the array on top of the stack";
 
     // String operations
-    NewString(String),
-    StringConcat,
-    StringLength,
-    FreeString,
+    NewString(text: Text) => "NEWSTR", String, fixed(0, 1), "Create a string and push its reference";
+    StringConcat => "STRCAT", String, fixed(2, 1), "Concatenate the top two strings";
+    StringLength => "STRLEN", String, fixed(1, 1), "Length of the string on top of the stack";
+    FreeString => "FREESTR", String, fixed(1, 0), "Free the string on top of the stack";
 
     // I/O Operations
-    Print,
-    PrintChar,
-    PrintStr(String),
+    Print => "PRINT", IO, fixed(1, 0), "Print the top value";
+    PrintChar => "PRINTCHAR", IO, fixed(1, 0), "Print the top value as an ASCII character";
+    PrintStr(text: Text) => "PRINTSTR", IO, fixed(0, 0), "Print a string literal";
+
+    Halt => "HALT", ControlFlow, fixed(0, 0), "Stop program execution";
+}
+
+impl Instruction {
+    pub fn info(&self) -> &'static OpcodeInfo {
+        self.opcode().info()
+    }
+
+    /// Values popped and pushed by this instruction, or `None` for `CALL`,
+    /// whose effect depends on the function being called, and for a `PICK`
+    /// or `ROLL` depth too large to count
+    pub fn stack_effect(&self) -> Option<(usize, usize)> {
+        match (self.info().effect, self) {
+            (StackEffect::Fixed { pops, pushes }, _) => Some((pops, pushes)),
+            (StackEffect::Counted, Instruction::Pick(depth)) => Some((depth.checked_add(1)?, depth.checked_add(2)?)),
+            (StackEffect::Counted, Instruction::Roll(depth)) => depth.checked_add(1).map(|n| (n, n)),
+            _ => None,
+        }
+    }
+
+    /// Target of a jump instruction, i.e. its `Address` operand
+    pub fn jump_target(&self) -> Option<usize> {
+        self.operands().into_iter().find_map(|operand| match operand {
+            Operand::Address(address) => Some(address),
+            _ => None,
+        })
+    }
 
-    Halt,
+    /// The same instruction with its jump target replaced; other instructions
+    /// are returned unchanged
+    pub fn with_jump_target(&self, target: usize) -> Instruction {
+        let operands: Vec<Operand> = self.operands().into_iter()
+            .map(|operand| match operand {
+                Operand::Address(_) => Operand::Address(target),
+                operand => operand,
+            })
+            .collect();
+        Instruction::from_operands(self.opcode(), &operands)
+            .expect("operand kinds are unchanged")
+    }
 }
 
 impl fmt::Display for Instruction {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Instruction::Push(n) => write!(f, "PUSH {}", n),
-            Instruction::Pop => write!(f, "POP"),
-            Instruction::Dup => write!(f, "DUP"),
-            Instruction::Swap => write!(f, "SWAP"),
-            Instruction::Add => write!(f, "ADD"),
-            Instruction::Sub => write!(f, "SUB"),
-            Instruction::Mul => write!(f, "MUL"),
-            Instruction::Div => write!(f, "DIV"),
-            Instruction::Load(var) => write!(f, "LOAD {}", var),
-            Instruction::Store(var) => write!(f, "STORE {}", var),
-            Instruction::Jump(addr) => write!(f, "JMP {}", addr),
-            Instruction::JumpIf(addr) => write!(f, "JMP_IF {}", addr),
-            Instruction::JumpIfZero(addr) => write!(f, "JMPZ {}", addr),
-            Instruction::JumpIfNotZero(addr) => write!(f, "JMPNZ {}", addr),
-            Instruction::Print => write!(f, "PRINT"),
-            Instruction::PrintChar => write!(f, "PRINTCHAR"),
-            Instruction::PrintStr(s) => write!(f, "PRINTSTR \"{}\"", s),
-            Instruction::NewArray => write!(f, "NEWARRAY"),
-            Instruction::ArrayGet => write!(f, "ARRAYGET"),
-            Instruction::ArraySet => write!(f, "ARRAYSET"),
-            Instruction::ArrayLength => write!(f, "ARRAYLEN"),
-            Instruction::FreeArray => write!(f, "FREEARR"),
-            Instruction::NewString(s) => write!(f, "NEWSTR \"{}\"", s),
-            Instruction::StringConcat => write!(f, "STRCAT"),
-            Instruction::StringLength => write!(f, "STRLEN"),
-            Instruction::FreeString => write!(f, "FREESTR"),
-            Instruction::Halt => write!(f, "HALT"),
-            _ => write!(f, "{:?}", self),
+        write!(f, "{}", self.info().mnemonic)?;
+        for operand in self.operands() {
+            write!(f, " {}", operand)?;
+        }
+        Ok(())
+    }
+}
+
+/// Markdown reference of the whole instruction set, grouped by category
+pub fn instruction_reference() -> String {
+    let mut categories: Vec<Category> = Vec::new();
+    for info in OPCODES {
+        if !categories.contains(&info.category) {
+            categories.push(info.category);
         }
     }
-}
\ No newline at end of file
+
+    let mut reference = String::new();
+    for category in categories {
+        reference.push_str(&format!("\n### {}\n", category));
+        for info in OPCODES.iter().filter(|info| info.category == category) {
+            reference.push_str(&format!("- `{}` - {}\n", info.syntax(), info.description));
+        }
+    }
+    reference
+}
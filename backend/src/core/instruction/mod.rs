@@ -1,2 +1,5 @@
+#[allow(clippy::module_inception)]
 pub mod instruction;
-pub use instruction::*;
\ No newline at end of file
+pub mod opcode;
+pub use instruction::*;
+pub use opcode::*;
\ No newline at end of file
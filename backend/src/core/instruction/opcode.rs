@@ -0,0 +1,252 @@
+use serde::Serialize;
+use std::fmt;
+use crate::core::value::Value;
+
+/// Kind of operand an instruction takes, as written in assembly source
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OperandKind {
+    /// Literal value: `42`, `1.5`, `true`, `false` or `nil`
+    Value,
+    /// Variable or function name
+    Name,
+    /// Quoted string literal
+    Text,
+    /// Jump target, written as a label or an absolute instruction index
+    Address,
+    /// Non-negative count or index
+    Count,
+}
+
+impl OperandKind {
+    /// Placeholder used in syntax summaries such as `PICK <count>`
+    pub fn placeholder(self) -> &'static str {
+        match self {
+            OperandKind::Value => "value",
+            OperandKind::Name => "name",
+            OperandKind::Text => "string",
+            OperandKind::Address => "label",
+            OperandKind::Count => "count",
+        }
+    }
+}
+
+/// A resolved operand, either parsed from source or taken from an instruction
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Value(Value),
+    Name(String),
+    Text(String),
+    Address(usize),
+    Count(usize),
+}
+
+impl Operand {
+    pub fn kind(&self) -> OperandKind {
+        match self {
+            Operand::Value(_) => OperandKind::Value,
+            Operand::Name(_) => OperandKind::Name,
+            Operand::Text(_) => OperandKind::Text,
+            Operand::Address(_) => OperandKind::Address,
+            Operand::Count(_) => OperandKind::Count,
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Value(value) => write!(f, "{}", value),
+            Operand::Name(name) => write!(f, "{}", name),
+            Operand::Text(text) => write!(f, "\"{}\"", escape_string(text)),
+            Operand::Address(address) => write!(f, "{}", address),
+            Operand::Count(count) => write!(f, "{}", count),
+        }
+    }
+}
+
+/// Source form of a string operand, the inverse of the assembler's
+/// `string_literal`: quotes, backslashes and control characters are escaped,
+/// every other character is written as-is.
+pub fn escape_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            '\0' => escaped.push_str("\\0"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Net effect of an instruction on the operand stack
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StackEffect {
+    /// Pops `pops` values and then pushes `pushes`
+    Fixed { pops: usize, pushes: usize },
+    /// Depends on the count operand (`PICK`, `ROLL`)
+    Counted,
+    /// Depends on the called function's parameters and return values
+    Call,
+}
+
+pub const fn fixed(pops: usize, pushes: usize) -> StackEffect {
+    StackEffect::Fixed { pops, pushes }
+}
+
+/// Grouping used for documentation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Category {
+    Stack,
+    Arithmetic,
+    Bitwise,
+    Float,
+    Memory,
+    Comparison,
+    Logic,
+    ControlFlow,
+    Function,
+    Array,
+    String,
+    IO,
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Category::Stack => "Stack Operations",
+            Category::Arithmetic => "Arithmetic",
+            Category::Bitwise => "Bitwise Operations",
+            Category::Float => "Floating-Point",
+            Category::Memory => "Memory Operations",
+            Category::Comparison => "Comparison",
+            Category::Logic => "Boolean Logic",
+            Category::ControlFlow => "Control Flow",
+            Category::Function => "Functions",
+            Category::Array => "Array Operations",
+            Category::String => "String Operations",
+            Category::IO => "I/O Operations",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// One row of the opcode table
+#[derive(Debug, Serialize)]
+pub struct OpcodeInfo {
+    pub mnemonic: &'static str,
+    pub operands: &'static [OperandKind],
+    pub effect: StackEffect,
+    pub category: Category,
+    pub description: &'static str,
+}
+
+impl OpcodeInfo {
+    /// Syntax summary such as `FUNC <name> <count>`
+    pub fn syntax(&self) -> String {
+        let mut syntax = self.mnemonic.to_string();
+        for kind in self.operands {
+            syntax.push_str(&format!(" <{}>", kind.placeholder()));
+        }
+        syntax
+    }
+}
+
+macro_rules! operand_type {
+    (Value) => { Value };
+    (Name) => { String };
+    (Text) => { String };
+    (Address) => { usize };
+    (Count) => { usize };
+}
+
+/// Declares the instruction set. Each row produces an `Instruction` variant, an
+/// `Opcode` and an `OpcodeInfo`, so the assembler, `Display` and the instruction
+/// reference cannot drift apart.
+macro_rules! opcodes {
+    ($(
+        $variant:ident $(( $($field:ident: $kind:ident),+ ))? => $mnemonic:literal,
+            $category:ident, $effect:expr, $description:literal;
+    )*) => {
+        #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+        pub enum Instruction {
+            $( $variant $(( $(operand_type!($kind)),+ ))?, )*
+        }
+
+        /// Operand-free tag of an `Instruction`, indexing `OPCODES`
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum Opcode {
+            $( $variant, )*
+        }
+
+        /// The instruction set in declaration order
+        pub static OPCODES: &[OpcodeInfo] = &[
+            $(
+                OpcodeInfo {
+                    mnemonic: $mnemonic,
+                    operands: &[$($(OperandKind::$kind),+)?],
+                    effect: $effect,
+                    category: Category::$category,
+                    description: $description,
+                },
+            )*
+        ];
+
+        impl Opcode {
+            pub const ALL: &'static [Opcode] = &[$( Opcode::$variant, )*];
+
+            pub fn info(self) -> &'static OpcodeInfo {
+                &OPCODES[self as usize]
+            }
+
+            pub fn from_mnemonic(mnemonic: &str) -> Option<Opcode> {
+                match mnemonic {
+                    $( $mnemonic => Some(Opcode::$variant), )*
+                    _ => None,
+                }
+            }
+        }
+
+        impl Instruction {
+            pub fn opcode(&self) -> Opcode {
+                match self {
+                    $( Instruction::$variant { .. } => Opcode::$variant, )*
+                }
+            }
+
+            /// Operands in source order
+            pub fn operands(&self) -> Vec<Operand> {
+                match self {
+                    $(
+                        Instruction::$variant $(( $($field),+ ))? => {
+                            vec![$($(Operand::$kind($field.clone())),+)?]
+                        }
+                    )*
+                }
+            }
+
+            /// Build an instruction from its opcode and operands. Returns `None`
+            /// when the operands do not match the opcode's signature.
+            pub fn from_operands(opcode: Opcode, operands: &[Operand]) -> Option<Instruction> {
+                match (opcode, operands) {
+                    $(
+                        (Opcode::$variant, [$($(Operand::$kind($field)),+)?]) => {
+                            Some(Instruction::$variant $(( $($field.clone()),+ ))?)
+                        }
+                    )*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+pub(crate) use opcodes;
+pub(crate) use operand_type;
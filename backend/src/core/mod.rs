@@ -1,6 +1,15 @@
+pub mod analysis;
 pub mod assembler;
+pub mod bytecode;
+pub mod cfg;
+pub mod debugger;
+pub mod disassembler;
 pub mod error;
 pub mod heap;
 pub mod instruction;
+pub mod optimizer;
+pub mod repl;
 pub mod state;
+pub mod value;
+pub mod verifier;
 pub mod vm;
\ No newline at end of file
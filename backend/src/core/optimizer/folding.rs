@@ -0,0 +1,280 @@
+use std::collections::{HashMap, HashSet};
+use crate::core::cfg::{successor_addresses, ControlFlowGraph};
+use crate::core::instruction::{Category, Instruction};
+use crate::core::state::Function;
+use crate::core::value::Value;
+use crate::core::vm::VM;
+use super::{apply_edits, OptimizationReport};
+
+/// What is known about one stack slot
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Slot {
+    Value(Value),
+    /// An integer whose value is unknown
+    Int,
+    Unknown,
+}
+
+impl Slot {
+    pub(crate) fn is_int(&self) -> bool {
+        matches!(self, Slot::Int | Slot::Value(Value::Int(_)))
+    }
+
+    /// What holds on both of two paths
+    fn join(&self, other: &Slot) -> Slot {
+        if self == other {
+            self.clone()
+        } else if self.is_int() && other.is_int() {
+            Slot::Int
+        } else {
+            Slot::Unknown
+        }
+    }
+}
+
+/// Slots known to be on top of the stack, topmost last. The stack holds at
+/// least this many values; anything below them is unknown.
+pub(crate) type KnownStack = Vec<Slot>;
+
+/// Fold constant expressions, resolve conditional jumps on known conditions and
+/// delete code unreachable from the entry point, until nothing changes.
+///
+/// Expressions are evaluated by the VM itself in checked mode, so a fold only
+/// happens when the result is the same in every arithmetic mode. Anything that
+/// would raise an error at runtime, such as division by zero, is left in place.
+pub fn fold_constants(instructions: &[Instruction]) -> (Vec<Instruction>, OptimizationReport) {
+    let mut report = OptimizationReport { before: instructions.len(), ..Default::default() };
+    let mut program = instructions.to_vec();
+
+    loop {
+        let mut changed = false;
+        for pass in [fold_expressions, resolve_branches, remove_dead_code] {
+            if let Some(next) = pass(&program, &mut report) {
+                program = next;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    report.after = program.len();
+    (program, report)
+}
+
+/// Instructions whose result depends only on the values they pop
+fn is_pure(instruction: &Instruction) -> bool {
+    let category = instruction.info().category;
+    let pops = instruction.stack_effect().map_or(0, |(pops, _)| pops);
+    pops > 0 && matches!(
+        category,
+        Category::Stack | Category::Arithmetic | Category::Bitwise | Category::Float | Category::Comparison | Category::Logic
+    )
+}
+
+/// Run a pure instruction on constant inputs, or `None` if it fails
+fn evaluate(inputs: &[Value], instruction: &Instruction) -> Option<Vec<Value>> {
+    let mut program: Vec<Instruction> = inputs.iter().cloned().map(Instruction::Push).collect();
+    program.push(instruction.clone());
+    let steps = program.len();
+
+    let mut vm = VM::new(program);
+    for _ in 0..steps {
+        vm.step().ok()?;
+    }
+    let results = vm.get_state().stack.clone();
+    if results.iter().any(|value| matches!(value, Value::Ref(_))) {
+        return None;
+    }
+    Some(results)
+}
+
+/// `PUSH a; PUSH b; MUL` becomes `PUSH a*b`, when no jump lands inside the
+/// sequence and the result is no longer than the original
+fn fold_expressions(program: &[Instruction], report: &mut OptimizationReport) -> Option<Vec<Instruction>> {
+    let targets: HashSet<usize> = program.iter().filter_map(Instruction::jump_target).collect();
+    let mut edits: Vec<Vec<Instruction>> = program.iter().map(|i| vec![i.clone()]).collect();
+    let mut changed = false;
+    let mut free_from = 0;
+
+    for (address, instruction) in program.iter().enumerate() {
+        if !is_pure(instruction) {
+            continue;
+        }
+        let (pops, pushes) = instruction.stack_effect().expect("pure instructions have a fixed effect");
+        if address < pops || address - pops < free_from || pushes > pops + 1 {
+            continue;
+        }
+        let start = address - pops;
+        if (start + 1..=address).any(|a| targets.contains(&a)) {
+            continue;
+        }
+        let inputs: Option<Vec<Value>> = program[start..address].iter()
+            .map(|i| match i {
+                Instruction::Push(value) => Some(value.clone()),
+                _ => None,
+            })
+            .collect();
+        let Some(results) = inputs.and_then(|inputs| evaluate(&inputs, instruction)) else { continue };
+
+        edits[start] = results.into_iter().map(Instruction::Push).collect();
+        for edit in &mut edits[start + 1..=address] {
+            edit.clear();
+        }
+        report.record("constant-folding");
+        changed = true;
+        free_from = address + 1;
+    }
+
+    changed.then(|| apply_edits(program, edits))
+}
+
+/// Replace conditional jumps on a condition known at every path into them
+/// with `POP`, followed by `JMP` when the branch is always taken
+fn resolve_branches(program: &[Instruction], report: &mut OptimizationReport) -> Option<Vec<Instruction>> {
+    let states = propagate(program);
+    let mut edits: Vec<Vec<Instruction>> = program.iter().map(|i| vec![i.clone()]).collect();
+    let mut changed = false;
+
+    for (address, instruction) in program.iter().enumerate() {
+        let (target, jumps_when) = match instruction {
+            Instruction::JumpIfZero(target) => (*target, false),
+            Instruction::JumpIf(target) | Instruction::JumpIfNotZero(target) => (*target, true),
+            _ => continue,
+        };
+        let condition = match states[address].as_ref().and_then(|state| state.last()) {
+            Some(Slot::Value(value)) => value.is_truthy().ok(),
+            _ => None,
+        };
+        let Some(condition) = condition else { continue };
+
+        edits[address] = if condition == jumps_when {
+            vec![Instruction::Pop, Instruction::Jump(target)]
+        } else {
+            vec![Instruction::Pop]
+        };
+        report.record("constant-branch");
+        changed = true;
+    }
+
+    changed.then(|| apply_edits(program, edits))
+}
+
+/// Known stack before each instruction, `None` where never reached. Function
+/// bodies start from an unknown stack, and calls forget everything.
+pub(crate) fn propagate(program: &[Instruction]) -> Vec<Option<KnownStack>> {
+    let functions = Function::scan(program);
+    let mut states: Vec<Option<KnownStack>> = vec![None; program.len()];
+    let mut worklist = Vec::new();
+
+    let entries = std::iter::once(0).chain(functions.values().map(|f| f.address + 1));
+    for entry in entries.filter(|&entry| entry < program.len()) {
+        states[entry] = Some(Vec::new());
+        worklist.push(entry);
+    }
+
+    while let Some(address) = worklist.pop() {
+        let state = states[address].clone().expect("queued instructions have a state");
+        let instruction = &program[address];
+        let after = transfer(instruction, state);
+
+        for successor in successor_addresses(instruction, address, &functions) {
+            if successor >= program.len() {
+                continue;
+            }
+            let merged = match &states[successor] {
+                None => after.clone(),
+                Some(existing) => merge(existing, &after),
+            };
+            if states[successor].as_ref() != Some(&merged) {
+                states[successor] = Some(merged);
+                worklist.push(successor);
+            }
+        }
+    }
+
+    states
+}
+
+fn transfer(instruction: &Instruction, mut state: KnownStack) -> KnownStack {
+    if let Instruction::Push(value) = instruction {
+        state.push(Slot::Value(value.clone()));
+        return state;
+    }
+    let Some((pops, pushes)) = instruction.stack_effect() else { return Vec::new() };
+    if matches!(instruction, Instruction::Call(_)) || state.len() < pops {
+        return vec![Slot::Unknown; pushes];
+    }
+
+    let inputs = state.split_off(state.len() - pops);
+    let results = match inputs.iter().map(|slot| match slot {
+        Slot::Value(value) => Some(value.clone()),
+        _ => None,
+    }).collect::<Option<Vec<Value>>>() {
+        Some(values) if is_pure(instruction) => evaluate(&values, instruction),
+        _ => None,
+    };
+    match results {
+        Some(results) => state.extend(results.into_iter().map(Slot::Value)),
+        None => {
+            let slot = if yields_int(instruction, &inputs) { Slot::Int } else { Slot::Unknown };
+            state.extend(std::iter::repeat_n(slot, pushes));
+        }
+    }
+    state
+}
+
+/// Instructions that push only integers, given what is known of their inputs.
+/// Integer and bitwise arithmetic on integers stays integral in every mode.
+fn yields_int(instruction: &Instruction, inputs: &[Slot]) -> bool {
+    match instruction.info().category {
+        Category::Arithmetic | Category::Bitwise => inputs.iter().all(Slot::is_int),
+        _ => matches!(instruction,
+            Instruction::Depth | Instruction::FloatToInt | Instruction::ArrayLength | Instruction::StringLength),
+    }
+}
+
+/// What holds on both stacks, slot by slot from the top
+fn merge(a: &KnownStack, b: &KnownStack) -> KnownStack {
+    let mut common: KnownStack = a.iter().rev().zip(b.iter().rev())
+        .map(|(x, y)| x.join(y))
+        .collect();
+    common.reverse();
+    common
+}
+
+/// Delete instructions no path from the entry point reaches. The `FUNC`,
+/// `BEGIN` and `END` markers of functions that are called are kept, because
+/// the VM locates function bodies through them; a function that is never
+/// called is deleted from `FUNC` through `END` as a unit.
+fn remove_dead_code(program: &[Instruction], report: &mut OptimizationReport) -> Option<Vec<Instruction>> {
+    let cfg = ControlFlowGraph::build(program, &HashMap::new());
+    let functions = Function::scan(program);
+
+    let mut markers = HashSet::new();
+    let mut uncalled = HashSet::new();
+    for function in functions.values() {
+        if cfg.block_at(function.address + 1).is_some_and(|block| block.reachable) {
+            markers.extend([function.address, function.address + 1, function.end_address]);
+        } else {
+            uncalled.extend(function.address..=function.end_address);
+        }
+    }
+
+    let mut edits: Vec<Vec<Instruction>> = program.iter().map(|i| vec![i.clone()]).collect();
+    let mut removed = 0;
+    for (address, edit) in edits.iter_mut().enumerate() {
+        let reachable = cfg.block_at(address).is_some_and(|block| block.reachable);
+        if uncalled.contains(&address) || (!reachable && !markers.contains(&address)) {
+            edit.clear();
+            removed += 1;
+        }
+    }
+
+    if removed == 0 {
+        return None;
+    }
+    *report.rewrites.entry("dead-code").or_default() += removed;
+    Some(apply_edits(program, edits))
+}
@@ -0,0 +1,5 @@
+#[allow(clippy::module_inception)]
+pub mod optimizer;
+pub mod folding;
+pub use optimizer::*;
+pub use folding::*;
\ No newline at end of file
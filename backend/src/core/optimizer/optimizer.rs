@@ -0,0 +1,166 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use crate::core::instruction::Instruction;
+use crate::core::value::Value;
+use super::{fold_constants, propagate, KnownStack};
+
+/// What an optimization pass changed
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct OptimizationReport {
+    /// Instruction count before optimizing
+    pub before: usize,
+    /// Instruction count after optimizing
+    pub after: usize,
+    /// How many times each rewrite was applied
+    pub rewrites: BTreeMap<&'static str, usize>,
+}
+
+impl OptimizationReport {
+    /// Instructions removed by the pass
+    pub fn saved(&self) -> usize {
+        self.before.saturating_sub(self.after)
+    }
+
+    pub(crate) fn record(&mut self, rewrite: &'static str) {
+        *self.rewrites.entry(rewrite).or_default() += 1;
+    }
+}
+
+/// Run every optimization pass over a program until none of them finds
+/// anything more to do
+pub fn optimize(instructions: &[Instruction]) -> (Vec<Instruction>, OptimizationReport) {
+    let mut report = OptimizationReport { before: instructions.len(), ..Default::default() };
+    let mut program = instructions.to_vec();
+
+    loop {
+        let (folded, folding) = fold_constants(&program);
+        let (optimized, peepholes) = peephole(&folded);
+        let changed = optimized != program;
+        for (rewrite, count) in folding.rewrites.into_iter().chain(peepholes.rewrites) {
+            *report.rewrites.entry(rewrite).or_default() += count;
+        }
+        program = optimized;
+        if !changed {
+            break;
+        }
+    }
+
+    report.after = program.len();
+    (program, report)
+}
+
+/// Replace each instruction with the sequence in `edits` at its address (empty
+/// to delete it) and remap every jump. A jump to a deleted instruction lands
+/// on whatever now follows it. Jump targets inside `edits` are old addresses.
+pub(crate) fn apply_edits(program: &[Instruction], edits: Vec<Vec<Instruction>>) -> Vec<Instruction> {
+    let mut output = Vec::with_capacity(program.len());
+    let mut remap = Vec::with_capacity(program.len() + 1);
+    for replacement in edits {
+        remap.push(output.len());
+        output.extend(replacement);
+    }
+    remap.push(output.len());
+
+    let shift = program.len() as isize - output.len() as isize;
+    for instruction in output.iter_mut() {
+        if let Some(target) = instruction.jump_target() {
+            let target = match remap.get(target) {
+                Some(&mapped) => mapped,
+                None => (target as isize - shift) as usize,
+            };
+            *instruction = instruction.with_jump_target(target);
+        }
+    }
+    output
+}
+
+/// Rewrite short instruction sequences into cheaper equivalents until nothing
+/// changes, remapping jump targets after every deletion.
+///
+/// A pattern never spans a jump target, so control cannot enter it halfway.
+/// Rewrites that would hide a runtime error, such as `SWAP; SWAP` on a short
+/// stack or `PUSH 0; ADD` on a float, only apply where the folding pass's
+/// known stack shows the error cannot happen.
+pub fn peephole(instructions: &[Instruction]) -> (Vec<Instruction>, OptimizationReport) {
+    let mut report = OptimizationReport { before: instructions.len(), ..Default::default() };
+    let mut program = instructions.to_vec();
+
+    loop {
+        let threaded = thread_jumps(&mut program, &mut report);
+        let targets: HashSet<usize> = program.iter().filter_map(Instruction::jump_target).collect();
+        let states = propagate(&program);
+
+        let mut edits: Vec<Vec<Instruction>> = program.iter().map(|i| vec![i.clone()]).collect();
+        let mut address = 0;
+        while address < program.len() {
+            let next = program.get(address + 1).filter(|_| !targets.contains(&(address + 1)));
+
+            let rewritten = next.and_then(|next| rewrite_pair(&program[address], next, states[address].as_ref()));
+            if let Some((rewrite, replacement)) = rewritten {
+                report.record(rewrite);
+                edits[address] = replacement;
+                edits[address + 1].clear();
+                address += 2;
+                continue;
+            }
+            if program[address] == Instruction::Jump(address + 1) {
+                report.record("jump-to-next");
+                edits[address].clear();
+            }
+            address += 1;
+        }
+
+        let output = apply_edits(&program, edits);
+        let changed = output != program;
+        program = output;
+        if !changed && !threaded {
+            break;
+        }
+    }
+
+    report.after = program.len();
+    (program, report)
+}
+
+/// Replacement for two adjacent instructions, or `None` to keep them.
+/// `before` is the known stack ahead of `first`, `None` if it is never reached.
+fn rewrite_pair(first: &Instruction, second: &Instruction, before: Option<&KnownStack>)
+                -> Option<(&'static str, Vec<Instruction>)> {
+    use Instruction::*;
+    let depth = before.map_or(0, Vec::len);
+    let int_on_top = before.and_then(|stack| stack.last()).is_some_and(|slot| slot.is_int());
+    match (first, second) {
+        (Push(_), Pop) => Some(("push-pop", vec![])),
+        (Swap, Swap) if depth >= 2 => Some(("swap-swap", vec![])),
+        (Push(Value::Int(0)), Add | Sub) | (Push(Value::Int(1)), Mul | Div) if int_on_top => {
+            Some(("identity-arithmetic", vec![]))
+        }
+        (Not, JumpIfZero(target)) => Some(("negated-branch", vec![JumpIfNotZero(*target)])),
+        (Not, JumpIfNotZero(target) | JumpIf(target)) => Some(("negated-branch", vec![JumpIfZero(*target)])),
+        _ => None,
+    }
+}
+
+/// Point jumps that land on an unconditional `JMP` at its final destination
+fn thread_jumps(program: &mut [Instruction], report: &mut OptimizationReport) -> bool {
+    let mut changed = false;
+    for address in 0..program.len() {
+        let Some(target) = program[address].jump_target() else { continue };
+
+        let mut destination = target;
+        let mut seen = HashSet::from([destination]);
+        while let Some(Instruction::Jump(next)) = program.get(destination) {
+            if !seen.insert(*next) {
+                break;
+            }
+            destination = *next;
+        }
+
+        if destination != target {
+            program[address] = program[address].with_jump_target(destination);
+            report.record("jump-threading");
+            changed = true;
+        }
+    }
+    changed
+}
@@ -0,0 +1,3 @@
+#[allow(clippy::module_inception)]
+pub mod repl;
+pub use repl::*;
\ No newline at end of file
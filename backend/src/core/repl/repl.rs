@@ -0,0 +1,273 @@
+use std::collections::{BTreeMap, HashMap};
+use crate::core::assembler::{directive, Assembler, FileResolver};
+use crate::core::error::VerifyError;
+use crate::core::heap::HeapValue;
+use crate::core::instruction::{Instruction, Operand};
+use crate::core::state::{DataItem, StopReason};
+use crate::core::value::Value;
+use crate::core::verifier;
+use crate::core::vm::VM;
+
+/// Instructions a single input may execute before the REPL stops it
+pub const STEP_LIMIT: usize = 100_000;
+
+const HELP: &str = "\
+Type assembly to run it immediately. Labels, constants, macros and functions
+from earlier lines stay available.
+  :stack        show the stack
+  :memory       show every global
+  :heap         show every heap allocation
+  :reset        start over with an empty VM
+  :load FILE    run the lines of FILE
+  :save FILE    write every accepted line to FILE
+  :help         show this help
+  :quit         leave";
+
+/// Interactive session around one long-lived `VM`.
+///
+/// Each input is assembled after everything accepted so far, and only the
+/// instructions it adds are executed. If the accepted part no longer assembles
+/// to what the VM already holds, as when an `.include`d file has changed, the
+/// input is rejected. Input that leaves a `.macro` or a
+/// function open is held back until the definition is complete.
+pub struct Repl {
+    vm: VM,
+    /// Every accepted input, in order
+    source: String,
+    /// Input waiting for the end of a macro or function
+    pending: String,
+    /// `.data` items already loaded into the VM
+    data: Vec<DataItem>,
+    finished: bool,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Repl {
+            vm: VM::new(Vec::new()),
+            source: String::new(),
+            pending: String::new(),
+            data: Vec::new(),
+            finished: false,
+        }
+    }
+
+    /// `"> "`, or `". "` while a definition is still open
+    pub fn prompt(&self) -> &'static str {
+        if self.pending.is_empty() { "> " } else { ". " }
+    }
+
+    /// Whether `:quit` has been entered
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Every accepted input, as `:save` writes it
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn vm(&self) -> &VM {
+        &self.vm
+    }
+
+    /// Handle one line of input and return the text to show for it
+    pub fn eval(&mut self, line: &str) -> String {
+        if self.pending.is_empty() {
+            if let Some(command) = line.trim().strip_prefix(':') {
+                return self.command(command);
+            }
+        }
+        self.pending.push_str(line);
+        self.pending.push('\n');
+        self.execute()
+    }
+
+    fn command(&mut self, command: &str) -> String {
+        let (name, argument) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+        let argument = argument.trim();
+        match name {
+            "stack" => describe_stack(&self.vm.get_state().stack),
+            "memory" => {
+                let memory: BTreeMap<_, _> = self.vm.get_memory().iter().collect();
+                if memory.is_empty() {
+                    return "memory: (empty)".to_string();
+                }
+                memory.iter().map(|(name, value)| format!("{} = {}", name, value)).collect::<Vec<_>>().join("\n")
+            }
+            "heap" => {
+                let entries = self.vm.get_state().heap.entries();
+                if entries.is_empty() {
+                    return "heap: (empty)".to_string();
+                }
+                entries.iter().map(|(id, value)| format!("&{} = {}", id, value)).collect::<Vec<_>>().join("\n")
+            }
+            "reset" => {
+                *self = Repl::new();
+                "reset".to_string()
+            }
+            "load" if !argument.is_empty() => match std::fs::read_to_string(argument) {
+                Ok(text) => {
+                    self.pending = text;
+                    if !self.pending.ends_with('\n') {
+                        self.pending.push('\n');
+                    }
+                    self.execute()
+                }
+                Err(e) => format!("error: cannot read {}: {}", argument, e),
+            },
+            "save" if !argument.is_empty() => match std::fs::write(argument, &self.source) {
+                Ok(()) => format!("saved {} line(s) to {}", self.source.lines().count(), argument),
+                Err(e) => format!("error: cannot write {}: {}", argument, e),
+            },
+            "load" | "save" => format!("usage: :{} FILE", name),
+            "help" => HELP.to_string(),
+            "quit" | "q" => {
+                self.finished = true;
+                String::new()
+            }
+            _ => format!("unknown command :{}; try :help", name),
+        }
+    }
+
+    /// Assemble the pending input after the accepted source and run what it adds
+    fn execute(&mut self) -> String {
+        if has_open_macro(&self.pending) {
+            return String::new();
+        }
+
+        let program = format!("{}{}", self.source, self.pending);
+        let mut assembler = Assembler::new().with_resolver(FileResolver::new("."));
+        let instructions = match assembler.assemble(&program) {
+            Ok(instructions) => instructions,
+            Err(errors) => {
+                self.pending.clear();
+                return errors.errors().map(|error| match &error.help {
+                    Some(help) => format!("error: {}\n  help: {}", error.message, help),
+                    None => format!("error: {}", error.message),
+                }).collect::<Vec<_>>().join("\n");
+            }
+        };
+
+        let report = verifier::verify(&instructions);
+        if report.errors.iter().any(|e| matches!(e, VerifyError::UnterminatedFunction { .. })) {
+            return String::new();
+        }
+        self.pending.clear();
+        if !report.is_ok() {
+            return report.errors.iter().map(|e| format!("error: {}", e)).collect::<Vec<_>>().join("\n");
+        }
+
+        let loaded = self.vm.get_state().instructions();
+        let (start, data_start) = (loaded.len(), self.data.len());
+        let unchanged = instructions.get(..start).is_some_and(|earlier| same_all(earlier, loaded, same_instruction))
+            && assembler.data().get(..data_start).is_some_and(|earlier| same_all(earlier, &self.data, same_data));
+        if !unchanged {
+            return "error: earlier input no longer assembles the same way; did an included file change?\n  help: :reset to start over".to_string();
+        }
+        self.source = program;
+        let memory_before = self.vm.get_memory().clone();
+        let heap_before: HashMap<usize, HeapValue> = self.vm.get_state().heap.entries().into_iter()
+            .map(|(id, value)| (id, value.clone()))
+            .collect();
+
+        self.vm.load_data(&assembler.data()[data_start..]);
+        self.data = assembler.data().to_vec();
+        self.vm.extend(instructions[start..].to_vec());
+        let stopped = self.run();
+
+        let mut lines = Vec::new();
+        let output = self.vm.take_output().concat();
+        if !output.is_empty() {
+            lines.push(output.trim_end_matches('\n').to_string());
+        }
+        lines.extend(stopped);
+        lines.push(describe_stack(&self.vm.get_state().stack));
+
+        let mut changed: Vec<(&String, &Value)> = self.vm.get_memory().iter()
+            .filter(|(name, value)| !memory_before.get(*name).is_some_and(|before| same_value(before, value)))
+            .collect();
+        changed.sort_by_key(|(name, _)| name.as_str());
+        lines.extend(changed.iter().map(|(name, value)| format!("{} = {}", name, value)));
+
+        let heap = self.vm.get_state().heap.entries();
+        for (id, value) in &heap {
+            if !heap_before.get(id).is_some_and(|before| same_heap_value(before, value)) {
+                lines.push(format!("&{} = {}", id, value));
+            }
+        }
+        let mut freed: Vec<usize> = heap_before.keys()
+            .filter(|id| !heap.iter().any(|(live, _)| live == *id))
+            .copied()
+            .collect();
+        freed.sort();
+        lines.extend(freed.iter().map(|id| format!("&{} freed", id)));
+
+        lines.join("\n")
+    }
+
+    /// Run until the program ends, halts or fails. Returns why it stopped
+    /// early, if it did.
+    fn run(&mut self) -> Option<String> {
+        match self.vm.run_with_limit(STEP_LIMIT) {
+            StopReason::Error { error } => Some(format!("error: {}", error)),
+            StopReason::StepLimit => Some(format!("stopped after {} steps", STEP_LIMIT)),
+            _ => None,
+        }
+    }
+}
+
+// Programs and state are compared bit for bit, since NaN never equals itself
+
+fn same_all<T>(a: &[T], b: &[T], same: fn(&T, &T) -> bool) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same(a, b))
+}
+
+fn same_value(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Float(a), Value::Float(b)) => a.to_bits() == b.to_bits(),
+        _ => a == b,
+    }
+}
+
+fn same_instruction(a: &Instruction, b: &Instruction) -> bool {
+    a.opcode() == b.opcode() && same_all(&a.operands(), &b.operands(), |a, b| match (a, b) {
+        (Operand::Value(a), Operand::Value(b)) => same_value(a, b),
+        _ => a == b,
+    })
+}
+
+fn same_heap_value(a: &HeapValue, b: &HeapValue) -> bool {
+    match (a, b) {
+        (HeapValue::Array(a), HeapValue::Array(b)) => same_all(a, b, same_value),
+        _ => a == b,
+    }
+}
+
+fn same_data(a: &DataItem, b: &DataItem) -> bool {
+    a.name == b.name && same_heap_value(&a.value, &b.value)
+}
+
+/// Whether `text` opens a `.macro` that it does not close
+fn has_open_macro(text: &str) -> bool {
+    let mut open = false;
+    for line in text.lines() {
+        match directive(line) {
+            Some((".macro", _, _)) => open = true,
+            Some((".endm", _, _)) => open = false,
+            _ => {}
+        }
+    }
+    open
+}
+
+fn describe_stack(stack: &[Value]) -> String {
+    let values: Vec<String> = stack.iter().map(Value::to_string).collect();
+    format!("stack: [{}]", values.join(", "))
+}
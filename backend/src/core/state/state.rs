@@ -1,20 +1,93 @@
 use std::collections::HashMap;
-use crate::core::heap::HeapManager; // Remove `HeapValue` if unused
+use std::fmt;
+use serde::{Serialize, Serializer, Deserialize};
+use crate::core::error::VMError;
+use crate::core::heap::{HeapManager, HeapValue};
+use crate::core::instruction::Instruction;
+use crate::core::value::Value;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct StackFrame {
     pub return_address: usize,
-    pub local_vars: HashMap<String, i64>,
+    pub params: Vec<Value>,
+    pub local_vars: HashMap<String, Value>,
 }
 
 #[derive(Debug)]
 pub struct Function {
     pub name: String,
     pub address: usize,
+    pub end_address: usize,
     pub param_count: usize,
     pub local_vars: Vec<String>,
 }
 
+impl Function {
+    /// Find every `DefineFunction` with a matching `EndFunction`, keyed by name.
+    /// Unterminated definitions are left out.
+    pub fn scan(instructions: &[Instruction]) -> HashMap<String, Function> {
+        let mut functions = HashMap::new();
+
+        for (address, instruction) in instructions.iter().enumerate() {
+            if let Instruction::DefineFunction(name, param_count) = instruction {
+                let mut depth = 0;
+                let mut end_address = None;
+                let mut local_vars = Vec::new();
+
+                for (offset, body) in instructions[address + 1..].iter().enumerate() {
+                    match body {
+                        Instruction::DefineFunction(..) => depth += 1,
+                        Instruction::EndFunction if depth == 0 => {
+                            end_address = Some(address + 1 + offset);
+                            break;
+                        }
+                        Instruction::EndFunction => depth -= 1,
+                        Instruction::CreateLocal(local) if depth == 0 => {
+                            local_vars.push(local.clone());
+                        }
+                        _ => {}
+                    }
+                }
+
+                if let Some(end_address) = end_address {
+                    functions.insert(name.clone(), Function {
+                        name: name.clone(),
+                        address,
+                        end_address,
+                        param_count: *param_count,
+                        local_vars,
+                    });
+                }
+            }
+        }
+
+        functions
+    }
+}
+
+/// A named string or array from a `.data` section. The VM allocates it on the
+/// heap before the program runs and binds its reference to a global.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataItem {
+    pub name: String,
+    pub value: HeapValue,
+}
+
+/// How much execution history `VM::enable_history` keeps for stepping back.
+/// The undo log holds at most `checkpoint_interval` instructions; older steps
+/// are rebuilt by replaying from one of the last `max_checkpoints` snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoryOptions {
+    pub checkpoint_interval: usize,
+    pub max_checkpoints: usize,
+}
+
+impl Default for HistoryOptions {
+    fn default() -> Self {
+        HistoryOptions { checkpoint_interval: 1024, max_checkpoints: 64 }
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct DebugOptions {
     pub show_stack: bool,
@@ -23,14 +96,101 @@ pub struct DebugOptions {
     pub show_instructions: bool,
 }
 
+/// How integer arithmetic behaves when a result does not fit in an `i64`.
+/// The mode is explicit so programs behave the same in debug and release builds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArithmeticMode {
+    /// Overflow raises `VMError::ArithmeticOverflow`
+    #[default]
+    Checked,
+    /// Results wrap around in two's complement
+    Wrapping,
+    /// Results clamp to `i64::MIN` / `i64::MAX`
+    Saturating,
+}
+
+impl ArithmeticMode {
+    /// Pick the result for this mode; `None` means a checked overflow
+    pub fn apply(self, checked: Option<i64>, wrapping: i64, saturating: i64) -> Option<i64> {
+        match self {
+            ArithmeticMode::Checked => checked,
+            ArithmeticMode::Wrapping => Some(wrapping),
+            ArithmeticMode::Saturating => Some(saturating),
+        }
+    }
+}
+
+/// Something a watchpoint observes between instructions
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Watch {
+    /// A global in `memory` being created, changed or removed
+    Memory(String),
+    /// A heap id being allocated, mutated or freed
+    Heap(usize),
+    /// The stack growing to at least this many values
+    StackDepth(usize),
+}
+
+/// What a watchpoint saw change
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum WatchEvent {
+    Memory { name: String, old: Option<Value>, new: Option<Value> },
+    HeapAllocated { id: usize, value: HeapValue },
+    HeapChanged { id: usize, old: HeapValue, new: HeapValue },
+    HeapFreed { id: usize, old: HeapValue },
+    StackDepth { depth: usize },
+}
+
+impl fmt::Display for WatchEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let describe = |value: &Option<Value>| value.as_ref().map_or("unset".to_string(), Value::to_string);
+        match self {
+            WatchEvent::Memory { name, old, new } => write!(f, "{}: {} -> {}", name, describe(old), describe(new)),
+            WatchEvent::HeapAllocated { id, value } => write!(f, "&{} allocated: {}", id, value),
+            WatchEvent::HeapChanged { id, old, new } => write!(f, "&{}: {} -> {}", id, old, new),
+            WatchEvent::HeapFreed { id, old } => write!(f, "&{} freed: {}", id, old),
+            WatchEvent::StackDepth { depth } => write!(f, "stack depth reached {}", depth),
+        }
+    }
+}
+
+/// Why `VM::run_until_stop` or `VM::step_checked` gave control back
+#[derive(Debug, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum StopReason {
+    /// About to execute the instruction at a breakpoint
+    Breakpoint { address: usize },
+    /// The instruction at `address` triggered watchpoint `id`
+    Watch { id: usize, address: usize, event: WatchEvent },
+    /// The program executed `HALT` or ran off the end
+    Halted,
+    /// The step budget of `VM::run_with_limit` ran out
+    StepLimit,
+    /// `VM::reverse_continue` reached the oldest state it still remembers
+    HistoryStart,
+    /// An instruction failed; the program counter is left on it
+    Error {
+        #[serde(serialize_with = "serialize_error")]
+        error: VMError,
+    },
+}
+
+fn serialize_error<S: Serializer>(error: &VMError, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(error)
+}
+
 #[derive(Debug)]
 pub struct VMState {
-    pub stack: Vec<i64>,
-    pub memory: HashMap<String, i64>,
+    pub stack: Vec<Value>,
+    pub memory: HashMap<String, Value>,
     pub program_counter: usize,
     pub call_stack: Vec<StackFrame>,
     pub functions: HashMap<String, Function>,
     pub heap: HeapManager,
+    pub arithmetic_mode: ArithmeticMode,
     instructions: Vec<crate::core::instruction::Instruction>, // Private field
 }
 
@@ -43,6 +203,7 @@ impl VMState {
             call_stack: Vec::new(),
             functions: HashMap::new(),
             heap: HeapManager::new(),
+            arithmetic_mode: ArithmeticMode::default(),
             instructions,
         }
     }
@@ -50,4 +211,9 @@ impl VMState {
     pub fn instructions(&self) -> &Vec<crate::core::instruction::Instruction> {
         &self.instructions
     }
+
+    /// Add instructions to the end of the program
+    pub fn extend_instructions(&mut self, instructions: Vec<Instruction>) {
+        self.instructions.extend(instructions);
+    }
 }
\ No newline at end of file
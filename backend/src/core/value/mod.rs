@@ -0,0 +1,3 @@
+#[allow(clippy::module_inception)]
+pub mod value;
+pub use value::*;
\ No newline at end of file
@@ -0,0 +1,97 @@
+use serde::{Serialize, Deserialize};
+use std::fmt;
+use crate::core::error::VMError;
+
+/// A tagged runtime value as held on the stack, in memory and inside arrays
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    /// Handle of a live allocation in the `HeapManager`
+    Ref(usize),
+    Nil,
+}
+
+impl Value {
+    /// Name of the value's type as used in `VMError::TypeError`
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Bool(_) => "bool",
+            Value::Ref(_) => "ref",
+            Value::Nil => "nil",
+        }
+    }
+
+    pub fn as_int(&self) -> Result<i64, VMError> {
+        match self {
+            Value::Int(n) => Ok(*n),
+            other => Err(other.type_error("int")),
+        }
+    }
+
+    pub fn as_float(&self) -> Result<f64, VMError> {
+        match self {
+            Value::Float(x) => Ok(*x),
+            other => Err(other.type_error("float")),
+        }
+    }
+
+    pub fn as_heap_ref(&self) -> Result<usize, VMError> {
+        match self {
+            Value::Ref(id) => Ok(*id),
+            other => Err(other.type_error("ref")),
+        }
+    }
+
+    /// Truthiness used by conditional jumps and logical operators.
+    /// Integers follow the C convention of zero being false.
+    pub fn is_truthy(&self) -> Result<bool, VMError> {
+        match self {
+            Value::Bool(b) => Ok(*b),
+            Value::Int(n) => Ok(*n != 0),
+            other => Err(other.type_error("bool")),
+        }
+    }
+
+    pub fn type_error(&self, expected: &str) -> VMError {
+        VMError::TypeError(expected.to_string(), self.type_name().to_string())
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Self {
+        Value::Int(n)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(x: f64) -> Self {
+        Value::Float(x)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            // Debug formatting keeps a decimal point or exponent ("3.0", "1e20"),
+            // so floats never read back as integers; NaN is spelled like the
+            // assembler's `nan` literal
+            Value::Float(x) if x.is_nan() => write!(f, "nan"),
+            Value::Float(x) => write!(f, "{:?}", x),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Ref(id) => write!(f, "&{}", id),
+            Value::Nil => write!(f, "nil"),
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,3 @@
+#[allow(clippy::module_inception)]
+pub mod verifier;
+pub use verifier::*;
\ No newline at end of file
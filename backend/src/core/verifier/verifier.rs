@@ -0,0 +1,108 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use crate::core::error::VerifyError;
+use crate::core::instruction::Instruction;
+
+/// Result of verifying a program, listing every problem found in address order
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct VerificationReport {
+    pub errors: Vec<VerifyError>,
+}
+
+impl VerificationReport {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// `Ok` when the program passed, otherwise the report itself
+    pub fn into_result(self) -> Result<(), VerificationReport> {
+        if self.is_ok() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl fmt::Display for VerificationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, error) in self.errors.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for VerificationReport {}
+
+/// Check a program before running it: every jump must land inside the
+/// program and inside the same function body (or top level) it starts in,
+/// every `CALL` must name a defined function, and every `FUNC` must be
+/// followed by `BEGIN` and closed by a matching `END`.
+pub fn verify(instructions: &[Instruction]) -> VerificationReport {
+    let mut errors = Vec::new();
+    let mut functions: HashMap<&str, usize> = HashMap::new();
+    let mut open: Vec<(usize, &str)> = Vec::new();
+    // Address of the `FUNC` whose body holds each instruction, `None` at top level
+    let mut owners: Vec<Option<usize>> = Vec::with_capacity(instructions.len());
+
+    for (address, instruction) in instructions.iter().enumerate() {
+        owners.push(open.last().map(|&(start, _)| start));
+
+        match instruction {
+            Instruction::DefineFunction(name, _) => {
+                if let Some(&first) = functions.get(name.as_str()) {
+                    errors.push(VerifyError::DuplicateFunction { address, name: name.clone(), first });
+                } else {
+                    functions.insert(name, address);
+                }
+                if !matches!(instructions.get(address + 1), Some(Instruction::BeginFunction)) {
+                    errors.push(VerifyError::MissingBegin { address, name: name.clone() });
+                }
+                open.push((address, name));
+            }
+            Instruction::BeginFunction => {
+                let follows_define = address > 0
+                    && matches!(instructions[address - 1], Instruction::DefineFunction(..));
+                if !follows_define {
+                    errors.push(VerifyError::UnexpectedBegin { address });
+                }
+            }
+            Instruction::EndFunction => match open.pop() {
+                Some(_) => {}
+                None => errors.push(VerifyError::UnmatchedEnd { address }),
+            },
+            _ => {}
+        }
+    }
+
+    for (address, name) in open {
+        errors.push(VerifyError::UnterminatedFunction { address, name: name.to_string() });
+    }
+
+    // Functions are registered up front, so calls may precede definitions,
+    // and owners are known for every address, so jumps may go forward
+    for (address, instruction) in instructions.iter().enumerate() {
+        if let Some(target) = instruction.jump_target() {
+            match owners.get(target) {
+                None => errors.push(VerifyError::JumpOutOfRange { address, target, len: instructions.len() }),
+                Some(owner) if *owner != owners[address] => {
+                    errors.push(VerifyError::JumpAcrossFunction { address, target });
+                }
+                Some(_) => {}
+            }
+        }
+        if let Instruction::Call(name) = instruction {
+            if !functions.contains_key(name.as_str()) {
+                errors.push(VerifyError::UnknownFunction { address, name: name.clone() });
+            }
+        }
+    }
+
+    errors.sort_by_key(VerifyError::address);
+    VerificationReport { errors }
+}
@@ -0,0 +1,148 @@
+use std::collections::{HashMap, VecDeque};
+use crate::core::heap::{HeapManager, HeapValue};
+use crate::core::state::{HistoryOptions, StackFrame, VMState};
+use crate::core::value::Value;
+
+/// Everything one instruction changed, with the values it overwrote
+#[derive(Debug, Default)]
+pub(crate) struct UndoEntry {
+    pub program_counter: usize,
+    /// Stack length once the instruction's operands are popped
+    pub stack_base: usize,
+    /// The operands it popped, bottom first
+    pub popped: Vec<Value>,
+    /// Globals it stored to and their previous values, in order
+    pub memory: Vec<(String, Option<Value>)>,
+    /// Heap objects it mutated or freed and their previous values, in order
+    pub heap: Vec<(usize, Option<HeapValue>)>,
+    /// Allocations from this id on were made by the instruction
+    pub heap_next_id: usize,
+    /// Call stack length and top frame, for instructions that touch frames
+    pub frames: Option<(usize, Option<StackFrame>)>,
+}
+
+impl UndoEntry {
+    /// Restore `state` to how it was before the instruction ran
+    pub fn undo(self, state: &mut VMState) {
+        state.program_counter = self.program_counter;
+        state.stack.truncate(self.stack_base);
+        state.stack.extend(self.popped);
+        for (name, value) in self.memory.into_iter().rev() {
+            match value {
+                Some(value) => state.memory.insert(name, value),
+                None => state.memory.remove(&name),
+            };
+        }
+        for (id, value) in self.heap.into_iter().rev() {
+            state.heap.restore(id, value);
+        }
+        state.heap.rewind(self.heap_next_id);
+        if let Some((depth, top)) = self.frames {
+            state.call_stack.truncate(depth - usize::from(top.is_some()));
+            state.call_stack.extend(top);
+        }
+    }
+}
+
+/// Full copy of the mutable parts of a `VMState`
+#[derive(Debug, Clone)]
+pub(crate) struct Snapshot {
+    stack: Vec<Value>,
+    memory: HashMap<String, Value>,
+    heap: HeapManager,
+    call_stack: Vec<StackFrame>,
+    program_counter: usize,
+}
+
+impl Snapshot {
+    pub fn take(state: &VMState) -> Self {
+        Snapshot {
+            stack: state.stack.clone(),
+            memory: state.memory.clone(),
+            heap: state.heap.clone(),
+            call_stack: state.call_stack.clone(),
+            program_counter: state.program_counter,
+        }
+    }
+
+    pub fn restore(&self, state: &mut VMState) {
+        state.stack = self.stack.clone();
+        state.memory = self.memory.clone();
+        state.heap = self.heap.clone();
+        state.call_stack = self.call_stack.clone();
+        state.program_counter = self.program_counter;
+    }
+}
+
+/// Recorded execution: checkpoints every `checkpoint_interval` instructions
+/// and an undo log for the instructions since the latest one
+#[derive(Debug)]
+pub(crate) struct History {
+    options: HistoryOptions,
+    /// Instructions executed since recording began
+    pub position: usize,
+    /// Snapshots with the position they were taken at, oldest first
+    checkpoints: VecDeque<(usize, Snapshot)>,
+    /// One entry per instruction since the latest checkpoint
+    undo: Vec<UndoEntry>,
+}
+
+impl History {
+    pub fn new(options: HistoryOptions, state: &VMState) -> Self {
+        let mut checkpoints = VecDeque::new();
+        checkpoints.push_back((0, Snapshot::take(state)));
+        History {
+            options: HistoryOptions {
+                checkpoint_interval: options.checkpoint_interval.max(1),
+                max_checkpoints: options.max_checkpoints.max(1),
+            },
+            position: 0,
+            checkpoints,
+            undo: Vec::new(),
+        }
+    }
+
+    /// Called before each instruction: start a new segment once the undo log
+    /// is full, forgetting the oldest checkpoint beyond the limit
+    pub fn prepare(&mut self, state: &VMState) {
+        if self.undo.len() >= self.options.checkpoint_interval {
+            self.undo.clear();
+            self.checkpoints.push_back((self.position, Snapshot::take(state)));
+            if self.checkpoints.len() > self.options.max_checkpoints {
+                self.checkpoints.pop_front();
+            }
+        }
+    }
+
+    pub fn record(&mut self, entry: UndoEntry) {
+        self.undo.push(entry);
+        self.position += 1;
+    }
+
+    /// Position of the oldest state that can still be reached
+    pub fn start(&self) -> usize {
+        self.checkpoints.front().map_or(self.position, |(position, _)| *position)
+    }
+
+    /// Undo the latest instruction if it is still in the undo log
+    pub fn pop(&mut self) -> Option<UndoEntry> {
+        let entry = self.undo.pop()?;
+        self.position -= 1;
+        Some(entry)
+    }
+
+    /// With the undo log empty, drop the latest checkpoint and restore the one
+    /// before it. Returns how many instructions to replay to get back to the
+    /// position before the current one.
+    pub fn rewind_segment(&mut self, state: &mut VMState) -> Option<usize> {
+        if !self.undo.is_empty() || self.checkpoints.len() < 2 {
+            return None;
+        }
+        self.checkpoints.pop_back();
+        let (position, snapshot) = self.checkpoints.back()?;
+        snapshot.restore(state);
+        let replay = self.position - 1 - position;
+        self.position = *position;
+        Some(replay)
+    }
+}
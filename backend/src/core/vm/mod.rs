@@ -1,2 +1,4 @@
+#[allow(clippy::module_inception)]
 pub mod vm;
+mod history;
 pub use vm::*;
\ No newline at end of file
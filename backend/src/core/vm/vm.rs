@@ -1,21 +1,75 @@
-use crate::core::instruction::Instruction;
+use crate::core::instruction::{Category, Instruction};
 use crate::core::error::VMError;
-use crate::core::state::{VMState, DebugOptions};
+use crate::core::state::{
+    VMState, DebugOptions, Function, StackFrame, ArithmeticMode, DataItem, HistoryOptions, StopReason, Watch,
+    WatchEvent,
+};
+use crate::core::vm::history::{History, UndoEntry};
 use crate::core::heap::HeapValue;
-use std::collections::HashMap;
+use crate::core::value::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 pub struct VM {
     state: VMState,
     debug_options: DebugOptions,
     output_buffer: Vec<String>,
+    breakpoints: BTreeSet<usize>,
+    watches: BTreeMap<usize, Watch>,
+    next_watch: usize,
+    /// Breakpoint address last reported, so resuming executes its instruction
+    paused_at: Option<usize>,
+    history: Option<History>,
+    /// Changes made so far by the instruction being executed, while recording
+    recording: Option<UndoEntry>,
+}
+
+/// What a watchpoint saw before an instruction, to compare with after it
+enum Observed {
+    Memory(Option<Value>),
+    Heap(Option<HeapValue>),
+    StackDepth(usize),
 }
 
 impl VM {
     pub fn new(instructions: Vec<Instruction>) -> Self {
-        VM {
+        let mut vm = VM {
             state: VMState::new(instructions),
             debug_options: DebugOptions::default(),
             output_buffer: Vec::new(),
+            breakpoints: BTreeSet::new(),
+            watches: BTreeMap::new(),
+            next_watch: 1,
+            paused_at: None,
+            history: None,
+            recording: None,
+        };
+        vm.register_functions();
+        vm
+    }
+
+    /// Register every `DefineFunction` up front so functions can be called
+    /// before their definition appears in the program. Unterminated
+    /// definitions are left unregistered and surface as FunctionNotFound
+    /// when reached.
+    fn register_functions(&mut self) {
+        self.state.functions = Function::scan(self.state.instructions());
+    }
+
+    /// Append instructions to the program and point the program counter at
+    /// the first of them, keeping the stack, memory and heap. Functions are
+    /// registered again so the new code can define and call them.
+    pub fn extend(&mut self, instructions: Vec<Instruction>) {
+        self.state.program_counter = self.state.instructions().len();
+        self.state.extend_instructions(instructions);
+        self.register_functions();
+    }
+
+    /// Allocate each data item on the heap and bind its reference to a global
+    /// of the same name, as `Assembler::data` describes them
+    pub fn load_data(&mut self, data: &[DataItem]) {
+        for item in data {
+            let id = self.state.heap.allocate(item.value.clone());
+            self.state.memory.insert(item.name.clone(), Value::Ref(id));
         }
     }
 
@@ -23,28 +77,41 @@ impl VM {
         self.debug_options = options;
     }
 
+    pub fn set_arithmetic_mode(&mut self, mode: ArithmeticMode) {
+        self.state.arithmetic_mode = mode;
+    }
+
     pub fn step(&mut self) -> Result<bool, VMError> {
         if self.state.program_counter >= self.state.instructions().len() {
             return Ok(false);
         }
 
-        let instruction = self.state.instructions()[self.state.program_counter].clone();
+        let address = self.state.program_counter;
+        let instruction = self.state.instructions()[address].clone();
 
-        if self.debug_options.show_instructions {
-            println!("Executing instruction at PC {}: {:?}", self.state.program_counter, instruction);
-            println!("Current stack: {:?}", self.state.stack);
+        self.trace(address, &instruction);
+        if let Some(history) = self.history.as_mut() {
+            history.prepare(&self.state);
+            self.recording = Some(self.begin_undo(address, &instruction));
         }
 
-        // Execute the instruction
-        if let Err(e) = self.execute_instruction(instruction.clone()) {
+        // Advance first so control flow can set the program counter directly
+        self.state.program_counter = address + 1;
+        let result = self.execute_instruction(instruction.clone());
+        // Failed instructions are recorded too, so stepping back undoes
+        // whatever they did before failing
+        if let (Some(history), Some(entry)) = (self.history.as_mut(), self.recording.take()) {
+            history.record(entry);
+        }
+        if let Err(e) = result {
             if self.debug_options.show_instructions {
-                println!("Error executing instruction: {:?}", e);
+                eprintln!("Error executing instruction: {:?}", e);
             }
+            // Leave the program counter on the failing instruction
+            self.state.program_counter = address;
             return Err(e);
         }
 
-        self.state.program_counter += 1;
-
         // Check for halt after executing the instruction
         if matches!(instruction, Instruction::Halt) {
             return Ok(false);
@@ -53,17 +120,396 @@ impl VM {
         Ok(true)
     }
 
+    /// Start recording every instruction so the program can be stepped
+    /// backwards. Recording begins from the current state, so load data first.
+    pub fn enable_history(&mut self, options: HistoryOptions) {
+        self.history = Some(History::new(options, &self.state));
+    }
+
+    pub fn disable_history(&mut self) {
+        self.history = None;
+    }
+
+    /// How many instructions `step_back` can still undo
+    pub fn history_len(&self) -> usize {
+        self.history.as_ref().map_or(0, |history| history.position - history.start())
+    }
+
+    /// Undo the last instruction executed, restoring the stack, memory, heap,
+    /// call stack and program counter. Output already produced is kept.
+    /// Returns false when history is disabled or exhausted.
+    pub fn step_back(&mut self) -> bool {
+        let Some(history) = self.history.as_mut() else {
+            return false;
+        };
+        if let Some(entry) = history.pop() {
+            entry.undo(&mut self.state);
+        } else if let Some(replay) = history.rewind_segment(&mut self.state) {
+            self.replay(replay);
+        } else {
+            return false;
+        }
+        // Resuming forwards executes the instruction we are standing on
+        self.paused_at = Some(self.state.program_counter);
+        true
+    }
+
+    /// Step backwards until reaching a breakpoint or undoing an instruction
+    /// that triggers a watchpoint. A watch reports the change as the undone
+    /// instruction made it, so this finds the last instruction that touched a
+    /// value.
+    pub fn reverse_continue(&mut self) -> StopReason {
+        loop {
+            let after: Vec<(usize, Observed)> = self.watches.iter()
+                .map(|(&id, watch)| (id, self.observe(watch)))
+                .collect();
+            if !self.step_back() {
+                return StopReason::HistoryStart;
+            }
+            let address = self.state.program_counter;
+            for (id, after) in after {
+                let watch = &self.watches[&id];
+                if let Some(event) = watch_event(watch, self.observe(watch), after) {
+                    return StopReason::Watch { id, address, event };
+                }
+            }
+            if self.breakpoints.contains(&address) {
+                return StopReason::Breakpoint { address };
+            }
+        }
+    }
+
+    /// Execute recorded instructions again after restoring a checkpoint.
+    /// Execution is deterministic, so only output and traces must not repeat.
+    fn replay(&mut self, steps: usize) {
+        let output = self.output_buffer.len();
+        let options = std::mem::take(&mut self.debug_options);
+        for _ in 0..steps {
+            let _ = self.step();
+        }
+        self.debug_options = options;
+        self.output_buffer.truncate(output);
+    }
+
+    /// Save what `instruction` may overwrite: the operands it pops and, for
+    /// function instructions, the top frame. Globals and heap objects are
+    /// saved as they change.
+    fn begin_undo(&self, address: usize, instruction: &Instruction) -> UndoEntry {
+        let pops = match instruction {
+            Instruction::Call(name) => self.state.functions.get(name).map_or(0, |f| f.param_count),
+            _ => instruction.stack_effect().map_or(0, |(pops, _)| pops),
+        };
+        let stack_base = self.state.stack.len().saturating_sub(pops);
+        let frames = (instruction.info().category == Category::Function)
+            .then(|| (self.state.call_stack.len(), self.state.call_stack.last().cloned()));
+        UndoEntry {
+            program_counter: address,
+            stack_base,
+            popped: self.state.stack[stack_base..].to_vec(),
+            heap_next_id: self.state.heap.next_id(),
+            frames,
+            ..UndoEntry::default()
+        }
+    }
+
+    fn set_global(&mut self, name: String, value: Value) {
+        let old = self.state.memory.insert(name.clone(), value);
+        if let Some(entry) = self.recording.as_mut() {
+            entry.memory.push((name, old));
+        }
+    }
+
+    fn heap_mut(&mut self, id: usize) -> Option<&mut HeapValue> {
+        if let Some(entry) = self.recording.as_mut() {
+            entry.heap.push((id, self.state.heap.get(id).cloned()));
+        }
+        self.state.heap.get_mut(id)
+    }
+
+    fn free_heap(&mut self, id: usize) {
+        let old = self.state.heap.free(id);
+        if let Some(entry) = self.recording.as_mut() {
+            entry.heap.push((id, old));
+        }
+    }
+
+    /// Stop before executing the instruction at `address`. Returns false if
+    /// there already was a breakpoint there.
+    pub fn add_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.insert(address)
+    }
+
+    /// Returns false if there was no breakpoint at `address`
+    pub fn remove_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<usize> {
+        &self.breakpoints
+    }
+
+    /// Start watching and return the watchpoint's id
+    pub fn add_watch(&mut self, watch: Watch) -> usize {
+        let id = self.next_watch;
+        self.next_watch += 1;
+        self.watches.insert(id, watch);
+        id
+    }
+
+    /// Returns false if there was no watchpoint with this id
+    pub fn remove_watch(&mut self, id: usize) -> bool {
+        self.watches.remove(&id).is_some()
+    }
+
+    pub fn watches(&self) -> &BTreeMap<usize, Watch> {
+        &self.watches
+    }
+
+    /// Execute one instruction like `step`, honouring breakpoints and
+    /// watchpoints. Returns `None` when nothing stopped the program.
+    ///
+    /// A breakpoint stops before its instruction, both when the program
+    /// counter arrives there and when execution starts there; the next call
+    /// then executes the instruction. Watchpoints are checked after each
+    /// instruction.
+    pub fn step_checked(&mut self) -> Option<StopReason> {
+        let address = self.state.program_counter;
+        if self.breakpoints.contains(&address) && self.paused_at != Some(address) {
+            self.paused_at = Some(address);
+            return Some(StopReason::Breakpoint { address });
+        }
+        self.paused_at = None;
+
+        let observed: Vec<(usize, Observed)> = self.watches.iter()
+            .map(|(&id, watch)| (id, self.observe(watch)))
+            .collect();
+        match self.step() {
+            Ok(true) => {}
+            Ok(false) => return Some(StopReason::Halted),
+            Err(error) => return Some(StopReason::Error { error }),
+        }
+        for (id, before) in observed {
+            let watch = &self.watches[&id];
+            if let Some(event) = watch_event(watch, before, self.observe(watch)) {
+                return Some(StopReason::Watch { id, address, event });
+            }
+        }
+
+        let pc = self.state.program_counter;
+        if pc >= self.state.instructions().len() {
+            return Some(StopReason::Halted);
+        }
+        if self.breakpoints.contains(&pc) {
+            self.paused_at = Some(pc);
+            return Some(StopReason::Breakpoint { address: pc });
+        }
+        None
+    }
+
+    /// Run until a breakpoint, watchpoint, halt or error
+    pub fn run_until_stop(&mut self) -> StopReason {
+        loop {
+            if let Some(reason) = self.step_checked() {
+                return reason;
+            }
+        }
+    }
+
+    /// Like `run_until_stop`, but give up with `StopReason::StepLimit` after
+    /// executing `max_steps` instructions
+    pub fn run_with_limit(&mut self, max_steps: usize) -> StopReason {
+        for _ in 0..max_steps {
+            if let Some(reason) = self.step_checked() {
+                return reason;
+            }
+        }
+        StopReason::StepLimit
+    }
+
+    fn observe(&self, watch: &Watch) -> Observed {
+        match watch {
+            Watch::Memory(name) => Observed::Memory(self.state.memory.get(name).cloned()),
+            Watch::Heap(id) => Observed::Heap(self.state.heap.get(*id).cloned()),
+            Watch::StackDepth(_) => Observed::StackDepth(self.state.stack.len()),
+        }
+    }
+
+    /// Describe the instruction about to run on stderr, with the parts that
+    /// `DebugOptions` selects, so traces never mix with program output
+    fn trace(&self, address: usize, instruction: &Instruction) {
+        let options = &self.debug_options;
+        let mut parts = Vec::new();
+        if options.show_pc {
+            parts.push(format!("{:>4}", address));
+        }
+        if options.show_instructions {
+            parts.push(format!("{:<20}", instruction.to_string()));
+        }
+        if options.show_stack {
+            let stack: Vec<String> = self.state.stack.iter().map(Value::to_string).collect();
+            parts.push(format!("stack: [{}]", stack.join(", ")));
+        }
+        if options.show_memory {
+            let mut memory: Vec<String> = self.state.memory.iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect();
+            memory.sort();
+            parts.push(format!("memory: {{{}}}", memory.join(", ")));
+        }
+        if !parts.is_empty() {
+            eprintln!("{}", parts.join("  ").trim_end());
+        }
+    }
+
+    fn current_frame(&mut self) -> Result<&mut StackFrame, VMError> {
+        self.state.call_stack.last_mut().ok_or(VMError::EmptyCallStack)
+    }
+
+    fn return_from_function(&mut self) -> Result<(), VMError> {
+        let frame = self.state.call_stack.pop().ok_or(VMError::EmptyCallStack)?;
+        self.state.program_counter = frame.return_address;
+        Ok(())
+    }
+
+    /// Address of the instruction being executed; `step` has already moved
+    /// the program counter past it
+    fn current_address(&self) -> usize {
+        self.state.program_counter - 1
+    }
+
+    fn jump(&mut self, target: usize) -> Result<(), VMError> {
+        if target >= self.state.instructions().len() {
+            return Err(VMError::InvalidInstruction(target));
+        }
+        self.state.program_counter = target;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<Value, VMError> {
+        self.state.stack.pop().ok_or(VMError::StackUnderflow)
+    }
+
+    /// Fail with `StackUnderflow` unless at least `count` values are on the stack
+    fn require(&self, count: usize) -> Result<(), VMError> {
+        if self.state.stack.len() < count {
+            return Err(VMError::StackUnderflow);
+        }
+        Ok(())
+    }
+
+    /// Clone the value `depth` slots below the top of the stack
+    fn peek(&self, depth: usize) -> Result<Value, VMError> {
+        self.require(depth.checked_add(1).ok_or(VMError::StackUnderflow)?)?;
+        Ok(self.state.stack[self.state.stack.len() - 1 - depth].clone())
+    }
+
+    fn pop_int(&mut self) -> Result<i64, VMError> {
+        self.pop()?.as_int()
+    }
+
+    fn pop_heap_ref(&mut self) -> Result<usize, VMError> {
+        self.pop()?.as_heap_ref()
+    }
+
     fn binary_op<F>(&mut self, op: F) -> Result<(), VMError>
     where
-        F: FnOnce(i64, i64) -> Result<i64, VMError>,
+        F: FnOnce(Value, Value) -> Result<Value, VMError>,
     {
-        let b = self.state.stack.pop().ok_or(VMError::StackUnderflow)?;
-        let a = self.state.stack.pop().ok_or(VMError::StackUnderflow)?;
+        let b = self.pop()?;
+        let a = self.pop()?;
         let result = op(a, b)?;
         self.state.stack.push(result);
         Ok(())
     }
 
+    fn int_op<F>(&mut self, op: F) -> Result<(), VMError>
+    where
+        F: FnOnce(i64, i64) -> Result<Value, VMError>,
+    {
+        self.binary_op(|a, b| op(a.as_int()?, b.as_int()?))
+    }
+
+    /// Integer operation whose overflow behaviour follows the arithmetic mode
+    fn overflowing_op<F>(&mut self, op: F) -> Result<(), VMError>
+    where
+        F: FnOnce(i64, i64) -> Result<(Option<i64>, i64, i64), VMError>,
+    {
+        let mode = self.state.arithmetic_mode;
+        let pc = self.current_address();
+        self.int_op(|a, b| {
+            let (checked, wrapping, saturating) = op(a, b)?;
+            mode.apply(checked, wrapping, saturating)
+                .map(Value::Int)
+                .ok_or(VMError::ArithmeticOverflow(pc))
+        })
+    }
+
+    /// Single-operand counterpart of `overflowing_op`
+    fn overflowing_unary_op<F>(&mut self, op: F) -> Result<(), VMError>
+    where
+        F: FnOnce(i64) -> (Option<i64>, i64, i64),
+    {
+        let value = self.pop_int()?;
+        let (checked, wrapping, saturating) = op(value);
+        let result = self.state.arithmetic_mode.apply(checked, wrapping, saturating)
+            .ok_or(VMError::ArithmeticOverflow(self.current_address()))?;
+        self.state.stack.push(Value::Int(result));
+        Ok(())
+    }
+
+    /// Shifts by 0..=63 bits are exact. Larger or negative amounts overflow in
+    /// checked mode, are masked to six bits when wrapping, and shift every bit
+    /// out when saturating.
+    fn shift_op<F>(&mut self, shift: F) -> Result<(), VMError>
+    where
+        F: Fn(i64, u32) -> i64,
+    {
+        self.overflowing_op(|a, b| {
+            let exact = (0..64).contains(&b).then(|| shift(a, b as u32));
+            let saturated = exact.unwrap_or_else(|| shift(shift(a, 63), 1));
+            Ok((exact, shift(a, (b & 63) as u32), saturated))
+        })
+    }
+
+    fn float_op<F>(&mut self, op: F) -> Result<(), VMError>
+    where
+        F: FnOnce(f64, f64) -> Result<Value, VMError>,
+    {
+        self.binary_op(|a, b| op(a.as_float()?, b.as_float()?))
+    }
+
+    fn logic_op<F>(&mut self, op: F) -> Result<(), VMError>
+    where
+        F: FnOnce(bool, bool) -> bool,
+    {
+        self.binary_op(|a, b| Ok(Value::Bool(op(a.is_truthy()?, b.is_truthy()?))))
+    }
+
+    fn string_at(&self, id: usize) -> Result<&String, VMError> {
+        match self.state.heap.get(id) {
+            Some(HeapValue::String(s)) => Ok(s),
+            Some(HeapValue::Array(_)) => Err(VMError::TypeError("string".into(), "array".into())),
+            None => Err(VMError::InvalidHeapAddress(id)),
+        }
+    }
+
+    /// Render a value the way `PRINT` shows it, following heap references
+    fn format_value(&self, value: &Value) -> Result<String, VMError> {
+        match value {
+            Value::Ref(id) => match self.state.heap.get(*id) {
+                Some(HeapValue::String(s)) => Ok(s.clone()),
+                Some(HeapValue::Array(items)) => {
+                    let items = items.iter()
+                        .map(|item| self.format_value(item))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    Ok(format!("[{}]", items.join(", ")))
+                }
+                None => Err(VMError::InvalidHeapAddress(*id)),
+            },
+            other => Ok(other.to_string()),
+        }
+    }
+
     fn execute_instruction(&mut self, instruction: Instruction) -> Result<(), VMError> {
         match instruction {
             Instruction::Push(value) => {
@@ -71,11 +517,11 @@ impl VM {
                 Ok(())
             }
             Instruction::Pop => {
-                self.state.stack.pop().ok_or(VMError::StackUnderflow)?;
+                self.pop()?;
                 Ok(())
             }
             Instruction::Dup => {
-                let value = *self.state.stack.last().ok_or(VMError::StackUnderflow)?;
+                let value = self.state.stack.last().ok_or(VMError::StackUnderflow)?.clone();
                 self.state.stack.push(value);
                 Ok(())
             }
@@ -87,223 +533,387 @@ impl VM {
                 self.state.stack.swap(len - 1, len - 2);
                 Ok(())
             }
-            Instruction::Add => {
-                let b = self.state.stack.pop().ok_or(VMError::StackUnderflow)?;
-                let a = self.state.stack.pop().ok_or(VMError::StackUnderflow)?;
-                self.state.stack.push(a + b);
+            Instruction::Over => {
+                let value = self.peek(1)?;
+                self.state.stack.push(value);
                 Ok(())
             }
-            Instruction::Sub => {
-                let b = self.state.stack.pop().ok_or(VMError::StackUnderflow)?;
-                let a = self.state.stack.pop().ok_or(VMError::StackUnderflow)?;
-                self.state.stack.push(a - b);
+            Instruction::Rot => {
+                self.require(3)?;
+                let len = self.state.stack.len();
+                self.state.stack[len - 3..].rotate_left(1);
                 Ok(())
             }
-            Instruction::Mul => {
-                let b = self.state.stack.pop().ok_or(VMError::StackUnderflow)?;
-                let a = self.state.stack.pop().ok_or(VMError::StackUnderflow)?;
-                self.state.stack.push(a * b);
+            Instruction::RotBack => {
+                self.require(3)?;
+                let len = self.state.stack.len();
+                self.state.stack[len - 3..].rotate_right(1);
+                Ok(())
+            }
+            Instruction::Nip => {
+                self.require(2)?;
+                let len = self.state.stack.len();
+                self.state.stack.remove(len - 2);
+                Ok(())
+            }
+            Instruction::Tuck => {
+                let value = self.peek(0)?;
+                self.require(2)?;
+                let len = self.state.stack.len();
+                self.state.stack.insert(len - 2, value);
+                Ok(())
+            }
+            Instruction::Pick(depth) => {
+                let value = self.peek(depth)?;
+                self.state.stack.push(value);
+                Ok(())
+            }
+            Instruction::Roll(depth) => {
+                self.require(depth.checked_add(1).ok_or(VMError::StackUnderflow)?)?;
+                let len = self.state.stack.len();
+                self.state.stack[len - 1 - depth..].rotate_left(1);
+                Ok(())
+            }
+            Instruction::TwoDup => {
+                self.require(2)?;
+                let len = self.state.stack.len();
+                self.state.stack.extend_from_within(len - 2..);
+                Ok(())
+            }
+            Instruction::TwoDrop => {
+                self.require(2)?;
+                let len = self.state.stack.len();
+                self.state.stack.truncate(len - 2);
+                Ok(())
+            }
+            Instruction::Depth => {
+                let depth = self.state.stack.len() as i64;
+                self.state.stack.push(Value::Int(depth));
                 Ok(())
             }
-            Instruction::Div => {
-                let b = self.state.stack.pop().ok_or(VMError::StackUnderflow)?;
-                let a = self.state.stack.pop().ok_or(VMError::StackUnderflow)?;
+            Instruction::Add => self.overflowing_op(|a, b| {
+                Ok((a.checked_add(b), a.wrapping_add(b), a.saturating_add(b)))
+            }),
+            Instruction::Sub => self.overflowing_op(|a, b| {
+                Ok((a.checked_sub(b), a.wrapping_sub(b), a.saturating_sub(b)))
+            }),
+            Instruction::Mul => self.overflowing_op(|a, b| {
+                Ok((a.checked_mul(b), a.wrapping_mul(b), a.saturating_mul(b)))
+            }),
+            Instruction::Div => self.overflowing_op(|a, b| {
                 if b == 0 {
                     return Err(VMError::DivisionByZero);
                 }
-                self.state.stack.push(a / b);
+                // Only i64::MIN / -1 can overflow
+                Ok((a.checked_div(b), a.wrapping_div(b), a.saturating_div(b)))
+            }),
+            // MOD and DIVMOD use Euclidean division: the remainder is never
+            // negative, so a == q * b + r with 0 <= r < |b|. Only the quotient of
+            // i64::MIN by -1 can overflow; the remainder always fits.
+            Instruction::Mod => self.int_op(|a, b| {
+                if b == 0 {
+                    return Err(VMError::DivisionByZero);
+                }
+                Ok(Value::Int(a.wrapping_rem_euclid(b)))
+            }),
+            Instruction::DivMod => {
+                let b = self.pop_int()?;
+                let a = self.pop_int()?;
+                if b == 0 {
+                    return Err(VMError::DivisionByZero);
+                }
+                let quotient = self.state.arithmetic_mode
+                    .apply(a.checked_div_euclid(b), a.wrapping_div_euclid(b), a.checked_div_euclid(b).unwrap_or(i64::MAX))
+                    .ok_or(VMError::ArithmeticOverflow(self.current_address()))?;
+                self.state.stack.push(Value::Int(quotient));
+                self.state.stack.push(Value::Int(a.wrapping_rem_euclid(b)));
                 Ok(())
             }
-            Instruction::Store(name) => {
-                let value = self.state.stack.pop().ok_or(VMError::StackUnderflow)?;
-                self.state.memory.insert(name, value);
+            Instruction::Neg => self.overflowing_unary_op(|a| {
+                (a.checked_neg(), a.wrapping_neg(), a.saturating_neg())
+            }),
+            Instruction::Abs => self.overflowing_unary_op(|a| {
+                (a.checked_abs(), a.wrapping_abs(), a.saturating_abs())
+            }),
+            Instruction::Min => self.int_op(|a, b| Ok(Value::Int(a.min(b)))),
+            Instruction::Max => self.int_op(|a, b| Ok(Value::Int(a.max(b)))),
+            Instruction::Pow => self.overflowing_op(|a, b| {
+                if b < 0 {
+                    return Err(VMError::NegativeExponent(b));
+                }
+                if let Ok(exp) = u32::try_from(b) {
+                    return Ok((a.checked_pow(exp), a.wrapping_pow(exp), a.saturating_pow(exp)));
+                }
+                // Only 0, 1 and -1 stay in range for larger exponents; the
+                // sign of anything else depends on the exponent's parity
+                let odd = b % 2 == 1;
+                Ok(match a {
+                    0 | 1 => (Some(a), a, a),
+                    -1 => {
+                        let result = if odd { -1 } else { 1 };
+                        (Some(result), result, result)
+                    }
+                    _ => {
+                        let saturated = if a < 0 && odd { i64::MIN } else { i64::MAX };
+                        (None, wrapping_pow(a, b as u64), saturated)
+                    }
+                })
+            }),
+            Instruction::BitAnd => self.int_op(|a, b| Ok(Value::Int(a & b))),
+            Instruction::BitOr => self.int_op(|a, b| Ok(Value::Int(a | b))),
+            Instruction::BitXor => self.int_op(|a, b| Ok(Value::Int(a ^ b))),
+            Instruction::BitNot => {
+                let value = self.pop_int()?;
+                self.state.stack.push(Value::Int(!value));
                 Ok(())
             }
-            Instruction::Load(name) => {
-                let value = *self.state.memory.get(&name)
-                    .ok_or(VMError::InvalidMemoryAccess(0))?;
-                self.state.stack.push(value);
+            Instruction::ShiftLeft => self.shift_op(|a, n| a << n),
+            Instruction::ShiftRight => self.shift_op(|a, n| ((a as u64) >> n) as i64),
+            Instruction::ShiftRightArithmetic => self.shift_op(|a, n| a >> n),
+            Instruction::FAdd => self.float_op(|a, b| Ok(Value::Float(a + b))),
+            Instruction::FSub => self.float_op(|a, b| Ok(Value::Float(a - b))),
+            Instruction::FMul => self.float_op(|a, b| Ok(Value::Float(a * b))),
+            Instruction::FDiv => self.float_op(|a, b| {
+                if b == 0.0 {
+                    return Err(VMError::DivisionByZero);
+                }
+                Ok(Value::Float(a / b))
+            }),
+            Instruction::FLessThan => self.float_op(|a, b| Ok(Value::Bool(a < b))),
+            Instruction::FLessEqual => self.float_op(|a, b| Ok(Value::Bool(a <= b))),
+            Instruction::FGreaterThan => self.float_op(|a, b| Ok(Value::Bool(a > b))),
+            Instruction::FGreaterEqual => self.float_op(|a, b| Ok(Value::Bool(a >= b))),
+            Instruction::IntToFloat => {
+                let value = self.pop_int()?;
+                self.state.stack.push(Value::Float(value as f64));
                 Ok(())
             }
-            Instruction::Jump(target) => {
-                if target >= self.state.instructions().len() {
-                    return Err(VMError::InvalidInstruction(target));
+            Instruction::FloatToInt => {
+                // Truncates toward zero; NaN and out-of-range values are rejected
+                // rather than saturated
+                let value = self.pop()?.as_float()?;
+                if !value.is_finite() || value < i64::MIN as f64 || value >= i64::MAX as f64 {
+                    return Err(VMError::InvalidConversion(value));
                 }
-                self.state.program_counter = target - 1; // -1 because step() will increment
+                self.state.stack.push(Value::Int(value as i64));
+                Ok(())
+            }
+            Instruction::Store(name) => {
+                let value = self.pop()?;
+                self.set_global(name, value);
+                Ok(())
+            }
+            Instruction::Load(name) => {
+                let value = self.state.memory.get(&name)
+                    .ok_or(VMError::InvalidMemoryAccess(0))?
+                    .clone();
+                self.state.stack.push(value);
                 Ok(())
             }
+            Instruction::Jump(target) => self.jump(target),
             Instruction::JumpIfZero(target) => {
-                let condition = self.state.stack.pop().ok_or(VMError::StackUnderflow)?;
-                if condition == 0 {
-                    if target >= self.state.instructions().len() {
-                        return Err(VMError::InvalidInstruction(target));
-                    }
-                    self.state.program_counter = target - 1;
+                let condition = self.pop()?.is_truthy()?;
+                if !condition {
+                    self.jump(target)?;
                 }
                 Ok(())
             }
             Instruction::JumpIfNotZero(target) => {
-                let condition = self.state.stack.pop().ok_or(VMError::StackUnderflow)?;
-                if condition != 0 {
-                    if target >= self.state.instructions().len() {
-                        return Err(VMError::InvalidInstruction(target));
-                    }
-                    self.state.program_counter = target - 1;
+                let condition = self.pop()?.is_truthy()?;
+                if condition {
+                    self.jump(target)?;
                 }
                 Ok(())
             }
             Instruction::JumpIf(target) => {
-                let condition = self.state.stack.pop().ok_or(VMError::StackUnderflow)?;
-                if condition != 0 {
-                    if target >= self.state.instructions().len() {
-                        return Err(VMError::InvalidInstruction(target));
-                    }
-                    self.state.program_counter = target - 1;
+                let condition = self.pop()?.is_truthy()?;
+                if condition {
+                    self.jump(target)?;
                 }
                 Ok(())
             }
-            Instruction::Equal => self.binary_op(|a, b| Ok(if a == b { 1 } else { 0 })),
-            Instruction::NotEqual => self.binary_op(|a, b| Ok(if a != b { 1 } else { 0 })),
-            Instruction::LessThan => self.binary_op(|a, b| Ok(if a < b { 1 } else { 0 })),
-            Instruction::LessEqual => self.binary_op(|a, b| Ok(if a <= b { 1 } else { 0 })),
-            Instruction::GreaterThan => self.binary_op(|a, b| Ok(if a > b { 1 } else { 0 })),
-            Instruction::GreaterEqual => self.binary_op(|a, b| Ok(if a >= b { 1 } else { 0 })),
-            Instruction::And => self.binary_op(|a, b| Ok(if a != 0 && b != 0 { 1 } else { 0 })),
-            Instruction::Or => self.binary_op(|a, b| Ok(if a != 0 || b != 0 { 1 } else { 0 })),
+            Instruction::Equal => self.binary_op(|a, b| Ok(Value::Bool(a == b))),
+            Instruction::NotEqual => self.binary_op(|a, b| Ok(Value::Bool(a != b))),
+            Instruction::LessThan => self.int_op(|a, b| Ok(Value::Bool(a < b))),
+            Instruction::LessEqual => self.int_op(|a, b| Ok(Value::Bool(a <= b))),
+            Instruction::GreaterThan => self.int_op(|a, b| Ok(Value::Bool(a > b))),
+            Instruction::GreaterEqual => self.int_op(|a, b| Ok(Value::Bool(a >= b))),
+            Instruction::And => self.logic_op(|a, b| a && b),
+            Instruction::Or => self.logic_op(|a, b| a || b),
             Instruction::Not => {
-                let value = self.state.stack.pop().ok_or(VMError::StackUnderflow)?;
-                self.state.stack.push(if value == 0 { 1 } else { 0 });
+                let value = self.pop()?.is_truthy()?;
+                self.state.stack.push(Value::Bool(!value));
                 Ok(())
             }
             Instruction::Print => {
-                let value = self.state.stack.pop().ok_or(VMError::StackUnderflow)?;
-                // Check if the value is a heap reference
-                if let Some(heap_value) = self.state.heap.get(value as usize) {
-                    match heap_value {
-                        HeapValue::String(s) => self.push_output(format!("{}", s)),
-                        HeapValue::Array(arr) => self.push_output(format!("{:?}", arr)),
-                    }
-                } else {
-                    // If not a heap reference, print as a regular number
-                    self.push_output(format!("{}", value));
-                }
+                let value = self.pop()?;
+                let text = self.format_value(&value)?;
+                self.push_output(text);
                 Ok(())
             }
             Instruction::PrintStr(s) => {
-                let unescaped = s.replace("\\n", "\n");
-                self.push_output(unescaped);
+                self.push_output(s);
                 Ok(())
             }
             Instruction::PrintChar => {
-                let value = self.state.stack.pop().ok_or(VMError::StackUnderflow)?;
+                let value = self.pop_int()?;
                 self.push_output((value as u8 as char).to_string());
                 Ok(())
             }
             Instruction::NewArray => {
-                let size = self.state.stack.pop().ok_or(VMError::StackUnderflow)?;
+                let size = self.pop_int()?;
                 if size < 0 {
                     return Err(VMError::InvalidArrayIndex(size));
                 }
-                let array = vec![0; size as usize];
+                let array = vec![Value::Int(0); size as usize];
                 let array_id = self.state.heap.allocate(HeapValue::Array(array));
-                self.state.stack.push(array_id as i64);
+                self.state.stack.push(Value::Ref(array_id));
                 Ok(())
             }
             Instruction::ArrayGet => {
-                let index = self.state.stack.pop().ok_or(VMError::StackUnderflow)?;
-                let array_id = self.state.stack.pop().ok_or(VMError::StackUnderflow)? as usize;
+                let index = self.pop_int()?;
+                let array_id = self.pop_heap_ref()?;
 
-                if let Some(HeapValue::Array(array)) = self.state.heap.get(array_id) {
-                    if index < 0 || index as usize >= array.len() {
-                        return Err(VMError::ArrayBoundsError(index, array.len()));
+                match self.state.heap.get(array_id) {
+                    Some(HeapValue::Array(array)) => {
+                        if index < 0 || index as usize >= array.len() {
+                            return Err(VMError::ArrayBoundsError(index, array.len()));
+                        }
+                        let value = array[index as usize].clone();
+                        self.state.stack.push(value);
+                        Ok(())
                     }
-                    self.state.stack.push(array[index as usize]);
-                    Ok(())
-                } else {
-                    Err(VMError::InvalidHeapAddress(array_id))
+                    Some(HeapValue::String(_)) => Err(VMError::TypeError("array".into(), "string".into())),
+                    None => Err(VMError::InvalidHeapAddress(array_id)),
                 }
             }
             Instruction::ArraySet => {
-                let value = self.state.stack.pop().ok_or(VMError::StackUnderflow)?;
-                let index = self.state.stack.pop().ok_or(VMError::StackUnderflow)?;
-                let array_id = self.state.stack.pop().ok_or(VMError::StackUnderflow)? as usize;
+                let value = self.pop()?;
+                let index = self.pop_int()?;
+                let array_id = self.pop_heap_ref()?;
 
-                if let Some(HeapValue::Array(array)) = self.state.heap.get_mut(array_id) {
-                    if index < 0 || index as usize >= array.len() {
-                        return Err(VMError::ArrayBoundsError(index, array.len()));
+                match self.heap_mut(array_id) {
+                    Some(HeapValue::Array(array)) => {
+                        if index < 0 || index as usize >= array.len() {
+                            return Err(VMError::ArrayBoundsError(index, array.len()));
+                        }
+                        array[index as usize] = value;
+                        Ok(())
                     }
-                    array[index as usize] = value;
-                    Ok(())
-                } else {
-                    Err(VMError::InvalidHeapAddress(array_id))
+                    Some(HeapValue::String(_)) => Err(VMError::TypeError("array".into(), "string".into())),
+                    None => Err(VMError::InvalidHeapAddress(array_id)),
                 }
             }
             Instruction::ArrayLength => {
-                let array_id = self.state.stack.pop().ok_or(VMError::StackUnderflow)? as usize;
+                let array_id = self.pop_heap_ref()?;
 
-                if let Some(HeapValue::Array(array)) = self.state.heap.get(array_id) {
-                    self.state.stack.push(array.len() as i64);
-                    Ok(())
-                } else {
-                    Err(VMError::InvalidHeapAddress(array_id))
+                match self.state.heap.get(array_id) {
+                    Some(HeapValue::Array(array)) => {
+                        self.state.stack.push(Value::Int(array.len() as i64));
+                        Ok(())
+                    }
+                    Some(HeapValue::String(_)) => Err(VMError::TypeError("array".into(), "string".into())),
+                    None => Err(VMError::InvalidHeapAddress(array_id)),
                 }
             }
             Instruction::FreeArray => {
-                let array_id = self.state.stack.pop().ok_or(VMError::StackUnderflow)? as usize;
+                let array_id = self.pop_heap_ref()?;
 
-                if let Some(HeapValue::Array(_)) = self.state.heap.free(array_id) {
-                    Ok(())
-                } else {
-                    Err(VMError::InvalidHeapAddress(array_id))
+                match self.state.heap.get(array_id) {
+                    Some(HeapValue::Array(_)) => {
+                        self.free_heap(array_id);
+                        Ok(())
+                    }
+                    Some(HeapValue::String(_)) => Err(VMError::TypeError("array".into(), "string".into())),
+                    None => Err(VMError::InvalidHeapAddress(array_id)),
                 }
             }
             Instruction::NewString(s) => {
                 let string_id = self.state.heap.allocate(HeapValue::String(s));
-                self.state.stack.push(string_id as i64);
+                self.state.stack.push(Value::Ref(string_id));
                 Ok(())
             }
             Instruction::StringConcat => {
-                let str2_id = self.state.stack.pop().ok_or(VMError::StackUnderflow)? as usize;
-                let str1_id = self.state.stack.pop().ok_or(VMError::StackUnderflow)? as usize;
-
-                let s1 = if let Some(HeapValue::String(s1)) = self.state.heap.get(str1_id) {
-                    s1.clone()
-                } else {
-                    return Err(VMError::TypeError("string".into(), "non-string".into()));
-                };
-
-                let s2 = if let Some(HeapValue::String(s2)) = self.state.heap.get(str2_id) {
-                    s2.clone()
-                } else {
-                    return Err(VMError::TypeError("string".into(), "non-string".into()));
-                };
-
-                let result = format!("{}{}", s1, s2);
+                let str2_id = self.pop_heap_ref()?;
+                let str1_id = self.pop_heap_ref()?;
+
+                let result = format!("{}{}", self.string_at(str1_id)?, self.string_at(str2_id)?);
                 let result_id = self.state.heap.allocate(HeapValue::String(result));
-                self.state.stack.push(result_id as i64);
+                self.state.stack.push(Value::Ref(result_id));
                 Ok(())
             }
             Instruction::StringLength => {
-                let string_id = self.state.stack.pop().ok_or(VMError::StackUnderflow)? as usize;
-
-                if let Some(HeapValue::String(s)) = self.state.heap.get(string_id) {
-                    self.state.stack.push(s.len() as i64);
-                    Ok(())
-                } else {
-                    Err(VMError::TypeError("string".into(), "non-string".into()))
-                }
+                let string_id = self.pop_heap_ref()?;
+                let length = self.string_at(string_id)?.len();
+                self.state.stack.push(Value::Int(length as i64));
+                Ok(())
             }
             Instruction::FreeString => {
-                let string_id = self.state.stack.pop().ok_or(VMError::StackUnderflow)? as usize;
+                let string_id = self.pop_heap_ref()?;
+                self.string_at(string_id)?;
+                self.free_heap(string_id);
+                Ok(())
+            }
+            Instruction::DefineFunction(name, _) => {
+                // Reached by normal flow: skip over the body to the matching END
+                let function = self.state.functions.get(&name)
+                    .ok_or(VMError::FunctionNotFound(name))?;
+                self.state.program_counter = function.end_address + 1;
+                Ok(())
+            }
+            Instruction::BeginFunction => Ok(()),
+            Instruction::EndFunction => self.return_from_function(),
+            Instruction::Call(name) => {
+                let function = self.state.functions.get(&name)
+                    .ok_or(VMError::FunctionNotFound(name))?;
+                let (address, param_count) = (function.address, function.param_count);
 
-                if let Some(HeapValue::String(_)) = self.state.heap.free(string_id) {
-                    Ok(())
-                } else {
-                    Err(VMError::InvalidHeapAddress(string_id))
+                if self.state.stack.len() < param_count {
+                    return Err(VMError::StackUnderflow);
+                }
+                let params = self.state.stack.split_off(self.state.stack.len() - param_count);
+
+                self.state.call_stack.push(StackFrame {
+                    return_address: self.state.program_counter,
+                    params,
+                    local_vars: HashMap::new(),
+                });
+                self.state.program_counter = address + 1;
+                Ok(())
+            }
+            Instruction::Return => self.return_from_function(),
+            Instruction::PushParam(index) => {
+                let frame = self.current_frame()?;
+                let value = frame.params.get(index)
+                    .ok_or(VMError::InvalidParameter(index))?
+                    .clone();
+                self.state.stack.push(value);
+                Ok(())
+            }
+            Instruction::CreateLocal(name) => {
+                self.current_frame()?.local_vars.insert(name, Value::Nil);
+                Ok(())
+            }
+            Instruction::LoadLocal(name) => {
+                let frame = self.current_frame()?;
+                let value = frame.local_vars.get(&name)
+                    .ok_or(VMError::LocalVarNotFound(name))?
+                    .clone();
+                self.state.stack.push(value);
+                Ok(())
+            }
+            Instruction::StoreLocal(name) => {
+                let value = self.pop()?;
+                let frame = self.current_frame()?;
+                match frame.local_vars.get_mut(&name) {
+                    Some(slot) => {
+                        *slot = value;
+                        Ok(())
+                    }
+                    None => Err(VMError::LocalVarNotFound(name)),
                 }
             }
             Instruction::Halt => Ok(()),
-            _ => Err(VMError::InvalidInstruction(self.state.program_counter)),
         }
     }
 
@@ -311,7 +921,7 @@ impl VM {
         &self.state
     }
 
-    pub fn get_memory(&self) -> &HashMap<String, i64> {
+    pub fn get_memory(&self) -> &HashMap<String, Value> {
         &self.state.memory
     }
 
@@ -325,7 +935,7 @@ impl VM {
 
     pub fn push_output(&mut self, output: String) {
         if self.debug_options.show_instructions {
-            println!("Output: {}", output);
+            eprintln!("Output: {}", output);
         }
         self.output_buffer.push(output);
     }
@@ -333,4 +943,40 @@ impl VM {
     pub fn take_output(&mut self) -> Vec<String> {
         std::mem::take(&mut self.output_buffer)
     }
-}
\ No newline at end of file
+}
+
+/// `base` raised to `exp` modulo 2^64, by square-and-multiply over the
+/// whole exponent
+fn wrapping_pow(mut base: i64, mut exp: u64) -> i64 {
+    let mut result: i64 = 1;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = result.wrapping_mul(base);
+        }
+        base = base.wrapping_mul(base);
+        exp >>= 1;
+    }
+    result
+}
+
+/// The change a watchpoint sees between two observations of it
+fn watch_event(watch: &Watch, before: Observed, after: Observed) -> Option<WatchEvent> {
+    match (watch, before, after) {
+        (Watch::Memory(name), Observed::Memory(old), Observed::Memory(new)) => {
+            (new != old).then(|| WatchEvent::Memory { name: name.clone(), old, new })
+        }
+        (Watch::Heap(id), Observed::Heap(old), Observed::Heap(new)) => {
+            let id = *id;
+            match (old, new) {
+                (None, Some(value)) => Some(WatchEvent::HeapAllocated { id, value }),
+                (Some(old), None) => Some(WatchEvent::HeapFreed { id, old }),
+                (Some(old), Some(new)) if old != new => Some(WatchEvent::HeapChanged { id, old, new }),
+                _ => None,
+            }
+        }
+        (Watch::StackDepth(limit), Observed::StackDepth(old), Observed::StackDepth(depth)) => {
+            (old < *limit && depth >= *limit).then_some(WatchEvent::StackDepth { depth })
+        }
+        _ => None,
+    }
+}
@@ -1,2 +1,3 @@
 pub mod core;
-pub mod tests;
\ No newline at end of file
+#[cfg(test)]
+mod tests;
\ No newline at end of file
@@ -3,8 +3,15 @@ use actix_cors::Cors;
 use serde::{Deserialize, Serialize};
 use std::sync::Mutex;
 use virtual_machine::core::vm::VM;
-use virtual_machine::core::assembler::Assembler;
-use virtual_machine::core::state::DebugOptions;
+use virtual_machine::core::assembler::{Assembler, MemoryResolver};
+use virtual_machine::core::bytecode;
+use virtual_machine::core::cfg::ControlFlowGraph;
+use virtual_machine::core::error::AssemblerError;
+use virtual_machine::core::instruction::{Instruction, OPCODES};
+use virtual_machine::core::optimizer::{self, OptimizationReport};
+use virtual_machine::core::state::{DebugOptions, ArithmeticMode, DataItem, HistoryOptions, StopReason, Watch};
+use virtual_machine::core::value::Value;
+use virtual_machine::core::verifier;
 
 // Shared state between requests
 struct AppState {
@@ -13,16 +20,39 @@ struct AppState {
 
 #[derive(Debug, Serialize)]
 struct VMStateResponse {
-    stack: Vec<i64>,
-    memory: std::collections::HashMap<String, i64>,
+    stack: Vec<Value>,
+    memory: std::collections::HashMap<String, Value>,
     program_counter: usize,
     output: Vec<String>,
     instructions: Vec<String>,
+    arithmetic_mode: ArithmeticMode,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    diagnostics: Vec<AssemblerError>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    optimization: Option<OptimizationReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop: Option<StopReason>,
 }
 
 #[derive(Debug, Deserialize)]
 struct LoadProgramRequest {
     code: String,
+    #[serde(default)]
+    arithmetic_mode: ArithmeticMode,
+    #[serde(default)]
+    optimize: bool,
+    // Record an undo log for step-back and reverse-continue
+    #[serde(default)]
+    history: bool,
+    // Files the program can `.include`, keyed by path
+    #[serde(default)]
+    files: std::collections::HashMap<String, String>,
+}
+
+impl LoadProgramRequest {
+    fn assembler(&self) -> Assembler {
+        Assembler::new().with_resolver(MemoryResolver::new(self.files.clone()))
+    }
 }
 
 // Convert VM state to response format
@@ -37,37 +67,138 @@ impl From<&virtual_machine::core::state::VMState> for VMStateResponse {
                 .iter()
                 .map(|i| i.to_string())
                 .collect(),
+            arithmetic_mode: state.arithmetic_mode,
+            diagnostics: vec![],
+            optimization: None,
+            stop: None,
         }
     }
 }
 
+#[derive(Debug, Deserialize)]
+struct BreakpointRequest {
+    address: usize,
+}
+
+// Instructions `/api/continue` executes before giving up with `step_limit`
+const CONTINUE_STEP_LIMIT: usize = 1_000_000;
+
+#[derive(Debug, Deserialize)]
+struct LoadBytecodeQuery {
+    #[serde(default)]
+    arithmetic_mode: ArithmeticMode,
+    #[serde(default)]
+    optimize: bool,
+    #[serde(default)]
+    history: bool,
+}
+
+// Verify and optionally optimize (and re-verify) the program, then start a fresh VM on it with
+// its data items allocated, recording history if asked, and respond with its
+// initial state
+fn install_program(
+    data: &web::Data<AppState>,
+    instructions: Vec<Instruction>,
+    items: &[DataItem],
+    arithmetic_mode: ArithmeticMode,
+    optimize: bool,
+    history: bool,
+    diagnostics: Vec<AssemblerError>,
+) -> HttpResponse {
+    let report = verifier::verify(&instructions);
+    if !report.is_ok() {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Verification failed with {} error(s)", report.errors.len()),
+            "verification": report,
+        }));
+    }
+
+    let (instructions, optimization) = if optimize {
+        let (optimized, report) = optimizer::optimize(&instructions);
+        (optimized, Some(report))
+    } else {
+        (instructions, None)
+    };
+
+    // The optimizer must keep a program valid, so check the one that will run
+    if optimization.is_some() {
+        let report = verifier::verify(&instructions);
+        if !report.is_ok() {
+            return HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Optimized program failed verification with {} error(s)", report.errors.len()),
+                "verification": report,
+            }));
+        }
+    }
+
+    let mut vm = VM::new(instructions);
+    vm.load_data(items);
+    vm.set_arithmetic_mode(arithmetic_mode);
+    vm.set_debug_options(DebugOptions {
+        show_instructions: true,
+        show_stack: true,
+        show_pc: false,
+        show_memory: false,
+    });
+    if history {
+        vm.enable_history(HistoryOptions::default());
+    }
+
+    let state = vm.get_state();
+    let mut response = VMStateResponse::from(state);
+    response.output = vm.take_output();
+    response.diagnostics = diagnostics;
+    response.optimization = optimization;
+
+    // Store VM instance in app state
+    let mut vm_state = data.vm.lock().unwrap();
+    *vm_state = Some(vm);
+
+    HttpResponse::Ok().json(response)
+}
+
 // API endpoints
 async fn load_program(
     data: web::Data<AppState>,
     program: web::Json<LoadProgramRequest>,
 ) -> Result<HttpResponse> {
-    let mut assembler = Assembler::new();
+    let mut assembler = program.assembler();
     match assembler.assemble(&program.code) {
         Ok(instructions) => {
-            let mut vm = VM::new(instructions);
-            vm.set_debug_options(DebugOptions {
-                show_instructions: true,
-                show_stack: true,
-                show_pc: false,
-                show_memory: false,
-            });
-
-            let state = vm.get_state();
-            let mut response = VMStateResponse::from(state);
-            response.output = vm.take_output();
-
-            // Store VM instance in app state
-            let mut vm_state = data.vm.lock().unwrap();
-            *vm_state = Some(vm);
-
-            Ok(HttpResponse::Ok().json(response))
+            let warnings = assembler.diagnostics().to_vec();
+            Ok(install_program(&data, instructions, assembler.data(), program.arithmetic_mode, program.optimize, program.history, warnings))
         }
-        Err(e) => Ok(HttpResponse::BadRequest().body(format!("Assembly error: {:?}", e))),
+        Err(errors) => Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Assembly failed with {} error(s)", errors.errors().count()),
+            "diagnostics": errors,
+        }))),
+    }
+}
+
+// Load a pre-assembled program sent as a raw bytecode body
+async fn load_bytecode(
+    data: web::Data<AppState>,
+    query: web::Query<LoadBytecodeQuery>,
+    body: web::Bytes,
+) -> Result<HttpResponse> {
+    match bytecode::decode(&body) {
+        Ok(instructions) => Ok(install_program(&data, instructions, &[], query.arithmetic_mode, query.optimize, query.history, vec![])),
+        Err(e) => Ok(HttpResponse::BadRequest().body(format!("Bytecode error: {}", e))),
+    }
+}
+
+// Control-flow graph of a program in Graphviz DOT form
+async fn control_flow_graph(program: web::Json<LoadProgramRequest>) -> Result<HttpResponse> {
+    let mut assembler = program.assembler();
+    match assembler.assemble(&program.code) {
+        Ok(instructions) => {
+            let cfg = ControlFlowGraph::build(&instructions, assembler.labels());
+            Ok(HttpResponse::Ok().content_type("text/vnd.graphviz").body(cfg.to_dot()))
+        }
+        Err(errors) => Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Assembly failed with {} error(s)", errors.errors().count()),
+            "diagnostics": errors,
+        }))),
     }
 }
 
@@ -78,7 +209,7 @@ async fn step(data: web::Data<AppState>) -> Result<HttpResponse> {
     if let Some(vm) = vm_state.as_mut() {
         println!("VM found, executing step");
         match vm.step() {
-            Ok(continue_execution) => {
+            Ok(_) => {
                 let state = vm.get_state();
                 let mut response = VMStateResponse::from(state);
                 let output = vm.take_output();
@@ -98,6 +229,107 @@ async fn step(data: web::Data<AppState>) -> Result<HttpResponse> {
     }
 }
 
+// Run until a breakpoint, watchpoint, halt, error or the step limit
+async fn continue_execution(data: web::Data<AppState>) -> Result<HttpResponse> {
+    let mut vm_state = data.vm.lock().unwrap();
+
+    if let Some(vm) = vm_state.as_mut() {
+        let stop = vm.run_with_limit(CONTINUE_STEP_LIMIT);
+        let mut response = VMStateResponse::from(vm.get_state());
+        response.output = vm.take_output();
+        response.stop = Some(stop);
+        Ok(HttpResponse::Ok().json(response))
+    } else {
+        Ok(HttpResponse::BadRequest().body("No program loaded"))
+    }
+}
+
+// Undo the last instruction executed
+async fn step_back(data: web::Data<AppState>) -> Result<HttpResponse> {
+    let mut vm_state = data.vm.lock().unwrap();
+
+    if let Some(vm) = vm_state.as_mut() {
+        if !vm.step_back() {
+            return Ok(HttpResponse::BadRequest().body("No earlier history"));
+        }
+        let mut response = VMStateResponse::from(vm.get_state());
+        response.output = vm.take_output();
+        Ok(HttpResponse::Ok().json(response))
+    } else {
+        Ok(HttpResponse::BadRequest().body("No program loaded"))
+    }
+}
+
+// Run backwards to a breakpoint, the last change a watch sees or the oldest recorded state
+async fn reverse_continue(data: web::Data<AppState>) -> Result<HttpResponse> {
+    let mut vm_state = data.vm.lock().unwrap();
+
+    if let Some(vm) = vm_state.as_mut() {
+        let stop = vm.reverse_continue();
+        let mut response = VMStateResponse::from(vm.get_state());
+        response.output = vm.take_output();
+        response.stop = Some(stop);
+        Ok(HttpResponse::Ok().json(response))
+    } else {
+        Ok(HttpResponse::BadRequest().body("No program loaded"))
+    }
+}
+
+async fn add_breakpoint(
+    data: web::Data<AppState>,
+    request: web::Json<BreakpointRequest>,
+) -> Result<HttpResponse> {
+    let mut vm_state = data.vm.lock().unwrap();
+
+    if let Some(vm) = vm_state.as_mut() {
+        if request.address >= vm.get_state().instructions().len() {
+            return Ok(HttpResponse::BadRequest().body(format!("No instruction at {}", request.address)));
+        }
+        vm.add_breakpoint(request.address);
+        Ok(HttpResponse::Ok().json(vm.breakpoints()))
+    } else {
+        Ok(HttpResponse::BadRequest().body("No program loaded"))
+    }
+}
+
+async fn remove_breakpoint(data: web::Data<AppState>, address: web::Path<usize>) -> Result<HttpResponse> {
+    let mut vm_state = data.vm.lock().unwrap();
+
+    if let Some(vm) = vm_state.as_mut() {
+        if !vm.remove_breakpoint(*address) {
+            return Ok(HttpResponse::NotFound().body(format!("No breakpoint at {}", address)));
+        }
+        Ok(HttpResponse::Ok().json(vm.breakpoints()))
+    } else {
+        Ok(HttpResponse::BadRequest().body("No program loaded"))
+    }
+}
+
+// Watches are sent as {"memory": "x"}, {"heap": 1} or {"stack_depth": 8}
+async fn add_watch(data: web::Data<AppState>, watch: web::Json<Watch>) -> Result<HttpResponse> {
+    let mut vm_state = data.vm.lock().unwrap();
+
+    if let Some(vm) = vm_state.as_mut() {
+        let id = vm.add_watch(watch.into_inner());
+        Ok(HttpResponse::Ok().json(serde_json::json!({ "id": id, "watches": vm.watches() })))
+    } else {
+        Ok(HttpResponse::BadRequest().body("No program loaded"))
+    }
+}
+
+async fn remove_watch(data: web::Data<AppState>, id: web::Path<usize>) -> Result<HttpResponse> {
+    let mut vm_state = data.vm.lock().unwrap();
+
+    if let Some(vm) = vm_state.as_mut() {
+        if !vm.remove_watch(*id) {
+            return Ok(HttpResponse::NotFound().body(format!("No watch {}", id)));
+        }
+        Ok(HttpResponse::Ok().json(vm.watches()))
+    } else {
+        Ok(HttpResponse::BadRequest().body("No program loaded"))
+    }
+}
+
 async fn reset(data: web::Data<AppState>) -> Result<HttpResponse> {
     let mut vm_state = data.vm.lock().unwrap();
     *vm_state = None;
@@ -117,6 +349,10 @@ async fn get_state(data: web::Data<AppState>) -> Result<HttpResponse> {
     }
 }
 
+async fn instructions() -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(OPCODES))
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     println!("Starting VM server on http://127.0.0.1:3001");
@@ -136,9 +372,19 @@ async fn main() -> std::io::Result<()> {
             .service(
                 web::scope("/api")
                     .route("/load", web::post().to(load_program))
+                    .route("/load/bytecode", web::post().to(load_bytecode))
                     .route("/step", web::post().to(step))
+                    .route("/continue", web::post().to(continue_execution))
+                    .route("/step-back", web::post().to(step_back))
+                    .route("/reverse-continue", web::post().to(reverse_continue))
+                    .route("/breakpoints", web::post().to(add_breakpoint))
+                    .route("/breakpoints/{address}", web::delete().to(remove_breakpoint))
+                    .route("/watches", web::post().to(add_watch))
+                    .route("/watches/{id}", web::delete().to(remove_watch))
                     .route("/reset", web::post().to(reset))
                     .route("/state", web::get().to(get_state))
+                    .route("/instructions", web::get().to(instructions))
+                    .route("/cfg", web::post().to(control_flow_graph))
             )
     })
         .bind("127.0.0.1:3001")?
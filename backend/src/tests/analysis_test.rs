@@ -0,0 +1,182 @@
+#[cfg(test)]
+mod tests {
+    use crate::core::analysis::{analyze_stack, FunctionSummary};
+    use crate::core::assembler::Assembler;
+    use crate::core::error::{Severity, StackWarning};
+    use crate::tests::assemble;
+
+    #[test]
+    fn test_depths_and_maximum() {
+        const SOURCE: &str = r#"
+            PUSH 1
+            PUSH 2
+            2DUP
+            ADD
+            PICK 2
+            DEPTH
+            2DROP
+            2DROP
+        "#;
+
+        let analysis = analyze_stack(&assemble(SOURCE));
+        let depths: Vec<isize> = analysis.depths.iter().map(|d| d.unwrap()).collect();
+        assert_eq!(depths, vec![0, 1, 2, 4, 3, 4, 5, 3]);
+        assert_eq!(analysis.max_depth, Some(5));
+        assert_eq!(analysis.warnings, vec![]);
+    }
+
+    #[test]
+    fn test_depth_mismatch_at_merge() {
+        const SOURCE: &str = r#"
+            PUSH 1
+            JMPZ skip
+            PUSH 2
+            skip: PUSH 3
+            ADD
+            ADD
+        "#;
+
+        // The path that skips `PUSH 2` reaches the merge first and then underflows
+        let analysis = analyze_stack(&assemble(SOURCE));
+        assert_eq!(analysis.warnings, vec![
+            StackWarning::DepthMismatch { address: 3, expected: 0, found: 1 },
+            StackWarning::Underflow { address: 4, depth: 1, needed: 2 },
+        ]);
+    }
+
+    #[test]
+    fn test_guaranteed_underflow() {
+        let analysis = analyze_stack(&assemble("PUSH 1\nADD\nPRINT"));
+        assert_eq!(analysis.warnings, vec![StackWarning::Underflow { address: 1, depth: 1, needed: 2 }]);
+        assert_eq!(analysis.depths[2], None);
+    }
+
+    #[test]
+    fn test_loops_with_balanced_bodies() {
+        const SOURCE: &str = r#"
+            PUSH 3
+            STORE n
+            loop: LOAD n
+            PUSH 1
+            SUB
+            DUP
+            STORE n
+            DUP
+            PRINT
+            JMPNZ loop
+            HALT
+            PUSH 99
+        "#;
+
+        let analysis = analyze_stack(&assemble(SOURCE));
+        assert_eq!(analysis.warnings, vec![]);
+        assert_eq!(analysis.depths[2], Some(0));
+        assert_eq!(analysis.depths[11], None);
+        assert_eq!(analysis.max_depth, Some(2));
+    }
+
+    #[test]
+    fn test_growing_loops_are_unbounded() {
+        let analysis = analyze_stack(&assemble("loop: PUSH 1\nJMP loop"));
+        assert_eq!(analysis.max_depth, None);
+        assert_eq!(analysis.warnings, vec![StackWarning::DepthMismatch { address: 0, expected: 0, found: 1 }]);
+
+        // Calling a function whose body grows without bound is unbounded too
+        const SOURCE: &str = r#"
+            CALL grow
+            HALT
+            FUNC grow 0
+            BEGIN
+            top: PUSH 1
+            JMP top
+            END
+        "#;
+        let analysis = analyze_stack(&assemble(SOURCE));
+        assert_eq!(analysis.functions["grow"].max_depth, None);
+        assert_eq!(analysis.max_depth, None);
+
+        // Paths that merge at different depths without looping stay bounded
+        let analysis = analyze_stack(&assemble("PUSH 1\nJMPZ skip\nPUSH 2\nskip: PUSH 3\nHALT"));
+        assert!(analysis.max_depth.is_some());
+    }
+
+    #[test]
+    fn test_function_summaries() {
+        const SOURCE: &str = r#"
+            PUSH 5
+            CALL fact
+            PRINT
+            PUSH 1
+            PUSH 2
+            CALL add_below
+            PRINT
+            HALT
+
+            FUNC fact 1
+            BEGIN
+            PARAM 0
+            PUSH 1
+            LE
+            JMPZ recurse
+            PUSH 1
+            RET
+            recurse: PARAM 0
+            PARAM 0
+            PUSH 1
+            SUB
+            CALL fact
+            MUL
+            RET
+            END
+
+            FUNC add_below 1
+            BEGIN
+            PARAM 0
+            ADD
+            RET
+            END
+        "#;
+
+        let analysis = analyze_stack(&assemble(SOURCE));
+        assert_eq!(analysis.warnings, vec![]);
+        assert_eq!(analysis.functions["fact"].returns, Some(1));
+        assert_eq!(analysis.functions["fact"].call_effect(), Some((1, 1)));
+        assert_eq!(analysis.functions["add_below"], FunctionSummary {
+            params: 1,
+            borrowed: 1,
+            returns: Some(0),
+            max_depth: Some(1),
+        });
+        assert_eq!(analysis.functions["add_below"].call_effect(), Some((2, 1)));
+        assert_eq!(analysis.depths[6], Some(1));
+        assert!(analysis.max_depth.unwrap() >= 2);
+    }
+
+    #[test]
+    fn test_call_consumes_more_than_the_stack_holds() {
+        const SOURCE: &str = r#"
+            CALL pair
+            HALT
+            FUNC pair 2
+            BEGIN
+            END
+        "#;
+
+        let analysis = analyze_stack(&assemble(SOURCE));
+        assert_eq!(analysis.warnings, vec![StackWarning::Underflow { address: 0, depth: 0, needed: 2 }]);
+    }
+
+    #[test]
+    fn test_assembler_reports_warnings() {
+        const SOURCE: &str = "PUSH 1\n  ADD\nPRINT";
+
+        let mut assembler = Assembler::new();
+        assert!(assembler.assemble(SOURCE).is_ok());
+
+        let warnings = assembler.diagnostics();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].severity, Severity::Warning);
+        assert_eq!((warnings[0].line, warnings[0].column, warnings[0].token.as_str()), (2, 3, "ADD"));
+        assert_eq!(warnings[0].message, "stack underflow: needs 2 value(s) but the stack holds 1");
+    }
+}
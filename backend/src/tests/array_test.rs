@@ -2,6 +2,7 @@ use crate::core::vm::VM;
 use crate::core::assembler::Assembler;
 use crate::core::state::DebugOptions;
 use crate::core::error::VMError;
+use crate::core::value::Value;
 
 struct VMTester {
     vm: VM,
@@ -54,7 +55,8 @@ impl VMTester {
         self.all_output.join("")
     }
 
-    fn get_memory(&self) -> &std::collections::HashMap<String, i64> {
+    #[allow(dead_code)]
+    fn get_memory(&self) -> &std::collections::HashMap<String, Value> {
         self.vm.get_memory()
     }
 }
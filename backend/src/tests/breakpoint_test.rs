@@ -0,0 +1,133 @@
+use super::VMTester;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::error::VMError;
+    use crate::core::heap::HeapValue;
+    use crate::core::state::{StopReason, Watch, WatchEvent};
+    use crate::core::value::Value;
+
+    const SOURCE: &str = r#"
+        PUSH 0
+        STORE i
+    loop:
+        LOAD i
+        PUSH 1
+        ADD
+        DUP
+        STORE i
+        PUSH 3
+        LT
+        JMP_IF loop
+        HALT
+    "#;
+
+    fn load(source: &str) -> VMTester {
+        VMTester::new(source, false).expect("program should assemble")
+    }
+
+    #[test]
+    fn test_breakpoints_stop_before_their_instruction() {
+        let mut tester = load(SOURCE);
+        let vm = tester.vm_mut();
+        assert!(vm.add_breakpoint(2));
+        assert!(!vm.add_breakpoint(2));
+
+        for i in 0..3 {
+            assert!(matches!(vm.run_until_stop(), StopReason::Breakpoint { address: 2 }));
+            assert_eq!(vm.get_memory()["i"], Value::Int(i));
+        }
+        assert!(matches!(vm.run_until_stop(), StopReason::Halted));
+
+        assert!(vm.remove_breakpoint(2));
+        assert!(!vm.remove_breakpoint(2));
+        assert!(vm.breakpoints().is_empty());
+    }
+
+    #[test]
+    fn test_breakpoint_on_the_first_instruction() {
+        let mut tester = load(SOURCE);
+        let vm = tester.vm_mut();
+        vm.add_breakpoint(0);
+        assert!(matches!(vm.step_checked(), Some(StopReason::Breakpoint { address: 0 })));
+        // Resuming executes the instruction under the breakpoint
+        assert!(vm.step_checked().is_none());
+        assert_eq!(vm.get_state().program_counter, 1);
+    }
+
+    #[test]
+    fn test_memory_watch() {
+        let mut tester = load(SOURCE);
+        let vm = tester.vm_mut();
+        let id = vm.add_watch(Watch::Memory("i".to_string()));
+
+        let StopReason::Watch { id: hit, address, event } = vm.run_until_stop() else {
+            panic!("expected the watch to trigger");
+        };
+        assert_eq!((hit, address), (id, 1));
+        assert_eq!(event, WatchEvent::Memory { name: "i".to_string(), old: None, new: Some(Value::Int(0)) });
+        assert_eq!(event.to_string(), "i: unset -> 0");
+
+        let StopReason::Watch { address, event, .. } = vm.run_until_stop() else {
+            panic!("expected the watch to trigger");
+        };
+        assert_eq!(address, 6);
+        assert_eq!(event.to_string(), "i: 0 -> 1");
+
+        assert!(vm.remove_watch(id));
+        assert!(!vm.remove_watch(id));
+        assert!(matches!(vm.run_until_stop(), StopReason::Halted));
+    }
+
+    #[test]
+    fn test_heap_watch() {
+        const SOURCE: &str = r#"
+            PUSH 2
+            NEWARRAY
+            DUP
+            PUSH 0
+            PUSH 7
+            ARRAYSET
+            FREEARR
+        "#;
+        let mut tester = load(SOURCE);
+        let vm = tester.vm_mut();
+        // Ids are watched before they are allocated
+        vm.add_watch(Watch::Heap(1));
+
+        let StopReason::Watch { event, .. } = vm.run_until_stop() else { panic!("expected an allocation") };
+        assert_eq!(event, WatchEvent::HeapAllocated { id: 1, value: HeapValue::Array(vec![Value::Int(0), Value::Int(0)]) });
+
+        let StopReason::Watch { event, .. } = vm.run_until_stop() else { panic!("expected a mutation") };
+        assert_eq!(event.to_string(), "&1: [0, 0] -> [7, 0]");
+
+        let StopReason::Watch { event, .. } = vm.run_until_stop() else { panic!("expected a free") };
+        assert_eq!(event.to_string(), "&1 freed: [7, 0]");
+        assert!(matches!(vm.run_until_stop(), StopReason::Halted));
+    }
+
+    #[test]
+    fn test_stack_depth_watch() {
+        let mut tester = load("PUSH 1\nPUSH 2\nPUSH 3\nPOP\nPUSH 4\nPOP\nPOP\nPOP");
+        let vm = tester.vm_mut();
+        vm.add_watch(Watch::StackDepth(3));
+
+        let StopReason::Watch { address, event, .. } = vm.run_until_stop() else { panic!("expected the depth") };
+        assert_eq!((address, event), (2, WatchEvent::StackDepth { depth: 3 }));
+        // Only growing to the depth again triggers it
+        let StopReason::Watch { address, .. } = vm.run_until_stop() else { panic!("expected the depth") };
+        assert_eq!(address, 4);
+        assert!(matches!(vm.run_until_stop(), StopReason::Halted));
+    }
+
+    #[test]
+    fn test_errors_and_step_limits() {
+        let mut tester = load("POP");
+        let StopReason::Error { error } = tester.vm_mut().run_until_stop() else { panic!("expected an error") };
+        assert!(matches!(error, VMError::StackUnderflow));
+
+        let mut tester = load("spin: JMP spin");
+        assert!(matches!(tester.vm_mut().run_with_limit(50), StopReason::StepLimit));
+    }
+}
@@ -0,0 +1,143 @@
+#[cfg(test)]
+mod tests {
+    use crate::core::assembler::Assembler;
+    use crate::core::bytecode::{self, MAGIC, VERSION};
+    use crate::core::error::BytecodeError;
+    use crate::core::instruction::Instruction;
+    use crate::core::value::Value;
+    use crate::core::vm::VM;
+    use crate::tests::every_instruction;
+
+    const SOURCE: &str = r#"
+        FUNC square 1
+        BEGIN
+        PARAM 0
+        PARAM 0
+        MUL
+        RET
+        END
+        PUSH -12
+        CALL square
+        STORE result
+        LOAD result
+        PRINT
+        NEWSTR "done"
+        PRINTSTR "result"
+    "#;
+
+    fn program() -> Vec<Instruction> {
+        Assembler::new().assemble(SOURCE).expect("Failed to assemble")
+    }
+
+    #[test]
+    fn test_every_opcode_round_trips() {
+        let program = every_instruction(8);
+
+        let bytes = bytecode::encode(&program).unwrap();
+        assert_eq!(&bytes[..4], MAGIC);
+        assert_eq!(bytecode::decode(&bytes), Ok(program));
+    }
+
+    #[test]
+    fn test_strings_are_pooled() {
+        let once = bytecode::encode(&[Instruction::Load("counter".to_string())]).unwrap();
+        let program = vec![
+            Instruction::Load("counter".to_string()),
+            Instruction::Store("counter".to_string()),
+            Instruction::NewString("counter".to_string()),
+        ];
+        let bytes = bytecode::encode(&program).unwrap();
+
+        assert_eq!(bytes.windows(7).filter(|w| w == b"counter").count(), 1);
+        assert_eq!(bytes.len(), once.len() + 4);
+        assert_eq!(bytecode::decode(&bytes), Ok(program));
+    }
+
+    #[test]
+    fn test_decoded_program_runs() {
+        let decoded = bytecode::decode(&bytecode::encode(&program()).unwrap()).unwrap();
+        let mut vm = VM::new(decoded);
+        while vm.step().expect("Failed to execute program") {}
+        assert_eq!(vm.take_output(), vec!["144".to_string(), "result".to_string()]);
+    }
+
+    #[test]
+    fn test_rejects_bad_files() {
+        let bytes = bytecode::encode(&program()).unwrap();
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        assert_eq!(bytecode::decode(&bad_magic), Err(BytecodeError::BadMagic));
+
+        let mut future = bytes.clone();
+        future[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert_eq!(bytecode::decode(&future), Err(BytecodeError::UnsupportedVersion(VERSION + 1, VERSION)));
+
+        let mut corrupted = bytes.clone();
+        corrupted[10] ^= 0xff;
+        assert!(matches!(bytecode::decode(&corrupted), Err(BytecodeError::ChecksumMismatch { .. })));
+
+        assert_eq!(bytecode::decode(&bytes[..5]), Err(BytecodeError::UnexpectedEnd));
+        assert!(bytecode::decode(&bytes[..bytes.len() - 1]).is_err());
+        assert_eq!(bytecode::decode(b""), Err(BytecodeError::UnexpectedEnd));
+
+        let huge = bytecode::encode(&[Instruction::Pick(usize::MAX)]).unwrap();
+        assert_eq!(bytecode::decode(&huge), Err(BytecodeError::CountTooLarge(usize::MAX)));
+        let largest = vec![Instruction::Roll(i64::MAX as usize)];
+        assert_eq!(bytecode::decode(&bytecode::encode(&largest).unwrap()), Ok(largest));
+    }
+
+    /// Replace the checksum after editing the bytes before it
+    fn reseal(mut bytes: Vec<u8>) -> Vec<u8> {
+        bytes.truncate(bytes.len() - 4);
+        let checksum = bytes.iter().fold(0x811c_9dc5u32, |hash, &byte| (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193));
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn test_heap_references_are_not_stored() {
+        assert_eq!(bytecode::encode(&[Instruction::Push(Value::Ref(3))]), Err(BytecodeError::HeapReference));
+
+        // PUSH nil with its tag changed to the one references used to have
+        let mut bytes = bytecode::encode(&[Instruction::Push(Value::Nil)]).unwrap();
+        let tag = bytes.len() - 5;
+        bytes[tag] = 4;
+        assert_eq!(bytecode::decode(&reseal(bytes)), Err(BytecodeError::InvalidValueTag(4)));
+    }
+
+    #[test]
+    fn test_names_must_be_identifiers() {
+        for name in ["a b", "3x", ""] {
+            let bytes = bytecode::encode(&[Instruction::Load(name.to_string())]).unwrap();
+            assert_eq!(bytecode::decode(&bytes), Err(BytecodeError::InvalidName(name.to_string())));
+        }
+        let program = vec![Instruction::Call("main.loop".to_string())];
+        assert_eq!(bytecode::decode(&bytecode::encode(&program).unwrap()), Ok(program));
+    }
+
+    #[test]
+    fn test_overlong_varints_are_rejected() {
+        // A pool length whose tenth byte sets bit 64, which does not exist
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x02]);
+        bytes.extend_from_slice(&[0, 0, 0, 0, 0]);
+        assert_eq!(bytecode::decode(&reseal(bytes.clone())), Err(BytecodeError::NumberTooLarge));
+
+        // Bit 63 alone is in range, so decoding only fails on the missing pool
+        bytes[15] = 0x01;
+        assert_eq!(bytecode::decode(&reseal(bytes)), Err(BytecodeError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("vm_bytecode_test_{}.svmb", std::process::id()));
+        bytecode::save(&path, &program()).expect("Failed to save bytecode");
+        let loaded = bytecode::load(&path);
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(loaded, Ok(program()));
+        assert!(matches!(bytecode::load(&path), Err(BytecodeError::IOError(_))));
+    }
+}
@@ -0,0 +1,118 @@
+#[cfg(test)]
+mod tests {
+    use crate::core::assembler::Assembler;
+    use crate::core::cfg::{ControlFlowGraph, Loop};
+
+    fn build(source: &str) -> ControlFlowGraph {
+        let mut assembler = Assembler::new();
+        let program = assembler.assemble(source).expect("Failed to assemble");
+        ControlFlowGraph::build(&program, assembler.labels())
+    }
+
+    const COUNTDOWN: &str = r#"
+        PUSH 3
+        STORE n
+        loop: LOAD n
+        JMPZ done
+        LOAD n
+        PRINT
+        LOAD n
+        PUSH 1
+        SUB
+        STORE n
+        JMP loop
+        PRINTSTR "never"
+        done: HALT
+    "#;
+
+    #[test]
+    fn test_blocks_split_at_labels_and_jumps() {
+        let cfg = build(COUNTDOWN);
+        let ranges: Vec<(usize, usize)> = cfg.blocks.iter().map(|b| (b.start, b.end)).collect();
+        assert_eq!(ranges, vec![(0, 2), (2, 4), (4, 11), (11, 12), (12, 13)]);
+
+        let successors: Vec<Vec<usize>> = cfg.blocks.iter().map(|b| b.successors.clone()).collect();
+        assert_eq!(successors, vec![vec![1], vec![4, 2], vec![1], vec![4], vec![]]);
+        assert_eq!(cfg.blocks[1].predecessors, vec![0, 2]);
+        assert_eq!(cfg.blocks[4].predecessors, vec![1, 3]);
+        assert_eq!(cfg.block_at(7).map(|b| b.id), Some(2));
+    }
+
+    #[test]
+    fn test_unreachable_blocks_and_loops() {
+        let cfg = build(COUNTDOWN);
+        assert_eq!(cfg.unreachable_blocks(), vec![3]);
+        assert_eq!(cfg.loops, vec![Loop { header: 1, latches: vec![2], blocks: vec![1, 2] }]);
+    }
+
+    #[test]
+    fn test_nested_loops() {
+        const SOURCE: &str = r#"
+            PUSH 0
+            STORE i
+            outer: PUSH 0
+            STORE j
+            inner: LOAD j
+            PUSH 1
+            ADD
+            DUP
+            STORE j
+            PUSH 2
+            LT
+            JMPNZ inner
+            LOAD i
+            PUSH 1
+            ADD
+            DUP
+            STORE i
+            PUSH 2
+            LT
+            JMPNZ outer
+        "#;
+
+        let cfg = build(SOURCE);
+        let headers: Vec<String> = cfg.loops.iter().map(|l| cfg.block_name(l.header)).collect();
+        assert_eq!(headers, vec!["outer", "inner"]);
+        assert_eq!(cfg.loops[0].blocks, vec![1, 2, 3]);
+        assert_eq!(cfg.loops[1].blocks, vec![2]);
+        assert_eq!(cfg.unreachable_blocks(), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_function_bodies_are_reachable_through_calls() {
+        const SOURCE: &str = r#"
+            PUSH 2
+            CALL double
+            PRINT
+            HALT
+            FUNC double 1
+            BEGIN
+            PARAM 0
+            PUSH 2
+            MUL
+            RET
+            END
+            FUNC unused 0
+            BEGIN
+            RET
+            END
+        "#;
+
+        let cfg = build(SOURCE);
+        let unreachable: Vec<usize> = cfg.unreachable_blocks().iter().map(|&id| cfg.blocks[id].start).collect();
+        assert_eq!(unreachable, vec![4, 10, 11, 12, 14]);
+    }
+
+    #[test]
+    fn test_dot_export_uses_label_names() {
+        let dot = build(COUNTDOWN).to_dot();
+        assert!(dot.starts_with("digraph cfg {\n"));
+        assert!(dot.contains("b1 [label=\"loop:\\l   2  LOAD n\\l   3  JMPZ done\\l\"];"));
+        assert!(dot.contains("  10  JMP loop\\l"));
+        assert!(dot.contains("b3 [label=\"B3:\\l  11  PRINTSTR \\\"never\\\"\\l\", style=dashed, color=gray];"));
+        assert!(dot.contains("b1 -> b4 [label=\"false\"];"));
+        assert!(dot.contains("b1 -> b2 [label=\"true\"];"));
+        assert!(dot.contains("b2 -> b1 [style=bold];"));
+        assert!(dot.ends_with("}\n"));
+    }
+}
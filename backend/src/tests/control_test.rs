@@ -39,10 +39,13 @@ mod tests {
         end:    HALT
         "#;
 
-        let mut tester = VMTester::new(SOURCE, false)
-            .expect("Failed to create VM tester");
-
-        tester.run().expect("Failed to execute program");
-        assert_eq!(tester.get_output(), "1\n0\n1\n");
+        let testers = [
+            VMTester::new(SOURCE, false).expect("Failed to create VM tester"),
+            VMTester::optimized(SOURCE, false).expect("Failed to create optimized VM tester"),
+        ];
+        for mut tester in testers {
+            tester.run().expect("Failed to execute program");
+            assert_eq!(tester.get_output(), "1\n0\n1\n");
+        }
     }
 }
\ No newline at end of file
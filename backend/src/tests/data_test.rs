@@ -0,0 +1,113 @@
+use super::{diagnostics, VMTester};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::assembler::Assembler;
+    use crate::core::heap::HeapValue;
+    use crate::core::state::DataItem;
+    use crate::core::value::Value;
+
+    #[test]
+    fn test_constants() {
+        const SOURCE: &str = r#"
+            .const LIMIT 3
+            .const RATE 1.5
+            .const START LIMIT   // constants may refer to earlier ones
+            .const DEBUG false
+            .const BACK 2
+
+            PUSH START
+            PUSH LIMIT
+            MUL
+            PRINT
+            PUSH RATE
+            PRINT
+            PUSH DEBUG
+            PRINT
+            PUSH 7
+            PUSH 8
+            PUSH 9
+            PICK BACK
+            HALT
+        "#;
+
+        let mut assembler = Assembler::new();
+        assembler.assemble(SOURCE).unwrap();
+        assert_eq!(assembler.constants().get("START"), Some(&Value::Int(3)));
+
+        let mut tester = VMTester::new(SOURCE, false).unwrap();
+        tester.run().unwrap();
+        assert_eq!(tester.get_output(), "91.5false");
+        assert_eq!(tester.get_stack().last(), Some(&Value::Int(7)));
+    }
+
+    #[test]
+    fn test_data_section() {
+        const SOURCE: &str = r#"
+            .const SCALE 10
+            .data
+            greeting: "Hello, data"
+            squares: 0, 1, 4, 9, 16
+            scaled: SCALE 20 SCALE   // operands may be spaced instead of comma separated
+            .text
+
+            LOAD greeting
+            PRINT
+            LOAD squares
+            PUSH 3
+            ARRAYGET
+            PRINT
+            LOAD squares
+            ARRAYLEN
+            PRINT
+            HALT
+        "#;
+
+        let mut assembler = Assembler::new();
+        assembler.assemble(SOURCE).unwrap();
+        assert_eq!(assembler.data()[0], DataItem {
+            name: "greeting".to_string(),
+            value: HeapValue::String("Hello, data".to_string()),
+        });
+        assert_eq!(assembler.data()[1].value,
+                   HeapValue::Array([0, 1, 4, 9, 16].map(Value::Int).to_vec()));
+
+        let mut tester = VMTester::new(SOURCE, false).unwrap();
+        tester.run().unwrap();
+        assert_eq!(tester.get_output(), "Hello, data95");
+        assert!(matches!(tester.get_memory().get("squares"), Some(Value::Ref(_))));
+    }
+
+    #[test]
+    fn test_constant_and_data_errors() {
+        const SOURCE: &str = r#".const SIZE 4
+.const SIZE 5
+.const nil 1
+.const WORD "text"
+PUSH SIZ
+PICK WORD
+.data
+table: 1, "two"
+PUSH 1
+.bss"#;
+
+        let errors = diagnostics(SOURCE);
+        let messages: Vec<(usize, &str)> = errors.iter()
+            .map(|e| (e.line, e.message.as_str()))
+            .collect();
+        assert_eq!(messages, vec![
+            (2, "Duplicate definition: SIZE"),
+            (3, "`nil` is reserved and cannot be redefined"),
+            (4, "Expected a value operand"),
+            (5, "Expected a number, true, false, nil or constant, found SIZ"),
+            (6, "Expected a count operand"),
+            (8, "Expected a value operand"),
+            (9, "Expected a data item"),
+            (10, "Unknown directive: .bss"),
+        ]);
+        assert_eq!(errors[0].help.as_deref(), Some("`SIZE` is first defined on line 1"));
+        assert_eq!(errors[3].help.as_deref(), Some("did you mean `SIZE`?"));
+        assert_eq!((errors[5].column, errors[5].token.as_str()), (11, "\"two\""));
+    }
+}
@@ -0,0 +1,181 @@
+#[cfg(test)]
+mod tests {
+    use crate::core::assembler::Assembler;
+    use crate::core::debugger::Debugger;
+    use crate::core::value::Value;
+
+    const SOURCE: &str = r#"
+        .data
+        msg: "done"
+        .text
+        FUNC square 1
+        BEGIN
+            PARAM 0
+            PARAM 0
+            MUL
+            RET
+        END
+            PUSH 0
+            STORE i
+        loop:
+            LOAD i
+            CALL square
+            PRINT
+            LOAD i
+            PUSH 1
+            ADD
+            DUP
+            STORE i
+            PUSH 3
+            LT
+            JMP_IF loop
+            HALT
+    "#;
+
+    fn debugger() -> Debugger {
+        let mut assembler = Assembler::new();
+        let instructions = assembler.assemble(SOURCE).expect("program should assemble");
+        Debugger::new(instructions, assembler.data().to_vec(), assembler.labels().clone())
+    }
+
+    #[test]
+    fn test_breakpoints_by_label_function_and_address() {
+        let mut debugger = debugger();
+        assert_eq!(debugger.execute("break loop"), "Breakpoint 1 at 9 <loop>");
+        assert_eq!(debugger.execute("break square"), "Breakpoint 2 at 1 <square+1>");
+        assert_eq!(debugger.execute("b 8"), "Breakpoint 3 at 8");
+        assert_eq!(debugger.execute("break nowhere"), "No label or function named nowhere");
+        assert_eq!(debugger.execute("break 99"), "Address 99 is outside the program (0..21)");
+        assert_eq!(
+            debugger.execute("info breakpoints"),
+            "Num  Type        What\n1    breakpoint  9 <loop>\n2    breakpoint  1 <square+1>\n3    breakpoint  8"
+        );
+        assert_eq!(debugger.execute("delete 3"), "Deleted breakpoint 3");
+        assert_eq!(debugger.execute("delete 3"), "No breakpoint or watchpoint number 3");
+
+        assert_eq!(debugger.execute("run"), "Breakpoint 1, 9 <loop>: LOAD i");
+        assert_eq!(debugger.execute("continue"), "Breakpoint 2, 1 <square+1>: BEGIN");
+        // Output produced on the way is shown before the stop
+        assert_eq!(debugger.execute("delete 2"), "Deleted breakpoint 2");
+        assert_eq!(debugger.execute("c"), "0\nBreakpoint 1, 9 <loop>: LOAD i");
+    }
+
+    #[test]
+    fn test_step_enters_calls_and_next_runs_them() {
+        let mut debugger = debugger();
+        debugger.execute("break 10");
+        debugger.execute("run");
+        assert_eq!(debugger.execute("step"), "1 <square+1>: BEGIN");
+        assert_eq!(debugger.execute("bt"), "#0  square(0) at 1 <square+1>\n#1  <top level> at 10 <loop+1>");
+
+        debugger.execute("run");
+        assert_eq!(debugger.execute("next"), "11 <loop+2>: PRINT");
+        assert_eq!(debugger.vm().get_state().stack, vec![Value::Int(0)]);
+        // An empty line repeats the last movement
+        assert_eq!(debugger.execute(""), "0\n12 <loop+3>: LOAD i");
+    }
+
+    #[test]
+    fn test_inspection() {
+        let mut debugger = debugger();
+        debugger.execute("break loop");
+        debugger.execute("run");
+        debugger.execute("next");
+        assert_eq!(debugger.execute("print i"), "i = 0");
+        assert_eq!(debugger.execute("p msg"), "msg = &1 \"done\"");
+        assert_eq!(debugger.execute("print j"), "No variable named j in the current frame or globals");
+        assert_eq!(debugger.execute("stack"), "stack: [0]");
+        assert_eq!(debugger.execute("heap &1"), "&1 = \"done\"");
+        assert_eq!(debugger.execute("heap 7"), "No heap allocation &7");
+        assert_eq!(debugger.execute("list 2"), [
+            "square:",
+            "     0  FUNC square 1",
+            "     1  BEGIN",
+            "     2  PARAM 0",
+            "     3  PARAM 0",
+            "     4  MUL",
+            "     5  RET",
+            "     6  END",
+            "     7  PUSH 0",
+        ].join("\n"));
+        assert_eq!(debugger.execute("list"), [
+            "     5  RET",
+            "     6  END",
+            "     7  PUSH 0",
+            "     8  STORE i",
+            "loop:",
+            "  *  9  LOAD i",
+            "=>  10  CALL square",
+            "    11  PRINT",
+            "    12  LOAD i",
+            "    13  PUSH 1",
+            "    14  ADD",
+            "    15  DUP",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let mut debugger = debugger();
+        assert_eq!(debugger.execute("watch i"), "Watchpoint 1: i");
+        assert_eq!(debugger.execute("watch depth 2"), "Watchpoint 2: stack depth 2");
+        assert_eq!(debugger.execute("watch"), "usage: watch NAME | watch &ID | watch depth N");
+        assert_eq!(debugger.execute("run"), "Watchpoint 1: i: unset -> 0\n9 <loop>: LOAD i");
+        assert_eq!(debugger.execute("c"), "Watchpoint 2: stack depth reached 2\n4 <square+4>: MUL");
+        assert_eq!(debugger.execute("c"), "0\nWatchpoint 2: stack depth reached 2\n14 <loop+5>: ADD");
+        assert_eq!(debugger.execute("c"), "Watchpoint 2: stack depth reached 2\n16 <loop+7>: STORE i");
+        assert_eq!(debugger.execute("c"), "Watchpoint 1: i: 0 -> 1\n17 <loop+8>: PUSH 3");
+        assert_eq!(debugger.execute("delete 1"), "Deleted watchpoint 1");
+        assert_eq!(
+            debugger.execute("info breakpoints"),
+            "Num  Type        What\n2    watchpoint  stack depth 2"
+        );
+
+        // Watchpoints survive a restart
+        assert_eq!(debugger.execute("run"), "Watchpoint 2: stack depth reached 2\n4 <square+4>: MUL");
+    }
+
+    #[test]
+    fn test_reverse_execution() {
+        let mut debugger = debugger();
+        assert_eq!(debugger.execute("reverse-step"), "No earlier history\n0 <square>: FUNC square 1");
+        debugger.execute("break 17");
+        debugger.execute("run");
+        debugger.execute("c");
+        assert_eq!(debugger.execute("print i"), "i = 2");
+
+        assert_eq!(debugger.execute("rs"), "16 <loop+7>: STORE i");
+        assert_eq!(debugger.execute(""), "15 <loop+6>: DUP");
+        assert_eq!(debugger.execute("print i"), "i = 1");
+        assert_eq!(debugger.execute("rc"), "Breakpoint 1, 17 <loop+8>: PUSH 3");
+        assert_eq!(debugger.execute("print i"), "i = 1");
+
+        debugger.execute("delete 1");
+        debugger.execute("watch i");
+        assert_eq!(debugger.execute("rc"), "Watchpoint 2: i: 0 -> 1\n16 <loop+7>: STORE i");
+        assert_eq!(debugger.execute("rc"), "Watchpoint 2: i: unset -> 0\n8: STORE i");
+
+        // A finished program can be wound back and resumed
+        debugger.execute("delete 2");
+        assert_eq!(debugger.execute("c"), "014\nProgram finished");
+        assert_eq!(debugger.execute("rs"), "20 <loop+11>: HALT");
+        assert_eq!(debugger.execute("c"), "Program finished");
+    }
+
+    #[test]
+    fn test_program_end_and_errors() {
+        let mut debugger = debugger();
+        assert_eq!(debugger.execute("continue"), "014\nProgram finished");
+        assert_eq!(debugger.execute("step"), "The program is not being run; use run to start it again");
+        assert_eq!(debugger.execute("run"), "014\nProgram finished");
+
+        let mut assembler = Assembler::new();
+        let instructions = assembler.assemble("PUSH 1\nstart: POP\nPOP").unwrap();
+        let mut failing = Debugger::new(instructions, Vec::new(), assembler.labels().clone());
+        assert_eq!(failing.execute("run"), "error: Stack underflow\n2 <start+1>: POP");
+        assert_eq!(failing.execute("frobnicate"), "unknown command frobnicate; try help");
+        assert!(!failing.is_finished());
+        failing.execute("quit");
+        assert!(failing.is_finished());
+    }
+}
@@ -0,0 +1,89 @@
+#[cfg(test)]
+mod tests {
+    use crate::core::assembler::Assembler;
+    use crate::core::error::Severity;
+    use crate::tests::diagnostics;
+
+    #[test]
+    fn test_collects_every_error_in_one_pass() {
+        const SOURCE: &str = r#"
+            PUSH 1
+            PUHS 2
+            ADD 3
+            JMP nowhere
+            PRINT
+        "#;
+
+        let errors = diagnostics(SOURCE);
+        let lines: Vec<usize> = errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec![3, 4, 5]);
+        assert!(errors.iter().all(|e| e.severity == Severity::Error));
+    }
+
+    #[test]
+    fn test_unknown_instruction_suggests_mnemonic() {
+        let errors = diagnostics("PUSH 1\n  PUHS 2");
+        assert_eq!(errors.len(), 1);
+        let error = &errors[0];
+        assert_eq!((error.line, error.column), (2, 3));
+        assert_eq!(error.token, "PUHS");
+        assert_eq!(error.message, "Unknown instruction: PUHS");
+        assert_eq!(error.help.as_deref(), Some("did you mean `PUSH`?"));
+    }
+
+    #[test]
+    fn test_operand_errors_point_at_the_operand() {
+        let errors = diagnostics("PICK -1\nADD 3\nLOAD \"x\"");
+        let located: Vec<(usize, usize, &str)> = errors.iter()
+            .map(|e| (e.line, e.column, e.token.as_str()))
+            .collect();
+        assert_eq!(located, vec![(1, 6, "-1"), (2, 5, "3"), (3, 6, "\"x\"")]);
+        assert_eq!(errors[1].message, "ADD expects 0 operand(s), found 1");
+        assert_eq!(errors[1].help.as_deref(), Some("usage: ADD"));
+        assert_eq!(errors[2].help.as_deref(), Some("usage: LOAD <name>"));
+    }
+
+    #[test]
+    fn test_missing_operand_reports_usage() {
+        let errors = diagnostics("PUSH");
+        assert_eq!(errors[0].token, "PUSH");
+        assert_eq!(errors[0].help.as_deref(), Some("usage: PUSH <value>"));
+    }
+
+    #[test]
+    fn test_label_errors() {
+        const SOURCE: &str = r#"loop: PUSH 1
+loop: POP
+JMP lopo"#;
+
+        let errors = diagnostics(SOURCE);
+        assert_eq!(errors.len(), 2);
+        assert_eq!((errors[0].line, errors[0].column, errors[0].token.as_str()), (2, 1, "loop"));
+        assert_eq!(errors[0].message, "Duplicate label: loop");
+        assert_eq!(errors[0].help.as_deref(), Some("`loop` is first defined on line 1"));
+        assert_eq!((errors[1].line, errors[1].column, errors[1].token.as_str()), (3, 5, "lopo"));
+        assert_eq!(errors[1].message, "Label not found: lopo");
+        assert_eq!(errors[1].help.as_deref(), Some("did you mean `loop`?"));
+    }
+
+    #[test]
+    fn test_unparseable_lines_are_reported() {
+        let errors = diagnostics("PUSH 1 @\n  ???\nPRINT // fine");
+        assert_eq!(errors.len(), 2);
+        assert_eq!((errors[0].line, errors[0].column, errors[0].token.as_str()), (1, 8, "@"));
+        assert_eq!(errors[0].message, "Unexpected input");
+        assert_eq!((errors[1].line, errors[1].column, errors[1].token.as_str()), (2, 3, "???"));
+        assert_eq!(errors[1].message, "Could not parse line");
+    }
+
+    #[test]
+    fn test_diagnostics_serialize_and_display() {
+        let errors = Assembler::new().assemble("PUHS 1").unwrap_err();
+        assert_eq!(errors.to_string(), "1:1: error: Unknown instruction: PUHS");
+
+        let json = serde_json::to_value(&errors).unwrap();
+        assert_eq!(json[0]["line"], 1);
+        assert_eq!(json[0]["severity"], "error");
+        assert_eq!(json[0]["token"], "PUHS");
+    }
+}
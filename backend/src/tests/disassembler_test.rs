@@ -0,0 +1,113 @@
+use super::{assemble, VMTester};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::assembler::Assembler;
+    use crate::core::disassembler::Disassembler;
+    use crate::core::error::DisassemblyError;
+    use crate::core::instruction::Instruction;
+    use crate::core::value::Value;
+
+    const SOURCE: &str = r#"
+        PUSH 0
+        STORE i
+        top: LOAD i
+        PUSH 3
+        LT
+        JMPZ done
+        PRINTSTR "i = \"*\""
+        LOAD i
+        PRINT
+        LOAD i
+        PUSH 1
+        ADD
+        STORE i
+        JMP top
+        done: HALT
+    "#;
+
+    #[test]
+    fn test_labels_are_synthesized_for_jump_targets() {
+        let program = assemble(SOURCE);
+        let source = Disassembler::new().disassemble(&program).unwrap();
+
+        assert!(source.contains("L2:  LOAD i\n"));
+        assert!(source.contains("JMPZ L14\n"));
+        assert!(source.contains("JMP L2\n"));
+        assert!(source.contains("PRINTSTR \"i = \\\"*\\\"\"\n"));
+        assert_eq!(assemble(&source), program);
+    }
+
+    #[test]
+    fn test_disassembled_program_behaves_the_same() {
+        let source = Disassembler::new().disassemble(&assemble(SOURCE)).unwrap();
+
+        let mut original = VMTester::new(SOURCE, false).expect("Failed to create VM tester");
+        let mut round_trip = VMTester::new(&source, false).expect("Failed to create VM tester");
+        original.run().expect("Failed to execute program");
+        round_trip.run().expect("Failed to execute program");
+
+        assert_eq!(round_trip.get_output(), original.get_output());
+    }
+
+    #[test]
+    fn test_non_finite_floats_round_trip() {
+        let program = assemble("PUSH inf\nPUSH -inf\nPUSH nan\nPUSH -1.5\nHALT");
+        assert_eq!(program[0], Instruction::Push(Value::Float(f64::INFINITY)));
+        assert_eq!(program[1], Instruction::Push(Value::Float(f64::NEG_INFINITY)));
+
+        let source = Disassembler::new().disassemble(&program).unwrap();
+        assert!(source.contains("PUSH inf\n"));
+        assert!(source.contains("PUSH -inf\n"));
+        assert!(source.contains("PUSH nan\n"));
+        assert_eq!(Disassembler::new().disassemble(&assemble(&source)).unwrap(), source);
+    }
+
+    #[test]
+    fn test_address_annotations() {
+        let program = assemble(SOURCE);
+        let source = Disassembler::with_addresses().disassemble(&program).unwrap();
+
+        let lines: Vec<&str> = source.lines().collect();
+        assert_eq!(lines.len(), program.len());
+        assert!(lines[0].ends_with("//  0"));
+        assert!(lines[14].starts_with("L14: HALT"));
+        assert!(lines[14].ends_with("// 14"));
+        assert_eq!(assemble(&source), program);
+    }
+
+    #[test]
+    fn test_targets_outside_the_program_stay_numeric() {
+        let program = vec![
+            Instruction::Push(Value::Float(0.5)),
+            Instruction::JumpIf(7),
+            Instruction::NewString("C:\\temp\\".to_string()),
+        ];
+        let source = Disassembler::new().disassemble(&program).unwrap();
+
+        assert_eq!(source, "PUSH 0.5\nJMP_IF 7\nNEWSTR \"C:\\\\temp\\\\\"\n");
+        assert_eq!(assemble(&source), program);
+    }
+
+    #[test]
+    fn test_heap_references_have_no_source_form() {
+        let program = vec![Instruction::Push(Value::Int(1)), Instruction::Push(Value::Ref(3))];
+
+        assert_eq!(Disassembler::new().disassemble(&program),
+                   Err(DisassemblyError::HeapReference { address: 1, value: "&3".to_string() }));
+        assert!(Assembler::new().assemble("PUSH &3").is_err());
+    }
+
+    #[test]
+    fn test_names_must_be_identifiers() {
+        let program = vec![Instruction::Load("a b".to_string())];
+        assert_eq!(Disassembler::new().disassemble(&program),
+                   Err(DisassemblyError::InvalidName { address: 0, name: "a b".to_string() }));
+        assert!(Disassembler::new().disassemble(&[Instruction::Store("3x".to_string())]).is_err());
+
+        let program = vec![Instruction::Load("total_1".to_string()), Instruction::Call("main.loop".to_string())];
+        let source = Disassembler::new().disassemble(&program).unwrap();
+        assert_eq!(assemble(&source), program);
+    }
+}
@@ -0,0 +1,103 @@
+use super::VMTester;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::error::VMError;
+    use crate::core::value::Value;
+
+    #[test]
+    fn test_float_arithmetic() {
+        const SOURCE: &str = r#"
+        // Average of three readings
+        PUSH 2.5
+        PUSH 4.0
+        FADD
+        PUSH 3.5
+        FADD
+        PUSH 3
+        ITOF
+        FDIV
+        DUP
+        PRINT       // Should print 3.3333333333333335
+        PRINTSTR "\n"
+        FTOI
+        PRINT       // Should print 3
+        PRINTSTR "\n"
+        PUSH 1.5e2
+        PRINT       // Should print 150.0
+        PRINTSTR "\n"
+        HALT
+        "#;
+
+        let mut tester = VMTester::new(SOURCE, false)
+            .expect("Failed to create VM tester");
+
+        tester.run().expect("Failed to execute program");
+        assert_eq!(tester.get_output(), "3.3333333333333335\n3\n150.0\n");
+    }
+
+    #[test]
+    fn test_square_root_by_newton() {
+        const SOURCE: &str = r#"
+        // x = x - (x*x - 2) / (2*x), iterated while the estimate keeps moving
+        PUSH 1.0
+        STORE x
+        PUSH 6
+        STORE n
+
+        loop:   LOAD x
+                LOAD x
+                LOAD x
+                FMUL
+                PUSH 2.0
+                FSUB
+                PUSH 2.0
+                LOAD x
+                FMUL
+                FDIV
+                FSUB
+                STORE x
+
+                LOAD n
+                PUSH 1
+                SUB
+                DUP
+                STORE n
+                PUSH 0
+                GT
+                JMPNZ loop
+
+        end:    HALT
+        "#;
+
+        let mut tester = VMTester::new(SOURCE, false)
+            .expect("Failed to create VM tester");
+
+        tester.run().expect("Failed to execute program");
+        match tester.get_memory().get("x") {
+            Some(Value::Float(x)) => assert!((x - std::f64::consts::SQRT_2).abs() < 1e-12),
+            other => panic!("Expected a float, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_float_comparisons_and_errors() {
+        let mut tester = VMTester::new("PUSH 0.1\nPUSH 0.2\nFLT\nPUSH 1.0\nPUSH 1.0\nFGE", false)
+            .expect("Failed to create VM tester");
+        tester.run().expect("Failed to execute program");
+        assert_eq!(tester.get_stack(), &vec![Value::Bool(true), Value::Bool(true)]);
+
+        let mut tester = VMTester::new("PUSH 1.0\nPUSH 2\nFADD", false)
+            .expect("Failed to create VM tester");
+        assert!(matches!(tester.run(), Err(VMError::TypeError(_, _))));
+
+        let mut tester = VMTester::new("PUSH 1.0\nPUSH 0.0\nFDIV", false)
+            .expect("Failed to create VM tester");
+        assert!(matches!(tester.run(), Err(VMError::DivisionByZero)));
+
+        let mut tester = VMTester::new("PUSH 1e300\nFTOI", false)
+            .expect("Failed to create VM tester");
+        assert!(matches!(tester.run(), Err(VMError::InvalidConversion(_))));
+    }
+}
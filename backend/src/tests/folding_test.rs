@@ -0,0 +1,154 @@
+use super::{assemble, VMTester};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::error::VMError;
+    use crate::core::instruction::Instruction;
+    use crate::core::optimizer::{fold_constants, optimize};
+    use crate::core::value::Value;
+
+    #[test]
+    fn test_folds_constant_expressions() {
+        const SOURCE: &str = r#"
+            PUSH 2
+            PUSH 3
+            MUL
+            PUSH 4
+            ADD
+            PUSH 10
+            LT
+            PRINT
+            PUSH 1.5
+            ITOF
+            PRINT
+        "#;
+
+        let (program, report) = fold_constants(&assemble("PUSH 2\nPUSH 3\nMUL\nPRINT"));
+        assert_eq!(program, vec![Instruction::Push(Value::Int(6)), Instruction::Print]);
+        assert_eq!(report.rewrites["constant-folding"], 1);
+
+        let (program, _) = fold_constants(&assemble(SOURCE));
+        assert_eq!(&program[..2], &[Instruction::Push(Value::Bool(false)), Instruction::Print]);
+        // ITOF on a float is a type error at runtime, so it stays
+        assert_eq!(&program[2..], &[Instruction::Push(Value::Float(1.5)), Instruction::IntToFloat, Instruction::Print]);
+    }
+
+    #[test]
+    fn test_keeps_runtime_errors() {
+        const SOURCE: &str = r#"
+            PUSH 7
+            PUSH 0
+            DIV
+            PUSH 9223372036854775807
+            PUSH 1
+            ADD
+        "#;
+
+        let program = assemble(SOURCE);
+        let (folded, report) = fold_constants(&program);
+        assert_eq!(folded, program);
+        assert!(report.rewrites.is_empty());
+
+        let mut tester = VMTester::optimized(SOURCE, false).expect("Failed to create VM tester");
+        assert!(matches!(tester.run(), Err(VMError::DivisionByZero)));
+    }
+
+    #[test]
+    fn test_resolves_constant_branches_and_removes_dead_code() {
+        const SOURCE: &str = r#"
+            PUSH 1
+            PUSH 2
+            GT
+            JMPZ else
+            PRINTSTR "then"
+            JMP end
+            else: PRINTSTR "else"
+            end: HALT
+            PRINTSTR "after halt"
+        "#;
+
+        let (program, report) = optimize(&assemble(SOURCE));
+        assert_eq!(program, vec![Instruction::PrintStr("else".to_string()), Instruction::Halt]);
+        assert_eq!(report.rewrites["constant-branch"], 1);
+        assert!(report.rewrites["dead-code"] >= 3);
+        assert_eq!(report.after, 2);
+    }
+
+    #[test]
+    fn test_branch_on_value_known_through_merge() {
+        const SOURCE: &str = r#"
+            LOAD input
+            JMPZ left
+            PUSH 1
+            JMP join
+            left: PUSH 1
+            join: JMPNZ yes
+            PRINTSTR "no"
+            yes: PRINTSTR "yes"
+        "#;
+
+        let (program, _) = fold_constants(&assemble(SOURCE));
+        assert!(!program.contains(&Instruction::PrintStr("no".to_string())));
+        assert!(!program.iter().any(|i| matches!(i, Instruction::JumpIfNotZero(_))));
+
+        let mut tester = VMTester::optimized(&format!("PUSH 0\nSTORE input\n{}", SOURCE), false)
+            .expect("Failed to create VM tester");
+        tester.run().expect("Failed to execute program");
+        assert_eq!(tester.get_output(), "yes");
+    }
+
+    #[test]
+    fn test_unknown_conditions_and_called_functions_survive() {
+        const SOURCE: &str = r#"
+            PUSH 4
+            CALL square
+            PRINT
+            HALT
+            FUNC square 1
+            BEGIN
+            PARAM 0
+            PARAM 0
+            MUL
+            RET
+            END
+            FUNC unused 0
+            BEGIN
+            RET
+            END
+        "#;
+
+        let (program, _) = optimize(&assemble(SOURCE));
+        assert!(program.contains(&Instruction::DefineFunction("square".to_string(), 1)));
+        assert!(program.contains(&Instruction::EndFunction));
+        assert!(!program.contains(&Instruction::DefineFunction("unused".to_string(), 0)));
+
+        let mut tester = VMTester::optimized(SOURCE, false).expect("Failed to create VM tester");
+        tester.run().expect("Failed to execute program");
+        assert_eq!(tester.get_output(), "16");
+    }
+
+    #[test]
+    fn test_uncalled_inline_function_is_removed_whole() {
+        const SOURCE: &str = r#"
+            PUSH 1
+            PRINT
+            FUNC unused 0
+            BEGIN
+            PUSH 2
+            PRINT
+            END
+            PUSH 3
+            PRINT
+            HALT
+        "#;
+
+        let (program, _) = optimize(&assemble(SOURCE));
+        assert!(!program.contains(&Instruction::DefineFunction("unused".to_string(), 0)));
+        assert!(!program.contains(&Instruction::EndFunction));
+
+        let mut tester = VMTester::optimized(SOURCE, false).expect("Failed to create VM tester");
+        tester.run().expect("Failed to execute program");
+        assert_eq!(tester.get_output(), "13");
+    }
+}
\ No newline at end of file
@@ -0,0 +1,105 @@
+use super::VMTester;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::error::VMError;
+    use crate::core::value::Value;
+
+    const FACTORIAL_SOURCE: &str = r#"
+        // Recursive factorial of 5
+        PUSH 5
+        CALL fact
+        PRINT
+        PRINTSTR "\n"
+        HALT
+
+        FUNC fact 1
+        BEGIN
+                PARAM 0
+                PUSH 1
+                LE
+                JMPZ recurse
+                PUSH 1
+                RET
+        recurse: PARAM 0
+                PARAM 0
+                PUSH 1
+                SUB
+                CALL fact
+                MUL
+                RET
+        END
+    "#;
+
+    const LOCALS_SOURCE: &str = r#"
+        // Each call gets its own locals; globals are untouched
+        PUSH 7
+        STORE total
+        PUSH 3
+        PUSH 4
+        CALL sum_sq
+        PRINT
+        PRINTSTR "\n"
+        HALT
+
+        FUNC sum_sq 2
+        BEGIN
+                LOCAL total
+                PARAM 0
+                DUP
+                MUL
+                STOREL total
+                PARAM 1
+                DUP
+                MUL
+                LOADL total
+                ADD
+                STOREL total
+                LOADL total
+        END
+    "#;
+
+    #[test]
+    fn test_recursive_function() {
+        let mut tester = VMTester::new(FACTORIAL_SOURCE, false)
+            .expect("Failed to create VM tester");
+
+        tester.run().expect("Failed to execute program");
+
+        assert_eq!(tester.get_output(), "120\n");
+        assert!(tester.get_stack().is_empty(), "Stack should be empty after execution");
+    }
+
+    #[test]
+    fn test_locals_are_frame_scoped() {
+        let mut tester = VMTester::new(LOCALS_SOURCE, false)
+            .expect("Failed to create VM tester");
+
+        tester.run().expect("Failed to execute program");
+
+        assert_eq!(tester.get_output(), "25\n");
+        assert_eq!(tester.get_memory().get("total"), Some(&Value::Int(7)));
+    }
+
+    fn run_error(source: &str) -> VMError {
+        let mut tester = VMTester::new(source, false)
+            .expect("Failed to create VM tester");
+
+        tester.run().expect_err("Expected execution to fail")
+    }
+
+    #[test]
+    fn test_function_errors() {
+        assert!(matches!(run_error("RET"), VMError::EmptyCallStack));
+        assert!(matches!(run_error("CALL missing"), VMError::FunctionNotFound(_)));
+        assert!(matches!(
+            run_error("FUNC f 0\nBEGIN\nPARAM 0\nEND\nCALL f"),
+            VMError::InvalidParameter(0)
+        ));
+        assert!(matches!(
+            run_error("FUNC f 0\nBEGIN\nLOADL x\nEND\nCALL f"),
+            VMError::LocalVarNotFound(_)
+        ));
+    }
+}
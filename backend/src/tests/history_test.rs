@@ -0,0 +1,188 @@
+use super::VMTester;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::heap::HeapValue;
+    use crate::core::state::{HistoryOptions, StopReason, Watch, WatchEvent};
+    use crate::core::value::Value;
+    use crate::core::vm::VM;
+
+    // Touches the stack, globals, locals, frames and every kind of heap change
+    const SOURCE: &str = r#"
+        FUNC fill 2
+        BEGIN
+            LOCAL i
+            PUSH 0
+            STOREL i
+        next:
+            PARAM 0
+            LOADL i
+            PARAM 1
+            ARRAYSET
+            LOADL i
+            PUSH 1
+            ADD
+            DUP
+            STOREL i
+            PUSH 3
+            LT
+            JMP_IF next
+            RET
+        END
+            PUSH 3
+            NEWARRAY
+            DUP
+            STORE numbers
+            PUSH 7
+            CALL fill
+            NEWSTR "ab"
+            NEWSTR "cd"
+            STRCAT
+            STORE text
+            LOAD numbers
+            FREEARR
+            HALT
+    "#;
+
+    type State = (usize, Vec<Value>, Vec<(String, Value)>, Vec<(usize, HeapValue)>, usize);
+
+    fn state(vm: &VM) -> State {
+        let state = vm.get_state();
+        let mut memory: Vec<(String, Value)> = state.memory.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        memory.sort_by(|a, b| a.0.cmp(&b.0));
+        let heap = state.heap.entries().into_iter().map(|(id, value)| (id, value.clone())).collect();
+        (state.program_counter, state.stack.clone(), memory, heap, state.call_stack.len())
+    }
+
+    /// Run to the end recording the state before every instruction, then
+    /// check that stepping back restores each of them in turn
+    fn check_round_trip(options: HistoryOptions) {
+        let mut tester = VMTester::new(SOURCE, false).unwrap();
+        let vm = tester.vm_mut();
+        vm.enable_history(options);
+
+        let mut states = vec![state(vm)];
+        while let Ok(true) = vm.step() {
+            states.push(state(vm));
+        }
+        // Every instruction, HALT included, can be undone
+        assert_eq!(vm.history_len(), states.len());
+
+        assert!(vm.step_back());
+        for expected in states.iter().rev() {
+            assert_eq!(&state(vm), expected);
+            if vm.history_len() == 0 {
+                break;
+            }
+            assert!(vm.step_back());
+        }
+        assert_eq!(vm.history_len(), 0);
+        assert!(!vm.step_back());
+        assert!(vm.get_state().stack.is_empty());
+        assert!(vm.get_state().heap.entries().is_empty());
+    }
+
+    #[test]
+    fn test_step_back_restores_every_state() {
+        check_round_trip(HistoryOptions::default());
+    }
+
+    #[test]
+    fn test_step_back_across_checkpoints() {
+        check_round_trip(HistoryOptions { checkpoint_interval: 3, max_checkpoints: 100 });
+        check_round_trip(HistoryOptions { checkpoint_interval: 1, max_checkpoints: 100 });
+    }
+
+    #[test]
+    fn test_checkpoints_bound_the_history() {
+        let mut tester = VMTester::new(SOURCE, false).unwrap();
+        let vm = tester.vm_mut();
+        vm.enable_history(HistoryOptions { checkpoint_interval: 4, max_checkpoints: 2 });
+        while let Ok(true) = vm.step() {
+            assert!(vm.history_len() <= 8);
+        }
+
+        let mut undone = 0;
+        while vm.step_back() {
+            undone += 1;
+        }
+        assert!((4..=8).contains(&undone), "undid {} steps", undone);
+        assert!(matches!(vm.reverse_continue(), StopReason::HistoryStart));
+    }
+
+    #[test]
+    fn test_resuming_after_stepping_back() {
+        let mut tester = VMTester::new(SOURCE, false).unwrap();
+        let vm = tester.vm_mut();
+        vm.enable_history(HistoryOptions { checkpoint_interval: 5, max_checkpoints: 10 });
+        assert!(matches!(vm.run_until_stop(), StopReason::Halted));
+        let end = state(vm);
+
+        for _ in 0..12 {
+            assert!(vm.step_back());
+        }
+        assert!(matches!(vm.run_until_stop(), StopReason::Halted));
+        assert_eq!(state(vm), end);
+    }
+
+    #[test]
+    fn test_reverse_continue_to_breakpoint() {
+        let mut tester = VMTester::new(SOURCE, false).unwrap();
+        let vm = tester.vm_mut();
+        vm.enable_history(HistoryOptions::default());
+        vm.run_until_stop();
+
+        // Address 5 is `next:`, reached once per element
+        vm.add_breakpoint(5);
+        for i in (0..3).rev() {
+            assert!(matches!(vm.reverse_continue(), StopReason::Breakpoint { address: 5 }));
+            assert_eq!(vm.get_state().call_stack[0].local_vars["i"], Value::Int(i));
+        }
+        assert!(matches!(vm.reverse_continue(), StopReason::HistoryStart));
+        assert_eq!(vm.get_state().program_counter, 0);
+
+        // Forwards again, the breakpoint we stopped at is executed, not re-hit
+        assert!(matches!(vm.run_until_stop(), StopReason::Breakpoint { address: 5 }));
+    }
+
+    #[test]
+    fn test_reverse_continue_finds_the_last_change() {
+        let mut tester = VMTester::new(SOURCE, false).unwrap();
+        let vm = tester.vm_mut();
+        vm.enable_history(HistoryOptions::default());
+        vm.run_until_stop();
+
+        vm.add_watch(Watch::Heap(1));
+        let StopReason::Watch { address, event, .. } = vm.reverse_continue() else { panic!("expected the free") };
+        assert_eq!(vm.get_state().instructions()[address].to_string(), "FREEARR");
+        assert_eq!(event, WatchEvent::HeapFreed { id: 1, old: HeapValue::Array(vec![Value::Int(7); 3]) });
+
+        let StopReason::Watch { event, .. } = vm.reverse_continue() else { panic!("expected the last store") };
+        assert_eq!(event.to_string(), "&1: [7, 7, 0] -> [7, 7, 7]");
+    }
+
+    #[test]
+    fn test_step_back_undoes_a_failed_instruction() {
+        let mut tester = VMTester::new("PUSH 1\nPUSH true\nADD", false).unwrap();
+        let vm = tester.vm_mut();
+        vm.enable_history(HistoryOptions::default());
+        assert!(matches!(vm.run_until_stop(), StopReason::Error { .. }));
+        assert_eq!(vm.get_state().program_counter, 2);
+
+        assert!(vm.step_back());
+        assert_eq!(vm.get_state().stack, vec![Value::Int(1), Value::Bool(true)]);
+        assert_eq!(vm.get_state().program_counter, 2);
+        assert!(vm.step_back());
+        assert_eq!(vm.get_state().stack, vec![Value::Int(1)]);
+    }
+
+    #[test]
+    fn test_history_is_off_by_default() {
+        let mut tester = VMTester::new("PUSH 1\nPOP", false).unwrap();
+        let vm = tester.vm_mut();
+        vm.step().unwrap();
+        assert_eq!(vm.history_len(), 0);
+        assert!(!vm.step_back());
+    }
+}
@@ -0,0 +1,112 @@
+use super::{diagnostics_with, VMTester};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::assembler::{include_name, Assembler, FileResolver, MemoryResolver, SourceResolver};
+    use crate::core::error::Expansion;
+    use std::collections::HashMap;
+
+    fn library() -> MemoryResolver {
+        let mut resolver = MemoryResolver::default();
+        resolver.insert("lib/math.asm", r#"
+.include "consts.asm"
+FUNC square 1
+BEGIN
+    PARAM 0
+    DUP
+    MUL
+    RET
+END
+"#);
+        resolver.insert("lib/consts.asm", ".const TEN 10");
+        resolver.insert("lib/macros.asm", ".macro SHOW value\n    PUSH %value\n    PRNT\n.endm");
+        resolver.insert("a.asm", "PUSH 1\n.include \"b.asm\"");
+        resolver.insert("b.asm", ".include \"./a.asm\"");
+        resolver
+    }
+
+    #[test]
+    fn test_include_name() {
+        assert_eq!(include_name("lib/math.asm", None), "lib/math.asm");
+        assert_eq!(include_name("consts.asm", Some("lib/math.asm")), "lib/consts.asm");
+        assert_eq!(include_name("./../x.asm", Some("lib/math.asm")), "x.asm");
+        assert_eq!(include_name("../x.asm", None), "../x.asm");
+        assert_eq!(include_name("/abs/x.asm", Some("lib/math.asm")), "/abs/x.asm");
+    }
+
+    #[test]
+    fn test_included_functions_and_constants() {
+        const SOURCE: &str = r#"
+            .include "lib/math.asm"   // nested includes resolve relative to the includer
+            PUSH TEN
+            CALL square
+            PRINT
+            HALT
+        "#;
+
+        let program = Assembler::new().with_resolver(library()).assemble(SOURCE).unwrap();
+        let mut tester = VMTester::from_program(program, &[], false);
+        tester.run().unwrap();
+        assert_eq!(tester.get_output(), "100");
+    }
+
+    #[test]
+    fn test_errors_name_the_included_file() {
+        let errors = diagnostics_with(Assembler::new().with_resolver(library()), "PUSH 1\n.include \"lib/macros.asm\"\nSHOW 2");
+        assert_eq!(errors.len(), 1);
+        let error = &errors[0];
+        assert_eq!((error.file.as_deref(), error.line, error.column), (Some("lib/macros.asm"), 3, 5));
+        assert_eq!(error.expansion, vec![Expansion { name: "SHOW".to_string(), file: None, line: 3, repeated: 0, cycle: 0 }]);
+        assert_eq!(error.to_string(), "lib/macros.asm:3:5: error: Unknown instruction: PRNT\n    \
+                                       in macro `SHOW` invoked at line 3");
+    }
+
+    #[test]
+    fn test_include_errors() {
+        let errors = diagnostics_with(Assembler::new().with_resolver(library()), ".include \"a.asm\"\n.include \"missing.asm\"\n.include lib");
+        let located: Vec<(Option<&str>, usize, &str)> = errors.iter()
+            .map(|e| (e.file.as_deref(), e.line, e.message.as_str()))
+            .collect();
+        assert_eq!(located, vec![
+            (None, 2, "Cannot include \"missing.asm\": no such file"),
+            (None, 3, "Expected a quoted path"),
+            (Some("b.asm"), 1, "Include cycle: a.asm -> b.asm -> a.asm"),
+        ]);
+
+        let errors = Assembler::new().assemble(".include \"a.asm\"").unwrap_err();
+        assert_eq!(errors.0[0].message, "Includes are not available here");
+    }
+
+    #[test]
+    fn test_file_resolver() {
+        let root = std::env::temp_dir().join(format!("vm_include_test_{}", std::process::id()));
+        std::fs::create_dir_all(root.join("lib")).unwrap();
+        std::fs::write(root.join("lib/hello.asm"), "PRINTSTR \"hello\"").unwrap();
+
+        let resolver = FileResolver::new(&root);
+        let source = resolver.resolve("hello.asm", Some("lib/main.asm")).unwrap();
+        assert_eq!(source.name, "lib/hello.asm");
+        assert!(resolver.resolve("nothing.asm", None).is_err());
+
+        let program = Assembler::new().with_resolver(resolver)
+            .assemble(".include \"lib/hello.asm\"\nHALT")
+            .unwrap();
+        assert_eq!(program.len(), 2);
+
+        // Includes may leave the root: `..` climbs above it and absolute paths are kept
+        let outside = std::env::temp_dir().join(format!("vm_include_outside_{}.asm", std::process::id()));
+        std::fs::write(&outside, "HALT").unwrap();
+        let file_name = outside.file_name().unwrap().to_str().unwrap();
+        let resolver = FileResolver::new(&root);
+        let climbed = resolver.resolve(&format!("../{}", file_name), None).unwrap();
+        assert_eq!((climbed.name, climbed.text.as_str()), (format!("../{}", file_name), "HALT"));
+        let absolute = resolver.resolve(outside.to_str().unwrap(), Some("lib/main.asm")).unwrap();
+        assert_eq!(absolute.name, outside.to_str().unwrap());
+        std::fs::remove_file(&outside).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let bundle = MemoryResolver::new(HashMap::from([("x.asm".to_string(), "HALT".to_string())]));
+        assert_eq!(bundle.resolve("x.asm", None).unwrap().text, "HALT");
+    }
+}
@@ -0,0 +1,123 @@
+use super::{assemble, VMTester};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::assembler::Assembler;
+    use crate::core::instruction::Instruction;
+
+    #[test]
+    fn test_standalone_labels_and_comments() {
+        const SOURCE: &str = r#"
+            ; semicolon comments work like //
+            start:
+                PUSH 3          ; count down from 3
+            loop:               // a label on its own line
+            again:
+                DUP
+                PRINT
+                PUSH 1
+                SUB
+                DUP
+                JMPNZ loop      ; jump back
+                HALT
+            end:
+        "#;
+
+        let mut assembler = Assembler::new();
+        assembler.assemble(SOURCE).unwrap();
+        let labels = assembler.labels();
+        assert_eq!((labels["start"], labels["loop"], labels["again"], labels["end"]), (0, 1, 1, 8));
+
+        let mut tester = VMTester::new(SOURCE, false).unwrap();
+        tester.run().unwrap();
+        assert_eq!(tester.get_output(), "321");
+    }
+
+    #[test]
+    fn test_local_labels() {
+        const SOURCE: &str = r#"
+            first:
+                PUSH 2
+            .loop:
+                PUSH 1
+                SUB
+                DUP
+                JMPNZ .loop
+                POP
+            second:
+                PUSH 3
+            .loop:
+                PUSH 1
+                SUB
+                DUP
+                JMPNZ .loop
+                JMP first.loop      // qualified names reach other scopes
+                HALT
+        "#;
+
+        let mut assembler = Assembler::new();
+        let program = assembler.assemble(SOURCE).unwrap();
+        assert_eq!(assembler.labels()["first.loop"], 1);
+        assert_eq!(assembler.labels()["second.loop"], 7);
+        assert_eq!(program[4], Instruction::JumpIfNotZero(1));
+        assert_eq!(program[10], Instruction::JumpIfNotZero(7));
+        assert_eq!(program[11], Instruction::Jump(1));
+    }
+
+    #[test]
+    fn test_anonymous_labels() {
+        const SOURCE: &str = r#"
+                PUSH 0
+                JMPZ 1f
+            1:  PUSH 5
+            1:  PUSH 1
+                SUB
+                DUP
+                JMPNZ 1b
+                JMP 2f
+            2:
+                HALT
+        "#;
+
+        assert_eq!(assemble(SOURCE)[1], Instruction::JumpIfZero(2));
+        assert_eq!(assemble(SOURCE)[6], Instruction::JumpIfNotZero(3));
+        assert_eq!(assemble(SOURCE)[7], Instruction::Jump(8));
+    }
+
+    #[test]
+    fn test_numeric_jump_targets() {
+        let program = assemble("PUSH 1\nJMP $+2\nHALT\nJMPZ $-3\nJMP $\nJMP 0");
+        assert_eq!(program[1..], [
+            Instruction::Jump(3),
+            Instruction::Halt,
+            Instruction::JumpIfZero(0),
+            Instruction::Jump(4),
+            Instruction::Jump(0),
+        ]);
+    }
+
+    #[test]
+    fn test_label_errors() {
+        const SOURCE: &str = r#".orphan:
+JMP .nowhere
+top:
+.x:
+.x:
+JMP 3b
+JMP $-9
+HALT"#;
+
+        let errors = Assembler::new().assemble(SOURCE).unwrap_err().0;
+        let messages: Vec<(usize, &str)> = errors.iter()
+            .map(|e| (e.line, e.message.as_str()))
+            .collect();
+        assert_eq!(messages, vec![
+            (1, "Local label .orphan has no parent label"),
+            (2, "Local label .nowhere used outside any parent label"),
+            (5, "Duplicate label: top.x"),
+            (6, "No label 3: before this line"),
+            (7, "Jump target $-9 is before the start of the program"),
+        ]);
+    }
+}
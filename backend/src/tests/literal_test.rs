@@ -0,0 +1,128 @@
+use super::{assemble, diagnostics, VMTester};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::assembler::{char_literal, number, string_literal, Assembler};
+    use crate::core::instruction::Instruction;
+    use crate::core::value::Value;
+
+    #[test]
+    fn test_string_escapes() {
+        assert_eq!(string_literal(r#""""#).unwrap(), ("", String::new()));
+        assert_eq!(string_literal(r#""say \"hi\"\t\\" rest"#).unwrap(), (" rest", "say \"hi\"\t\\".to_string()));
+        assert_eq!(string_literal(r#""\u{41}\u{263A}\0\r\n\'""#).unwrap().1, "A\u{263A}\0\r\n'");
+        assert!(string_literal(r#""\q""#).is_err());
+        assert!(string_literal(r#""\u{110000}""#).is_err());
+        assert!(string_literal(r#""open"#).is_err());
+    }
+
+    #[test]
+    fn test_escapes_are_resolved_at_assembly_time() {
+        const SOURCE: &str = r#"
+            PRINTSTR "line\none\t"
+            NEWSTR ""
+            STRLEN
+            PRINT
+            NEWSTR "a\\b"
+            DUP
+            STRLEN
+            PRINT
+            PRINT
+            HALT
+        "#;
+
+        let program = assemble(SOURCE);
+        assert_eq!(program[0], Instruction::PrintStr("line\none\t".to_string()));
+
+        let mut tester = VMTester::new(SOURCE, false).unwrap();
+        tester.run().unwrap();
+        assert_eq!(tester.get_output(), "line\none\t03a\\b");
+    }
+
+    #[test]
+    fn test_integer_literals() {
+        assert_eq!(number("0x1F").unwrap(), ("", 31));
+        assert_eq!(number("-0XfF_fF").unwrap(), ("", -65535));
+        assert_eq!(number("0b1010_0101").unwrap(), ("", 165));
+        assert_eq!(number("1_000_000").unwrap(), ("", 1_000_000));
+        assert_eq!(number("0xFFFFFFFFFFFFFFFF").unwrap(), ("", -1));
+        assert_eq!(number("-9223372036854775808").unwrap(), ("", i64::MIN));
+        assert_eq!(number("12, 3").unwrap(), (", 3", 12));
+        assert!(number("1__0").is_err());
+        assert!(number("9223372036854775808").is_err());
+        assert!(number("0x1_0000_0000_0000_0000").is_err());
+
+        let program = assemble("PUSH 0x10\nPUSH 0b11\nPUSH 1_000.25\nPICK 0x1");
+        assert_eq!(program, vec![
+            Instruction::Push(Value::Int(16)),
+            Instruction::Push(Value::Int(3)),
+            Instruction::Push(Value::Float(1000.25)),
+            Instruction::Pick(1),
+        ]);
+    }
+
+    #[test]
+    fn test_malformed_integer_literals() {
+        const SOURCE: &str = "PUSH 0x\nPUSH 0b102\nPUSH 1__0\nPUSH 99999999999999999999\nPUSH -0x1G, 2\nPUSH 12ab";
+
+        let errors = diagnostics(SOURCE);
+        let found: Vec<_> = errors.iter()
+            .map(|e| (e.line, e.column, e.token.as_str(), e.message.as_str()))
+            .collect();
+        assert_eq!(found, vec![
+            (1, 6, "0x", "Invalid hexadecimal literal"),
+            (2, 6, "0b102", "Invalid binary literal"),
+            (3, 6, "1__0", "Invalid integer literal"),
+            (4, 6, "99999999999999999999", "Integer literal out of range"),
+            (5, 6, "-0x1G", "Invalid hexadecimal literal"),
+            (6, 6, "12ab", "Invalid integer literal"),
+        ]);
+    }
+
+    #[test]
+    fn test_character_literals() {
+        assert_eq!(char_literal("'A'").unwrap(), ("", 65));
+        assert_eq!(char_literal(r"'\n'").unwrap(), ("", 10));
+        assert_eq!(char_literal(r"'\''").unwrap(), ("", 39));
+        assert_eq!(char_literal("'é'").unwrap(), ("", 233));
+        assert!(char_literal("''").is_err());
+        assert!(char_literal("'ab'").is_err());
+
+        const SOURCE: &str = r#"
+            .const NEWLINE '\n'
+            .data
+            word: 'H', 'i'
+            .text
+            LOAD word
+            PUSH 1
+            ARRAYGET
+            PUSH 'H'
+            PRINTCHAR
+            PRINTCHAR
+            PUSH NEWLINE
+            PRINTCHAR
+            HALT
+        "#;
+
+        let mut tester = VMTester::new(SOURCE, false).unwrap();
+        tester.run().unwrap();
+        assert_eq!(tester.get_output(), "Hi\n");
+    }
+
+    #[test]
+    fn test_literal_errors() {
+        const SOURCE: &str = "PRINTSTR \"bad \\q escape\"\nNEWSTR \"open\nPUSH 'xy'\n.const TAB '\\u{zz}'";
+
+        let errors = Assembler::new().assemble(SOURCE).unwrap_err().0;
+        let located: Vec<(usize, usize, &str, &str)> = errors.iter()
+            .map(|e| (e.line, e.column, e.token.as_str(), e.message.as_str()))
+            .collect();
+        assert_eq!(located, vec![
+            (1, 15, "\\q", "Invalid escape sequence"),
+            (2, 8, "\"open", "Unterminated string literal"),
+            (3, 6, "'xy", "Invalid character literal"),
+            (4, 13, "\\u{zz}", "Invalid escape sequence"),
+        ]);
+    }
+}
@@ -0,0 +1,177 @@
+use super::{diagnostics, VMTester};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::assembler::Assembler;
+    use crate::core::error::Expansion;
+    use crate::core::value::Value;
+
+    #[test]
+    fn test_macro_parameters() {
+        const SOURCE: &str = r#"
+            .macro ADDTO name, amount
+                LOAD %name
+                PUSH %amount
+                ADD
+                STORE %name
+            .endm
+
+            PUSH 10
+            STORE total
+            addto total, 5
+            ADDTO total, -3
+            LOAD total
+            PRINT
+            HALT
+        "#;
+
+        let mut tester = VMTester::new(SOURCE, false).unwrap();
+        tester.run().unwrap();
+        assert_eq!(tester.get_output(), "12");
+        assert_eq!(tester.get_memory().get("total"), Some(&Value::Int(12)));
+    }
+
+    #[test]
+    fn test_local_labels_are_unique_per_expansion() {
+        const SOURCE: &str = r#"
+            .macro COUNTDOWN from
+                PUSH %from
+            %%loop: DUP
+                PRINT
+                PUSH 1
+                SUB
+                DUP
+                JMPNZ %%loop
+                POP
+            .endm
+
+            COUNTDOWN 2
+            COUNTDOWN 3
+            HALT
+        "#;
+
+        let mut assembler = Assembler::new();
+        assembler.assemble(SOURCE).unwrap();
+        assert_eq!(assembler.labels().get("__loop_1"), Some(&1));
+        assert_eq!(assembler.labels().get("__loop_2"), Some(&9));
+
+        let mut tester = VMTester::new(SOURCE, false).unwrap();
+        tester.run().unwrap();
+        assert_eq!(tester.get_output(), "21321");
+    }
+
+    #[test]
+    fn test_nested_macros_and_invocation_labels() {
+        const SOURCE: &str = r#"
+            .macro INC name
+                LOAD %name
+                PUSH 1
+                ADD
+                STORE %name
+            .endm
+            .macro INC_TWICE name
+                INC %name
+                INC %name
+            .endm
+
+            PUSH 0
+            STORE n
+            again: INC_TWICE n
+            LOAD n
+            PUSH 6
+            LT
+            JMPNZ again
+            LOAD n
+            PRINT
+            HALT
+        "#;
+
+        let mut tester = VMTester::new(SOURCE, false).unwrap();
+        tester.run().unwrap();
+        assert_eq!(tester.get_output(), "6");
+    }
+
+    #[test]
+    fn test_errors_point_into_the_body_and_the_invocation() {
+        const SOURCE: &str = r#".macro OUTER value
+    INNER %value
+.endm
+.macro INNER value
+    PUSH %value
+    PRNT
+.endm
+OUTER 1"#;
+
+        let errors = diagnostics(SOURCE);
+        assert_eq!(errors.len(), 1);
+        let error = &errors[0];
+        assert_eq!((error.line, error.column), (6, 5));
+        assert_eq!(error.message, "Unknown instruction: PRNT");
+        assert_eq!(error.expansion, vec![
+            Expansion { name: "INNER".to_string(), file: None, line: 2, repeated: 0, cycle: 0 },
+            Expansion { name: "OUTER".to_string(), file: None, line: 8, repeated: 0, cycle: 0 },
+        ]);
+        assert_eq!(error.to_string(), "6:5: error: Unknown instruction: PRNT\n    \
+                                       in macro `INNER` invoked at line 2\n    \
+                                       in macro `OUTER` invoked at line 8");
+    }
+
+    #[test]
+    fn test_definition_errors() {
+        const SOURCE: &str = r#".macro PUSH x
+.endm
+.endm
+.macro TWICE
+    %%top: PUSH %x
+.endm
+TWICE
+TWICE 1
+.macro OPEN"#;
+
+        let errors = diagnostics(SOURCE);
+        let messages: Vec<(usize, &str)> = errors.iter()
+            .map(|e| (e.line, e.message.as_str()))
+            .collect();
+        assert_eq!(messages, vec![
+            (1, "Macro PUSH would shadow an instruction"),
+            (3, "`.endm` without a matching `.macro`"),
+            (5, "Unknown macro parameter: %x"),
+            (8, "Macro TWICE expects 0 argument(s), found 1"),
+            (9, "Macro definition is never closed"),
+        ]);
+        assert_eq!(errors[2].expansion, vec![Expansion { name: "TWICE".to_string(), file: None, line: 7, repeated: 0, cycle: 0 }]);
+    }
+
+    #[test]
+    fn test_recursive_macro_is_reported() {
+        const SOURCE: &str = ".macro FOREVER\n    FOREVER\n.endm\nFOREVER";
+
+        let errors = diagnostics(SOURCE);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "Macro FOREVER expands too deeply");
+        assert_eq!(errors[0].expansion, vec![
+            Expansion { name: "FOREVER".to_string(), file: None, line: 2, repeated: 30, cycle: 1 },
+            Expansion { name: "FOREVER".to_string(), file: None, line: 4, repeated: 0, cycle: 0 },
+        ]);
+        assert!(errors[0].to_string().ends_with(
+            "in macro `FOREVER` invoked at line 2\n    … (repeated 30 times)\n    in macro `FOREVER` invoked at line 4"));
+    }
+
+    #[test]
+    fn test_mutually_recursive_macros_are_collapsed() {
+        const SOURCE: &str = ".macro PING\n    PONG\n.endm\n.macro PONG\n    PING\n.endm\nPING";
+
+        let errors = diagnostics(SOURCE);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "Macro PING expands too deeply");
+        assert_eq!(errors[0].expansion, vec![
+            Expansion { name: "PONG".to_string(), file: None, line: 2, repeated: 0, cycle: 0 },
+            Expansion { name: "PING".to_string(), file: None, line: 5, repeated: 14, cycle: 2 },
+            Expansion { name: "PONG".to_string(), file: None, line: 2, repeated: 0, cycle: 0 },
+            Expansion { name: "PING".to_string(), file: None, line: 7, repeated: 0, cycle: 0 },
+        ]);
+        assert!(errors[0].to_string().contains(
+            "in macro `PING` invoked at line 5\n    … (last 2 frames repeated 14 times)\n"));
+    }
+}
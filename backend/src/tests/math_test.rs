@@ -0,0 +1,104 @@
+use super::VMTester;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::error::VMError;
+    use crate::core::state::ArithmeticMode;
+    use crate::core::value::Value;
+
+    fn ints(values: &[i64]) -> Vec<Value> {
+        values.iter().copied().map(Value::Int).collect()
+    }
+
+    #[test]
+    fn test_modulo_sign_rules() {
+        assert_eq!(VMTester::stack_after("PUSH 7\nPUSH 3\nMOD"), ints(&[1]));
+        assert_eq!(VMTester::stack_after("PUSH -7\nPUSH 3\nMOD"), ints(&[2]));
+        assert_eq!(VMTester::stack_after("PUSH 7\nPUSH -3\nMOD"), ints(&[1]));
+        assert_eq!(VMTester::stack_after("PUSH -7\nPUSH 3\nDIVMOD"), ints(&[-3, 2]));
+        assert_eq!(VMTester::stack_after("PUSH -9223372036854775808\nPUSH -1\nMOD"), ints(&[0]));
+    }
+
+    #[test]
+    fn test_parity_with_mod() {
+        const SOURCE: &str = r#"
+        // Same output as the DIV/MUL/SUB parity loop in control_test.rs
+        start:  PUSH 1
+                STORE x
+
+        loop:   LOAD x
+                PUSH 2
+                MOD
+                PRINT
+                PRINTSTR "\n"
+
+                LOAD x
+                PUSH 1
+                ADD
+                DUP
+                STORE x
+                PUSH 4
+                LT
+                JMPNZ loop
+
+        end:    HALT
+        "#;
+
+        let mut tester = VMTester::new(SOURCE, false)
+            .expect("Failed to create VM tester");
+
+        tester.run().expect("Failed to execute program");
+        assert_eq!(tester.get_output(), "1\n0\n1\n");
+    }
+
+    #[test]
+    fn test_unary_and_selection_operations() {
+        assert_eq!(VMTester::stack_after("PUSH 5\nNEG\nPUSH -5\nABS"), ints(&[-5, 5]));
+        assert_eq!(VMTester::stack_after("PUSH 3\nPUSH 9\nMIN\nPUSH 3\nPUSH 9\nMAX"), ints(&[3, 9]));
+        assert_eq!(VMTester::stack_after("PUSH 3\nPUSH 4\nPOW\nPUSH 2\nPUSH 0\nPOW"), ints(&[81, 1]));
+    }
+
+    #[test]
+    fn test_bitwise_operations() {
+        assert_eq!(VMTester::stack_after("PUSH 12\nPUSH 10\nBAND"), ints(&[8]));
+        assert_eq!(VMTester::stack_after("PUSH 12\nPUSH 10\nBOR"), ints(&[14]));
+        assert_eq!(VMTester::stack_after("PUSH 12\nPUSH 10\nBXOR"), ints(&[6]));
+        assert_eq!(VMTester::stack_after("PUSH 0\nBNOT"), ints(&[-1]));
+        assert_eq!(VMTester::stack_after("PUSH 1\nPUSH 4\nSHL"), ints(&[16]));
+        assert_eq!(VMTester::stack_after("PUSH -16\nPUSH 2\nSAR"), ints(&[-4]));
+        assert_eq!(VMTester::stack_after("PUSH -1\nPUSH 60\nSHR"), ints(&[15]));
+    }
+
+    #[test]
+    fn test_math_errors_and_modes() {
+        let run = |source: &str, mode: ArithmeticMode| {
+            let mut tester = VMTester::new(source, false)
+                .expect("Failed to create VM tester");
+            tester.set_arithmetic_mode(mode);
+            tester.run().map(|_| tester.get_stack().clone())
+        };
+
+        assert!(matches!(run("PUSH 1\nPUSH 0\nMOD", ArithmeticMode::Checked), Err(VMError::DivisionByZero)));
+        assert!(matches!(run("PUSH 2\nPUSH -1\nPOW", ArithmeticMode::Checked), Err(VMError::NegativeExponent(-1))));
+        assert!(matches!(run("PUSH 2\nPUSH 64\nPOW", ArithmeticMode::Checked), Err(VMError::ArithmeticOverflow(2))));
+        assert!(matches!(run("PUSH 1\nPUSH 64\nSHL", ArithmeticMode::Checked), Err(VMError::ArithmeticOverflow(2))));
+        assert!(matches!(run("PUSH -9223372036854775808\nNEG", ArithmeticMode::Checked), Err(VMError::ArithmeticOverflow(1))));
+
+        assert_eq!(run("PUSH 1\nPUSH 65\nSHL", ArithmeticMode::Wrapping).unwrap(), ints(&[2]));
+        assert_eq!(run("PUSH 1\nPUSH 65\nSHL", ArithmeticMode::Saturating).unwrap(), ints(&[0]));
+        assert_eq!(run("PUSH -8\nPUSH 70\nSAR", ArithmeticMode::Saturating).unwrap(), ints(&[-1]));
+        assert_eq!(run("PUSH 3\nPUSH 40\nPOW", ArithmeticMode::Saturating).unwrap(), ints(&[i64::MAX]));
+        assert_eq!(run("PUSH -9223372036854775808\nABS", ArithmeticMode::Wrapping).unwrap(), ints(&[i64::MIN]));
+
+        // Exponents beyond u32::MAX keep their parity
+        const HUGE: &str = "PUSH 4294967296\nPOW";
+        assert_eq!(run(&format!("PUSH -1\n{}", HUGE), ArithmeticMode::Checked).unwrap(), ints(&[1]));
+        assert_eq!(run("PUSH -1\nPUSH 4294967297\nPOW", ArithmeticMode::Checked).unwrap(), ints(&[-1]));
+        assert_eq!(run(&format!("PUSH 0\n{}", HUGE), ArithmeticMode::Checked).unwrap(), ints(&[0]));
+        assert!(matches!(run(&format!("PUSH 3\n{}", HUGE), ArithmeticMode::Checked), Err(VMError::ArithmeticOverflow(2))));
+        assert_eq!(run(&format!("PUSH 3\n{}", HUGE), ArithmeticMode::Wrapping).unwrap(), ints(&[2491309678558969857]));
+        assert_eq!(run("PUSH -3\nPUSH 4294967297\nPOW", ArithmeticMode::Saturating).unwrap(), ints(&[i64::MIN]));
+        assert_eq!(run(&format!("PUSH -3\n{}", HUGE), ArithmeticMode::Saturating).unwrap(), ints(&[i64::MAX]));
+    }
+}
@@ -1,8 +1,32 @@
 mod test_utils;
-pub(crate) use test_utils::VMTester;
+pub(crate) use test_utils::{assemble, diagnostics, diagnostics_with, every_instruction, VMTester};
 
+mod analysis_test;
 mod arithmetic_test;
 mod array_test;
+mod breakpoint_test;
+mod bytecode_test;
+mod cfg_test;
 mod control_test;
+mod data_test;
+mod debugger_test;
+mod diagnostics_test;
+mod disassembler_test;
+mod float_test;
+mod folding_test;
+mod function_test;
+mod history_test;
+mod include_test;
 mod io_test;
-mod string_test;
\ No newline at end of file
+mod label_test;
+mod literal_test;
+mod macro_test;
+mod math_test;
+mod opcode_test;
+mod optimizer_test;
+mod overflow_test;
+mod repl_test;
+mod stack_test;
+mod string_test;
+mod value_test;
+mod verifier_test;
\ No newline at end of file
@@ -0,0 +1,71 @@
+#[cfg(test)]
+mod tests {
+    use crate::core::assembler::Assembler;
+    use crate::core::instruction::{instruction_reference, Instruction, Opcode, OPCODES};
+    use crate::tests::every_instruction;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_every_instruction_round_trips() {
+        let program = every_instruction(8);
+        let source = program.iter()
+            .map(|instruction| instruction.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let assembled = Assembler::new().assemble(&source)
+            .expect("Display output should assemble");
+
+        assert_eq!(assembled, program);
+    }
+
+    #[test]
+    fn test_opcode_table_is_consistent() {
+        assert_eq!(Opcode::ALL.len(), OPCODES.len());
+
+        let mut mnemonics = HashSet::new();
+        for &opcode in Opcode::ALL {
+            let info = opcode.info();
+            assert!(mnemonics.insert(info.mnemonic), "Duplicate mnemonic {}", info.mnemonic);
+            assert_eq!(Opcode::from_mnemonic(info.mnemonic), Some(opcode));
+        }
+
+        for instruction in every_instruction(8) {
+            assert_eq!(Instruction::from_operands(instruction.opcode(), &instruction.operands()),
+                       Some(instruction.clone()));
+        }
+    }
+
+    #[test]
+    fn test_reference_lists_every_mnemonic() {
+        let reference = instruction_reference();
+        for info in OPCODES {
+            assert!(reference.contains(&format!("- `{}`", info.syntax())), "{} missing", info.mnemonic);
+        }
+        assert_eq!(reference.matches("### Control Flow").count(), 1);
+    }
+
+    #[test]
+    fn test_readme_reference_matches_table() {
+        let readme = include_str!("../../../README.md");
+        let reference = instruction_reference();
+        let first_heading = reference.lines().find(|line| line.starts_with("### ")).unwrap();
+
+        let start = readme.find(&format!("\n{}\n", first_heading)).expect("README lists the instruction set");
+        let end = start + readme[start..].find("\n## ").expect("a section follows the instruction set");
+        assert_eq!(&readme[start..end], reference,
+                   "README instruction reference is out of date; paste in instruction_reference()");
+    }
+
+    #[test]
+    fn test_operand_validation() {
+        let mut assembler = Assembler::new();
+        assert!(assembler.assemble("PUSH").is_err());
+        assert!(assembler.assemble("POP 1").is_err());
+        assert!(assembler.assemble("PICK -1").is_err());
+        assert!(assembler.assemble("LOAD \"x\"").is_err());
+        assert!(assembler.assemble("JMP nowhere").is_err());
+        assert_eq!(assembler.assemble("EQ\nNE\nAND\nOR\nNOT").unwrap(),
+                   vec![Instruction::Equal, Instruction::NotEqual, Instruction::And, Instruction::Or, Instruction::Not]);
+    }
+}
@@ -0,0 +1,174 @@
+use super::{assemble, VMTester};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::instruction::Instruction;
+    use crate::core::optimizer::peephole;
+    use crate::core::value::Value;
+
+    #[test]
+    fn test_removes_redundant_pairs() {
+        const SOURCE: &str = r#"
+            PUSH 5
+            PUSH 7
+            PUSH 9
+            POP
+            SWAP
+            SWAP
+            PUSH 0
+            ADD
+            PUSH 1
+            MUL
+            PRINT
+        "#;
+
+        let (program, report) = peephole(&assemble(SOURCE));
+        assert_eq!(program, vec![
+            Instruction::Push(Value::Int(5)),
+            Instruction::Push(Value::Int(7)),
+            Instruction::Print,
+        ]);
+        assert_eq!(report.before, 11);
+        assert_eq!(report.after, 3);
+        assert_eq!(report.saved(), 8);
+        assert_eq!(report.rewrites["push-pop"], 1);
+        assert_eq!(report.rewrites["swap-swap"], 1);
+        assert_eq!(report.rewrites["identity-arithmetic"], 2);
+    }
+
+    #[test]
+    fn test_rewrites_keep_runtime_errors() {
+        for source in [
+            "PUSH 2.5\nPUSH 0\nADD\nPRINT",
+            "PUSH true\nPUSH 0\nADD\nPRINT",
+            "PUSH 1.5\nPUSH 1\nDIV\nPRINT",
+            "PUSH 1\nSWAP\nSWAP",
+        ] {
+            let mut plain = VMTester::new(source, false).expect("Failed to create VM tester");
+            let mut optimized = VMTester::optimized(source, false).expect("Failed to create optimized VM tester");
+            let plain = plain.run().expect_err("the plain program fails");
+            let optimized = optimized.run().expect_err("the optimized program fails too");
+            assert_eq!(optimized.to_string(), plain.to_string(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_identities_on_known_integers() {
+        // DEPTH always pushes an integer, so adding zero to it is a no-op
+        let (program, report) = peephole(&assemble("DEPTH\nPUSH 0\nADD\nDUP\nSWAP\nSWAP\nPRINT"));
+        assert_eq!(program, vec![Instruction::Depth, Instruction::Dup, Instruction::Print]);
+        assert_eq!(report.rewrites["identity-arithmetic"], 1);
+        assert_eq!(report.rewrites["swap-swap"], 1);
+
+        // The value of x is unknown, so it might be a float
+        let program = assemble("LOAD x\nPUSH 0\nSUB\nPRINT");
+        assert_eq!(peephole(&program).0, program);
+    }
+
+    #[test]
+    fn test_negated_branches_and_remapping() {
+        const SOURCE: &str = r#"
+            top: LOAD flag
+            NOT
+            JMPZ done
+            PUSH 1
+            PUSH 2
+            POP
+            POP
+            JMP top
+            done: HALT
+        "#;
+
+        let (program, report) = peephole(&assemble(SOURCE));
+        assert_eq!(program, vec![
+            Instruction::Load("flag".to_string()),
+            Instruction::JumpIfNotZero(3),
+            Instruction::Jump(0),
+            Instruction::Halt,
+        ]);
+        assert_eq!(report.rewrites["negated-branch"], 1);
+        assert_eq!(report.rewrites["push-pop"], 2);
+    }
+
+    #[test]
+    fn test_jump_threading() {
+        const SOURCE: &str = r#"
+            PUSH 1
+            JMPNZ hop
+            PRINTSTR "fallthrough"
+            hop: JMP hop2
+            hop2: JMP end
+            PRINTSTR "skipped"
+            end: HALT
+        "#;
+
+        let (program, report) = peephole(&assemble(SOURCE));
+        assert_eq!(program[1], Instruction::JumpIfNotZero(6));
+        assert_eq!(program[3], Instruction::Jump(6));
+        assert_eq!(report.rewrites["jump-threading"], 2);
+    }
+
+    #[test]
+    fn test_patterns_do_not_span_jump_targets() {
+        const SOURCE: &str = r#"
+            PUSH 1
+            JMPZ pop
+            PUSH 7
+            pop: POP
+            PUSH 0
+            JMPZ back
+            SWAP
+            back: SWAP
+            JMP next
+            next: HALT
+        "#;
+
+        let (program, report) = peephole(&assemble(SOURCE));
+        assert_eq!(program, vec![
+            Instruction::Push(Value::Int(1)),
+            Instruction::JumpIfZero(3),
+            Instruction::Push(Value::Int(7)),
+            Instruction::Pop,
+            Instruction::Push(Value::Int(0)),
+            Instruction::JumpIfZero(7),
+            Instruction::Swap,
+            Instruction::Swap,
+            Instruction::Halt,
+        ]);
+        assert_eq!(report.rewrites.get("push-pop"), None);
+        assert_eq!(report.rewrites["jump-to-next"], 1);
+    }
+
+    #[test]
+    fn test_optimized_program_behaves_the_same() {
+        const SOURCE: &str = r#"
+            PUSH 0
+            STORE i
+            loop: LOAD i
+            PUSH 0
+            ADD
+            DUP
+            PRINT
+            PUSH 1
+            ADD
+            DUP
+            STORE i
+            PUSH 4
+            LT
+            NOT
+            JMPZ loop
+            JMP end
+            end: PRINTSTR "done"
+        "#;
+
+        let mut plain = VMTester::new(SOURCE, false).expect("Failed to create VM tester");
+        let mut optimized = VMTester::optimized(SOURCE, false).expect("Failed to create VM tester");
+        plain.run().expect("Failed to execute program");
+        optimized.run().expect("Failed to execute program");
+
+        assert_eq!(plain.get_output(), "0123done");
+        assert_eq!(optimized.get_output(), plain.get_output());
+        assert_eq!(optimized.get_memory(), plain.get_memory());
+    }
+}
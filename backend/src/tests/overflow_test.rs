@@ -0,0 +1,60 @@
+use super::VMTester;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::error::VMError;
+    use crate::core::state::ArithmeticMode;
+    use crate::core::value::Value;
+
+    fn run_in_mode(source: &str, mode: ArithmeticMode) -> Result<Vec<Value>, VMError> {
+        let mut tester = VMTester::new(source, false)
+            .expect("Failed to create VM tester");
+        tester.set_arithmetic_mode(mode);
+        tester.run()?;
+        Ok(tester.get_stack().clone())
+    }
+
+    #[test]
+    fn test_checked_overflow_reports_pc() {
+        const SOURCE: &str = r#"
+        PUSH 1
+        POP
+        PUSH 9223372036854775807
+        PUSH 1
+        ADD
+        HALT
+        "#;
+
+        match run_in_mode(SOURCE, ArithmeticMode::Checked) {
+            Err(VMError::ArithmeticOverflow(4)) => (),
+            other => panic!("Expected overflow at pc 4, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_wrapping_and_saturating_modes() {
+        let cases = [
+            ("PUSH 9223372036854775807\nPUSH 1\nADD", i64::MIN, i64::MAX),
+            ("PUSH -9223372036854775808\nPUSH 1\nSUB", i64::MAX, i64::MIN),
+            ("PUSH 4611686018427387904\nPUSH -4\nMUL", 0, i64::MIN),
+            ("PUSH -9223372036854775808\nPUSH -1\nDIV", i64::MIN, i64::MAX),
+        ];
+
+        for (source, wrapped, saturated) in cases {
+            assert!(matches!(
+                run_in_mode(source, ArithmeticMode::Checked),
+                Err(VMError::ArithmeticOverflow(_))
+            ));
+            assert_eq!(run_in_mode(source, ArithmeticMode::Wrapping).unwrap(), vec![Value::Int(wrapped)]);
+            assert_eq!(run_in_mode(source, ArithmeticMode::Saturating).unwrap(), vec![Value::Int(saturated)]);
+        }
+    }
+
+    #[test]
+    fn test_division_by_zero_in_every_mode() {
+        for mode in [ArithmeticMode::Checked, ArithmeticMode::Wrapping, ArithmeticMode::Saturating] {
+            assert!(matches!(run_in_mode("PUSH 1\nPUSH 0\nDIV", mode), Err(VMError::DivisionByZero)));
+        }
+    }
+}
@@ -0,0 +1,139 @@
+#[cfg(test)]
+mod tests {
+    use crate::core::repl::{Repl, STEP_LIMIT};
+    use crate::core::value::Value;
+
+    fn eval_all(repl: &mut Repl, lines: &str) -> Vec<String> {
+        lines.lines().map(|line| repl.eval(line.trim())).collect()
+    }
+
+    #[test]
+    fn test_lines_run_against_one_vm() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.eval("PUSH 2"), "stack: [2]");
+        assert_eq!(repl.eval("PUSH 3"), "stack: [2, 3]");
+        assert_eq!(repl.eval("ADD"), "stack: [5]");
+        assert_eq!(repl.eval("STORE total"), "stack: []\ntotal = 5");
+        // Unchanged memory is not repeated
+        assert_eq!(repl.eval("LOAD total"), "stack: [5]");
+        assert_eq!(repl.vm().get_state().stack, vec![Value::Int(5)]);
+    }
+
+    #[test]
+    fn test_output_and_heap_changes() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.eval("PRINTSTR \"hi\""), "hi\nstack: []");
+
+        let replies = eval_all(&mut repl, ".data\ngreeting: \"hello\"\n.text");
+        assert_eq!(replies[1], "stack: []\ngreeting = &1\n&1 = \"hello\"");
+        assert_eq!(repl.eval(":heap"), "&1 = \"hello\"");
+        assert_eq!(repl.eval(":memory"), "greeting = &1");
+    }
+
+    #[test]
+    fn test_functions_and_macros_wait_until_complete() {
+        let mut repl = Repl::new();
+        let replies = eval_all(&mut repl, r#"
+            FUNC square 1
+            BEGIN
+            PARAM 0
+            PARAM 0
+            MUL
+            END"#.trim());
+        assert!(replies[..5].iter().all(String::is_empty));
+        assert_eq!(repl.prompt(), "> ");
+
+        repl.eval(".macro twice x");
+        assert_eq!(repl.prompt(), ". ");
+        repl.eval("PUSH %x");
+        repl.eval("CALL square");
+        assert_eq!(repl.eval(".endm"), "stack: []");
+
+        assert_eq!(repl.eval("twice 3"), "stack: [9]");
+        assert_eq!(repl.eval("twice 4"), "stack: [9, 16]");
+    }
+
+    #[test]
+    fn test_errors_reject_the_line() {
+        let mut repl = Repl::new();
+        repl.eval("PUSH 1");
+        assert_eq!(repl.eval("BOGUS"), "error: Unknown instruction: BOGUS");
+        assert_eq!(
+            repl.eval("JMP nowhere"),
+            "error: Label not found: nowhere\n  help: define it with `label:` at the start of a line"
+        );
+
+        // A runtime error still keeps the line, since it already ran
+        assert_eq!(repl.eval("POP"), "stack: []");
+        assert_eq!(repl.eval("POP"), "error: Stack underflow\nstack: []");
+        assert_eq!(repl.source(), "PUSH 1\nPOP\nPOP\n");
+    }
+
+    #[test]
+    fn test_runaway_input_is_stopped() {
+        let mut repl = Repl::new();
+        let reply = repl.eval("spin: JMP spin");
+        assert!(reply.starts_with(&format!("stopped after {} steps", STEP_LIMIT)), "{}", reply);
+        assert_eq!(repl.eval("PUSH 1"), "stack: [1]");
+    }
+
+    #[test]
+    fn test_meta_commands() {
+        let mut repl = Repl::new();
+        repl.eval("PUSH 1");
+        assert_eq!(repl.eval(":stack"), "stack: [1]");
+        assert_eq!(repl.eval(":reset"), "reset");
+        assert_eq!(repl.eval(":stack"), "stack: []");
+        assert_eq!(repl.eval(":memory"), "memory: (empty)");
+        assert_eq!(repl.eval(":heap"), "heap: (empty)");
+        assert_eq!(repl.eval(":load"), "usage: :load FILE");
+        assert_eq!(repl.eval(":frobnicate"), "unknown command :frobnicate; try :help");
+        assert!(!repl.is_finished());
+        repl.eval(":quit");
+        assert!(repl.is_finished());
+    }
+
+    #[test]
+    fn test_changed_include_rejects_the_line() {
+        let path = std::env::temp_dir().join(format!("repl_include_{}.asm", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "PUSH 1\nPUSH 2").unwrap();
+
+        let mut repl = Repl::new();
+        assert_eq!(repl.eval(&format!(".include \"{}\"", path)), "stack: [1, 2]");
+
+        std::fs::write(path, "PUSH 1").unwrap();
+        assert!(repl.eval("ADD").starts_with("error: earlier input no longer assembles the same way"));
+        assert_eq!(repl.vm().get_state().stack, vec![Value::Int(1), Value::Int(2)]);
+
+        std::fs::write(path, "PUSH 1\nPUSH 2").unwrap();
+        assert_eq!(repl.eval("ADD"), "stack: [3]");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_nan_does_not_block_later_lines() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.eval("PUSH nan"), "stack: [nan]");
+        assert_eq!(repl.eval("PUSH 1"), "stack: [nan, 1]");
+
+        // Unchanged NaN globals and heap entries are not repeated either
+        eval_all(&mut repl, "PUSH nan\nSTORE x\n.data\nodd: nan, 2.5\n.text");
+        assert_eq!(repl.eval("POP"), "stack: [nan]");
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("repl_test_{}.asm", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut repl = Repl::new();
+        eval_all(&mut repl, "PUSH 6\nPUSH 7\nMUL\nSTORE answer");
+        assert_eq!(repl.eval(&format!(":save {}", path)), format!("saved 4 line(s) to {}", path));
+
+        let mut fresh = Repl::new();
+        assert_eq!(fresh.eval(&format!(":load {}", path)), "stack: []\nanswer = 42");
+        assert_eq!(fresh.source(), repl.source());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,102 @@
+use super::VMTester;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::error::VMError;
+    use crate::core::instruction::Instruction;
+    use crate::core::value::Value;
+
+    #[test]
+    fn test_stack_manipulation() {
+        const PRELUDE: &str = "PUSH 1\nPUSH 2\nPUSH 3\n";
+        let cases: [(&str, &[i64]); 12] = [
+            ("OVER", &[1, 2, 3, 2]),
+            ("ROT", &[2, 3, 1]),
+            ("-ROT", &[3, 1, 2]),
+            ("NIP", &[1, 3]),
+            ("TUCK", &[1, 3, 2, 3]),
+            ("PICK 0", &[1, 2, 3, 3]),
+            ("PICK 2", &[1, 2, 3, 1]),
+            ("ROLL 1", &[1, 3, 2]),
+            ("ROLL 2", &[2, 3, 1]),
+            ("2DUP", &[1, 2, 3, 2, 3]),
+            ("2DROP", &[1]),
+            ("DEPTH", &[1, 2, 3, 3]),
+        ];
+
+        for (instruction, expected) in cases {
+            let source = format!("{}{}", PRELUDE, instruction);
+            let expected: Vec<Value> = expected.iter().copied().map(Value::Int).collect();
+            assert_eq!(VMTester::stack_after(&source), expected, "{} produced the wrong stack", instruction);
+        }
+    }
+
+    #[test]
+    fn test_stack_manipulation_underflow() {
+        let cases = [
+            ("PUSH 1", "OVER"),
+            ("PUSH 1\nPUSH 2", "ROT"),
+            ("PUSH 1\nPUSH 2", "-ROT"),
+            ("PUSH 1", "NIP"),
+            ("PUSH 1", "TUCK"),
+            ("PUSH 1\nPUSH 2", "PICK 2"),
+            ("PUSH 1\nPUSH 2", "ROLL 2"),
+            ("PUSH 1", "2DUP"),
+            ("PUSH 1", "2DROP"),
+        ];
+
+        for (setup, instruction) in cases {
+            let source = format!("{}\n{}", setup, instruction);
+            let mut tester = VMTester::new(&source, false)
+                .expect("Failed to create VM tester");
+
+            match tester.run() {
+                Err(VMError::StackUnderflow) => (),
+                other => panic!("Expected stack underflow from {}, got {:?}", instruction, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_pick_and_roll_with_maximum_depth() {
+        for instruction in [Instruction::Pick(usize::MAX), Instruction::Roll(usize::MAX)] {
+            assert_eq!(instruction.stack_effect(), None);
+
+            let program = vec![Instruction::Push(Value::Int(1)), instruction.clone(), Instruction::Halt];
+            let mut tester = VMTester::from_program(program, &[], false);
+            match tester.run() {
+                Err(VMError::StackUnderflow) => (),
+                other => panic!("Expected stack underflow from {:?}, got {:?}", instruction, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_stack_words_replace_temporaries() {
+        const SOURCE: &str = r#"
+        // Sum of squares of 3 and 4 without STORE/LOAD
+        PUSH 3
+        PUSH 4
+        OVER
+        DUP
+        MUL
+        OVER
+        DUP
+        MUL
+        ADD
+        NIP
+        NIP
+        PRINT
+        HALT
+        "#;
+
+        let mut tester = VMTester::new(SOURCE, false)
+            .expect("Failed to create VM tester");
+
+        tester.run().expect("Failed to execute program");
+        assert_eq!(tester.get_output(), "25");
+        assert!(tester.get_memory().is_empty());
+        assert!(tester.get_stack().is_empty(), "Stack should be empty after execution");
+    }
+}
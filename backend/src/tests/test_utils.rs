@@ -1,7 +1,10 @@
 use crate::core::vm::VM;
 use crate::core::assembler::Assembler;
-use crate::core::state::DebugOptions;
-use crate::core::error::VMError;
+use crate::core::instruction::{Instruction, Opcode, Operand, OperandKind};
+use crate::core::optimizer::optimize;
+use crate::core::state::{DebugOptions, ArithmeticMode, DataItem};
+use crate::core::error::{AssemblerError, VMError};
+use crate::core::value::Value;
 use std::collections::HashMap;
 
 pub struct VMTester {
@@ -16,8 +19,21 @@ impl VMTester {
         let mut assembler = Assembler::new();
         let program = assembler.assemble(source)
             .map_err(|e| format!("Assembly error: {}", e))?;
+        Ok(Self::from_program(program, assembler.data(), debug))
+    }
 
+    /// Like `new`, but runs the optimizer over the assembled program first
+    pub fn optimized(source: &str, debug: bool) -> Result<Self, String> {
+        let mut assembler = Assembler::new();
+        let program = assembler.assemble(source)
+            .map_err(|e| format!("Assembly error: {}", e))?;
+        let (program, _) = optimize(&program);
+        Ok(Self::from_program(program, assembler.data(), debug))
+    }
+
+    pub fn from_program(program: Vec<Instruction>, data: &[DataItem], debug: bool) -> Self {
         let mut vm = VM::new(program);
+        vm.load_data(data);
 
         if debug {
             vm.set_debug_options(DebugOptions {
@@ -28,12 +44,24 @@ impl VMTester {
             });
         }
 
-        Ok(VMTester {
+        VMTester {
             vm,
             step_count: 0,
             max_steps: 1000,
             all_output: Vec::new(),
-        })
+        }
+    }
+
+    /// Run `source` to completion and return what is left on the stack
+    pub fn stack_after(source: &str) -> Vec<Value> {
+        let mut tester = Self::new(source, false)
+            .expect("Failed to create VM tester");
+        tester.run().expect("Failed to execute program");
+        tester.get_stack().clone()
+    }
+
+    pub fn set_arithmetic_mode(&mut self, mode: ArithmeticMode) {
+        self.vm.set_arithmetic_mode(mode);
     }
 
     pub fn run(&mut self) -> Result<(), VMError> {
@@ -55,11 +83,66 @@ impl VMTester {
         self.all_output.join("")
     }
 
-    pub fn get_memory(&self) -> &HashMap<String, i64> {
+    pub fn get_memory(&self) -> &HashMap<String, Value> {
         self.vm.get_memory()
     }
 
-    pub fn get_stack(&self) -> &Vec<i64> {
+    pub fn get_stack(&self) -> &Vec<Value> {
         &self.vm.get_state().stack
     }
-}
\ No newline at end of file
+
+    pub fn vm_mut(&mut self) -> &mut VM {
+        &mut self.vm
+    }
+}
+
+/// Assemble a source that is expected to be valid
+pub fn assemble(source: &str) -> Vec<Instruction> {
+    Assembler::new().assemble(source).expect("source should assemble")
+}
+
+/// Every diagnostic from a source that is expected to fail to assemble
+pub fn diagnostics(source: &str) -> Vec<AssemblerError> {
+    diagnostics_with(Assembler::new(), source)
+}
+
+/// Like `diagnostics`, using a configured assembler
+pub fn diagnostics_with(mut assembler: Assembler, source: &str) -> Vec<AssemblerError> {
+    assembler.assemble(source)
+        .expect_err("source should fail to assemble")
+        .0
+}
+
+/// An operand of `kind`, with `variant` picking among edge cases
+pub fn sample_operand(kind: OperandKind, variant: usize) -> Operand {
+    match kind {
+        OperandKind::Value => Operand::Value(match variant % 8 {
+            0 => Value::Int(-42),
+            1 => Value::Int(i64::MIN),
+            2 => Value::Int(i64::MAX),
+            3 => Value::Float(2.5),
+            4 => Value::Float(1e20),
+            5 => Value::Float(-2.5e-300),
+            6 => Value::Bool(true),
+            _ => Value::Nil,
+        }),
+        OperandKind::Name => Operand::Name(format!("name_{}", variant % 3)),
+        OperandKind::Text => Operand::Text("say \"héllo\" \\ to C:\\dir\\".to_string()),
+        OperandKind::Address => Operand::Address(variant * 1000),
+        OperandKind::Count => Operand::Count(variant),
+    }
+}
+
+/// `variants` instances of every opcode, with varied operands
+pub fn every_instruction(variants: usize) -> Vec<Instruction> {
+    Opcode::ALL.iter().enumerate()
+        .flat_map(|(i, &opcode)| (0..variants).map(move |variant| (i + variant, opcode)))
+        .map(|(variant, opcode)| {
+            let operands: Vec<Operand> = opcode.info().operands.iter()
+                .map(|&kind| sample_operand(kind, variant))
+                .collect();
+            Instruction::from_operands(opcode, &operands)
+                .expect("sample operands match the opcode table")
+        })
+        .collect()
+}
@@ -0,0 +1,79 @@
+use super::VMTester;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::error::VMError;
+    use crate::core::value::Value;
+
+    #[test]
+    fn test_numbers_are_not_mistaken_for_heap_references() {
+        const SOURCE: &str = r#"
+        // The string gets heap id 1, but the integer 1 must still print as 1
+        NEWSTR "hello"
+        PRINT
+        PRINTSTR "\n"
+        PUSH 1
+        PRINT
+        PRINTSTR "\n"
+        HALT
+        "#;
+
+        let mut tester = VMTester::new(SOURCE, false)
+            .expect("Failed to create VM tester");
+
+        tester.run().expect("Failed to execute program");
+        assert_eq!(tester.get_output(), "hello\n1\n");
+    }
+
+    #[test]
+    fn test_value_kinds() {
+        const SOURCE: &str = r#"
+        PUSH 3
+        NEWARRAY
+        STORE arr
+        LOAD arr
+        PUSH 1
+        PUSH true
+        ARRAYSET
+        LOAD arr
+        PRINT
+        PRINTSTR "\n"
+        PUSH nil
+        PRINT
+        PRINTSTR "\n"
+        PUSH 2
+        PUSH 2
+        LT
+        HALT
+        "#;
+
+        let mut tester = VMTester::new(SOURCE, false)
+            .expect("Failed to create VM tester");
+
+        tester.run().expect("Failed to execute program");
+        assert_eq!(tester.get_output(), "[0, true, 0]\nnil\n");
+        assert_eq!(tester.get_stack(), &vec![Value::Bool(false)]);
+        assert_eq!(tester.get_memory().get("arr"), Some(&Value::Ref(1)));
+    }
+
+    #[test]
+    fn test_type_errors() {
+        let sources = [
+            "NEWSTR \"a\"\nPUSH 1\nADD",
+            "PUSH 5\nPUSH 0\nARRAYGET",
+            "PUSH 1\nNEWARRAY\nSTRLEN",
+            "PUSH nil\nPRINTCHAR",
+        ];
+
+        for source in sources {
+            let mut tester = VMTester::new(source, false)
+                .expect("Failed to create VM tester");
+
+            match tester.run() {
+                Err(VMError::TypeError(_, _)) => (),
+                other => panic!("Expected type error for {:?}, got {:?}", source, other),
+            }
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use super::VMTester;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::assembler::Assembler;
+    use crate::core::error::VerifyError;
+    use crate::core::instruction::Instruction;
+    use crate::core::optimizer::optimize;
+    use crate::core::value::Value;
+    use crate::core::verifier::verify;
+
+    fn verify_source(source: &str) -> Vec<VerifyError> {
+        let program = Assembler::new().assemble(source).expect("Failed to assemble");
+        verify(&program).errors
+    }
+
+    #[test]
+    fn test_valid_program_passes() {
+        const SOURCE: &str = r#"
+            PUSH 3
+            CALL twice
+            PRINT
+            HALT
+            FUNC twice 1
+            BEGIN
+            PARAM 0
+            PUSH 2
+            MUL
+            RET
+            END
+        "#;
+
+        assert_eq!(verify_source(SOURCE), vec![]);
+        assert!(verify(&[]).into_result().is_ok());
+    }
+
+    #[test]
+    fn test_jump_targets_must_be_in_range() {
+        let program = vec![
+            Instruction::Push(Value::Bool(true)),
+            Instruction::JumpIf(2),
+            Instruction::Jump(9),
+        ];
+
+        let report = verify(&program);
+        assert_eq!(report.errors, vec![
+            VerifyError::JumpOutOfRange { address: 2, target: 9, len: 3 },
+        ]);
+        assert_eq!(report.to_string(), "2: jump target 9 is outside the program (0..3)");
+    }
+
+    #[test]
+    fn test_unknown_and_duplicate_functions() {
+        const SOURCE: &str = r#"
+            CALL missing
+            FUNC f 0
+            BEGIN
+            END
+            FUNC f 0
+            BEGIN
+            END
+        "#;
+
+        assert_eq!(verify_source(SOURCE), vec![
+            VerifyError::UnknownFunction { address: 0, name: "missing".to_string() },
+            VerifyError::DuplicateFunction { address: 4, name: "f".to_string(), first: 1 },
+        ]);
+    }
+
+    #[test]
+    fn test_unbalanced_function_blocks() {
+        const SOURCE: &str = r#"
+            END
+            BEGIN
+            FUNC f 0
+            PUSH 1
+            FUNC g 0
+            BEGIN
+        "#;
+
+        assert_eq!(verify_source(SOURCE), vec![
+            VerifyError::UnmatchedEnd { address: 0 },
+            VerifyError::UnexpectedBegin { address: 1 },
+            VerifyError::MissingBegin { address: 2, name: "f".to_string() },
+            VerifyError::UnterminatedFunction { address: 2, name: "f".to_string() },
+            VerifyError::UnterminatedFunction { address: 4, name: "g".to_string() },
+        ]);
+    }
+
+    #[test]
+    fn test_jump_to_first_instruction() {
+        const SOURCE: &str = r#"
+            start: PUSH 1
+            DEPTH
+            PUSH 3
+            LT
+            JMPNZ start
+            DEPTH
+            PRINT
+        "#;
+
+        assert_eq!(verify_source(SOURCE), vec![]);
+
+        let mut tester = VMTester::new(SOURCE, false)
+            .expect("Failed to create VM tester");
+        tester.run().expect("Failed to execute program");
+        assert_eq!(tester.get_output(), "3");
+    }
+
+    #[test]
+    fn test_jumps_stay_in_their_function() {
+        // Passes the function's BEGIN, so RET finds no frame to return from
+        const INTO: &str = r#"
+            JMP inner
+            FUNC f 0
+            BEGIN
+            inner: PUSH 1
+            RET
+            END
+        "#;
+        const OUT_OF: &str = r#"
+            FUNC f 0
+            BEGIN
+            JMP outer
+            END
+            outer: HALT
+        "#;
+
+        assert_eq!(verify_source(INTO), vec![VerifyError::JumpAcrossFunction { address: 0, target: 3 }]);
+        assert_eq!(verify_source(OUT_OF), vec![VerifyError::JumpAcrossFunction { address: 2, target: 4 }]);
+        assert!(VMTester::new(INTO, false).unwrap().run().is_err());
+    }
+
+    #[test]
+    fn test_optimized_programs_still_verify() {
+        const SOURCE: &str = r#"
+            PUSH 4
+            CALL countdown
+            HALT
+            FUNC countdown 1
+            BEGIN
+            top: PARAM 0
+            PRINT
+            PUSH 0
+            JMPZ top
+            END
+        "#;
+
+        let program = Assembler::new().assemble(SOURCE).expect("Failed to assemble");
+        assert_eq!(verify(&program).errors, vec![]);
+        assert_eq!(verify(&optimize(&program).0).errors, vec![]);
+    }
+}
@@ -0,0 +1,78 @@
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// Fresh scratch directory for one test
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("vm_cli_{}_{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn vm(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_vm")).args(args).output().unwrap()
+}
+
+#[test]
+fn test_run_prints_output_and_reports_errors() {
+    let dir = scratch("run");
+    let ok = dir.join("ok.asm");
+    std::fs::write(&ok, ".include \"lib.asm\"\nPUSH TWO\nPUSH 3\nMUL\nPRINT\nHALT").unwrap();
+    std::fs::write(dir.join("lib.asm"), ".const TWO 2").unwrap();
+    let failing = dir.join("failing.asm");
+    std::fs::write(&failing, "PRINTSTR \"before\"\nPUSH 1\nPUSH 0\nDIV").unwrap();
+    let endless = dir.join("endless.asm");
+    std::fs::write(&endless, "loop: JMP loop").unwrap();
+
+    let output = vm(&["run", ok.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "6");
+
+    let output = vm(&["run", failing.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "before");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Division by zero (at instruction 3)"));
+
+    let output = vm(&["run", "--max-steps", "50", endless.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(4));
+
+    let output = vm(&["run", "--trace", "--trace-stack", ok.to_str().unwrap()]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "6");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("   2  MUL"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_asm_disasm_and_check() {
+    let dir = scratch("asm");
+    let source = dir.join("prog.asm");
+    std::fs::write(&source, "start: PUSH 1\nJMPNZ start\nHALT").unwrap();
+    let broken = dir.join("broken.asm");
+    std::fs::write(&broken, "PUSH 1\nPUHS 2").unwrap();
+
+    assert!(vm(&["asm", source.to_str().unwrap()]).status.success());
+    let bytecode = dir.join("prog.svmb");
+    assert!(std::fs::read(&bytecode).unwrap().starts_with(b"SVMB"));
+
+    let output = vm(&["disasm", bytecode.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "L0: PUSH 1\n    JMPNZ L0\n    HALT\n");
+
+    // Neither bytecode nor the listing can carry `.data`, so both refuse it
+    let with_data = dir.join("data.asm");
+    std::fs::write(&with_data, ".data\ngreeting: \"hi\"\n.text\nLOAD greeting\nPRINT\nHALT").unwrap();
+    for command in ["asm", "disasm"] {
+        let output = vm(&[command, with_data.to_str().unwrap()]);
+        assert_eq!(output.status.code(), Some(3));
+        assert!(String::from_utf8_lossy(&output.stderr).contains("cannot hold .data items"));
+    }
+
+    let output = vm(&["check", source.to_str().unwrap()]);
+    assert!(output.status.success());
+
+    let output = vm(&["check", broken.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(3));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("broken.asm:2:1: error: Unknown instruction: PUHS"));
+    assert!(stderr.contains("help: did you mean `PUSH`?"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
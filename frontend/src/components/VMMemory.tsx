@@ -1,7 +1,8 @@
 import React from 'react';
+import { formatValue, type VMValue } from '../types/vm';
 
 interface VMMemoryProps {
-    memory: Record<string, number>;
+    memory: Record<string, VMValue>;
 }
 
 export const VMMemory: React.FC<VMMemoryProps> = ({ memory }) => {
@@ -14,7 +15,7 @@ export const VMMemory: React.FC<VMMemoryProps> = ({ memory }) => {
                 ) : (
                     Object.entries(memory).map(([key, value]) => (
                         <div key={key} className="font-mono">
-                            {`${key}: ${value}`}
+                            {`${key}: ${formatValue(value)}`}
                         </div>
                     ))
                 )}
@@ -1,7 +1,8 @@
 import React from 'react';
+import { formatValue, type VMValue } from '../types/vm';
 
 interface VMStackProps {
-    stack: number[];
+    stack: VMValue[];
 }
 
 export const VMStack: React.FC<VMStackProps> = ({ stack }) => {
@@ -14,7 +15,7 @@ export const VMStack: React.FC<VMStackProps> = ({ stack }) => {
                 ) : (
                     stack.map((value, index) => (
                         <div key={index} className="font-mono">
-                            {`${stack.length - 1 - index}: ${value}`}
+                            {`${stack.length - 1 - index}: ${formatValue(value)}`}
                         </div>
                     ))
                 )}
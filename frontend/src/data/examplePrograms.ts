@@ -91,7 +91,7 @@ start_loop:
   STORE i     // Update counter
   
   PUSH 5
-  LessEqual   // Check if <= 5
+  LE          // Check if <= 5
   JMPNZ start_loop  // Continue if true
 
 HALT`,
@@ -101,20 +101,20 @@ HALT`,
 PUSH 10
 PUSH 20
 LT            // 10 < 20
-PRINT         // Output: 1 (true)
-PRINTSTR "\n"
+PRINT         // Output: true
+PRINTSTR "\\n"
 
 PUSH 30
 PUSH 30
 LE            // 30 <= 30
-PRINT         // Output: 1 (true)
-PRINTSTR "\n"
+PRINT         // Output: true
+PRINTSTR "\\n"
 
 PUSH 50
 PUSH 40
 GT            // 50 > 40
-PRINT         // Output: 1 (true)
-PRINTSTR "\n"
+PRINT         // Output: true
+PRINTSTR "\\n"
 HALT`,
 
     // Calculator
@@ -167,5 +167,33 @@ PUSH 76    // L
 PRINTCHAR
 PUSH 79    // O
 PRINTCHAR
+HALT`,
+
+    // Constants and Data
+    tables: `// Lookup Tables
+.const COUNT 5
+
+.data
+title: "First primes: "
+primes: 2, 3, 5, 7, 11
+.text
+
+LOAD title
+PRINT
+PUSH 0
+STORE i
+loop: LOAD primes
+LOAD i
+ARRAYGET
+PRINT         // Output: 2 3 5 7 11
+PRINTSTR " "
+LOAD i
+PUSH 1
+ADD
+DUP
+STORE i
+PUSH COUNT
+LT
+JMPNZ loop
 HALT`
 } as const;
\ No newline at end of file
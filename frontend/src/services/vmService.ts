@@ -1,4 +1,4 @@
-import type { VMState } from '../types/vm';
+import type { AssemblerDiagnostic, VMState } from '../types/vm';
 
 const API_BASE_URL = process.env.NEXT_PUBLIC_API_URL || 'http://localhost:3001/api';
 
@@ -15,7 +15,7 @@ export class VMService {
 
             if (!response.ok) {
                 const errorText = await response.text();
-                throw new Error(`Failed to load program: ${errorText}`);
+                throw new Error(`Failed to load program: ${this.formatLoadError(errorText)}`);
             }
 
             const data = await response.json();
@@ -108,12 +108,34 @@ export class VMService {
                 programCounter: data.program_counter || 0,
                 instructions,
                 output: data.output || [],
+                arithmeticMode: data.arithmetic_mode,
+                diagnostics: data.diagnostics,
             };
         } catch (error) {
             console.error('Error transforming VM state:', error);
             throw error;
         }
     }
+
+    // Render assembler diagnostics as one "line:column: message" entry per line
+    private formatLoadError(errorText: string): string {
+        try {
+            const body = JSON.parse(errorText);
+            if (!Array.isArray(body.diagnostics)) {
+                return errorText;
+            }
+            const lines = body.diagnostics.map((d: AssemblerDiagnostic) =>
+                (d.file ? `${d.file}:` : '') +
+                `${d.line}:${d.column}: ${d.severity}: ${d.message}` + (d.help ? ` (${d.help})` : '') +
+                (d.expansion ?? []).map(e =>
+                    `\n    in macro \`${e.name}\` invoked at ${e.file ? `${e.file}:` : 'line '}${e.line}` +
+                    (e.cycle === 1 ? `\n    … (repeated ${e.repeated} times)` :
+                     e.cycle ? `\n    … (last ${e.cycle} frames repeated ${e.repeated} times)` : '')).join(''));
+            return [body.error, ...lines].join('\n');
+        } catch {
+            return errorText;
+        }
+    }
 }
 
 export const vmService = new VMService();
\ No newline at end of file
@@ -47,10 +47,51 @@ export type Instruction = {
     value?: number | string;
 };
 
+export type VMValue =
+    | { type: 'int'; value: number }
+    | { type: 'float'; value: number }
+    | { type: 'bool'; value: boolean }
+    | { type: 'ref'; value: number }
+    | { type: 'nil' };
+
+export const formatValue = (value: VMValue): string => {
+    switch (value.type) {
+        case 'ref':
+            return `&${value.value}`;
+        case 'nil':
+            return 'nil';
+        case 'float':
+            return Number.isInteger(value.value) ? value.value.toFixed(1) : `${value.value}`;
+        default:
+            return `${value.value}`;
+    }
+};
+
 export type VMState = {
-    stack: number[];
-    memory: Record<string, number>;
+    stack: VMValue[];
+    memory: Record<string, VMValue>;
     programCounter: number;
     instructions: Instruction[];
     output: string[];
-};
\ No newline at end of file
+    arithmeticMode?: 'checked' | 'wrapping' | 'saturating';
+    diagnostics?: AssemblerDiagnostic[];
+};
+
+export interface AssemblerDiagnostic {
+    file?: string;
+    line: number;
+    column: number;
+    token: string;
+    severity: 'error' | 'warning';
+    message: string;
+    help?: string;
+    expansion?: MacroExpansion[];
+}
+
+export interface MacroExpansion {
+    name: string;
+    file?: string;
+    line: number;
+    repeated?: number;
+    cycle?: number;
+}